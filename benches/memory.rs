@@ -0,0 +1,130 @@
+//! Compares `SimpleHashmapMemory` and `PagedMemory` on VM runs.
+//!
+//! The contracts are the shapes of the large-calldata and memory-copy-heavy compiler tests:
+//! one reads its whole calldata word by word, the other copies the calldata into the heap and
+//! then copies the heap onto itself. Both are run with the runner, so the tracers it attaches
+//! are measured too.
+//!
+//! Run with `cargo bench --bench memory`.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use zk_evm::ethereum_types::{Address, U256};
+use zk_evm::zkevm_opcode_defs::UMAOpcode;
+use zkevm_tester::assembly::{assemble, code_hash, ret_ok, uma_inc};
+use zkevm_tester::compiler_tests::{
+    run_vm_multi_contracts_with_options, VmExecutionResult, VmLaunchOption, VmRunOptions,
+};
+use zkevm_tester::tester_memory::MemoryBackend;
+
+const ITERATIONS: usize = 10;
+const CYCLES_LIMIT: usize = 1 << 20;
+
+/// Reads the calldata word by word through the calldata pointer in `r1`.
+fn large_calldata(words: usize) -> Vec<u8> {
+    let mut program = vec![uma_inc(UMAOpcode::FatPointerRead, (1, 0, 2, 1)); words];
+    program.push(ret_ok());
+
+    assemble(&program)
+}
+
+/// Copies the calldata into the heap, then copies the heap onto itself right after.
+fn memory_copy(words: usize) -> Vec<u8> {
+    let mut program = vec![];
+    for _ in 0..words {
+        // r2 = calldata[r1++]; heap[r3++] = r2
+        program.push(uma_inc(UMAOpcode::FatPointerRead, (1, 0, 2, 1)));
+        program.push(uma_inc(UMAOpcode::HeapWrite, (3, 2, 3, 0)));
+    }
+    for _ in 0..words {
+        // r2 = heap[r4++]; heap[r3++] = r2
+        program.push(uma_inc(UMAOpcode::HeapRead, (4, 0, 2, 4)));
+        program.push(uma_inc(UMAOpcode::HeapWrite, (3, 2, 3, 0)));
+    }
+    program.push(ret_ok());
+
+    assemble(&program)
+}
+
+/// Runs `bytecode` with `calldata_words` words of calldata to the end. Returns the number of cycles.
+fn run(bytecode: &[u8], calldata_words: usize, memory_backend: MemoryBackend) -> usize {
+    let address = Address::from_low_u64_be(0x10000);
+    let calldata: Vec<u8> = (0..calldata_words)
+        .flat_map(|word| {
+            let mut buffer = [0u8; 32];
+            U256::from(word).to_big_endian(&mut buffer);
+            buffer
+        })
+        .collect();
+    // the default account and the EVM simulator are never called
+    let default_code = assemble(&[ret_ok()]);
+    let default_code_hash = code_hash(&default_code);
+
+    let snapshot = run_vm_multi_contracts_with_options(
+        String::new(),
+        HashMap::from([(address, bytecode.to_vec())]),
+        &calldata,
+        HashMap::new(),
+        HashMap::new(),
+        address,
+        None,
+        VmLaunchOption::Default,
+        CYCLES_LIMIT,
+        HashMap::from([(default_code_hash, default_code)]),
+        HashMap::new(),
+        default_code_hash,
+        default_code_hash,
+        VmRunOptions {
+            memory_backend,
+            register_contracts: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(
+        matches!(snapshot.execution_result, VmExecutionResult::Ok(_)),
+        "the benchmark contract has failed: {:?}",
+        snapshot.execution_result
+    );
+
+    snapshot.num_cycles_used
+}
+
+fn measure(name: &str, mut workload: impl FnMut() -> usize) {
+    let mut total = Duration::ZERO;
+    let mut cycles = 0;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        cycles = workload();
+        total += start.elapsed();
+    }
+    println!(
+        "{:<40} {:>12.3?} per iteration, {} cycles",
+        name,
+        total / ITERATIONS as u32,
+        cycles
+    );
+}
+
+fn main() {
+    for words in [1 << 10, 1 << 12] {
+        println!("{} words", words);
+
+        let code = large_calldata(words);
+        measure("large calldata / SimpleHashmapMemory", || {
+            run(&code, words, MemoryBackend::Hashmap)
+        });
+        measure("large calldata / PagedMemory", || {
+            run(&code, words, MemoryBackend::Paged)
+        });
+
+        let code = memory_copy(words);
+        measure("memory copy / SimpleHashmapMemory", || {
+            run(&code, words, MemoryBackend::Hashmap)
+        });
+        measure("memory copy / PagedMemory", || {
+            run(&code, words, MemoryBackend::Paged)
+        });
+    }
+}
@@ -0,0 +1,215 @@
+use crate::U256;
+use zk_evm::zkevm_opcode_defs::decoding::EncodingModeProduction;
+use zk_evm::zkevm_opcode_defs::{
+    AddOpcode, Condition, DecodedOpcode, FarCallOpcode, ImmMemHandlerFlags, NearCallOpcode, Opcode,
+    OpcodeVariant, Operand, RegOrImmFlags, RetOpcode, ShiftOpcode, UMAOpcode,
+    FAR_CALL_STATIC_FLAG_IDX, UMA_INCREMENT_FLAG_IDX,
+};
+
+/// Encodes the instruction with the registers `(src0, src1, dst0)`.
+pub fn instruction(
+    opcode: Opcode,
+    src0_operand_type: Operand,
+    dst0_operand_type: Operand,
+    registers: (u8, u8, u8),
+    imm_0: u16,
+) -> u64 {
+    DecodedOpcode::<8, EncodingModeProduction> {
+        variant: OpcodeVariant {
+            opcode,
+            src0_operand_type,
+            dst0_operand_type,
+            flags: [false; 2],
+        },
+        condition: Condition::Always,
+        src0_reg_idx: registers.0,
+        src1_reg_idx: registers.1,
+        dst0_reg_idx: registers.2,
+        dst1_reg_idx: 0,
+        imm_0,
+        imm_1: 0,
+    }
+    .serialize_as_integer()
+}
+
+/// `opcode` with the registers `(src0, src1, dst0, dst1)`, without incrementing the offset.
+pub fn uma(opcode: UMAOpcode, registers: (u8, u8, u8, u8)) -> u64 {
+    uma_with_flags(opcode, registers, [false; 2])
+}
+
+/// The same as `uma`, but also writes the offset incremented by 32 into `dst1`.
+pub fn uma_inc(opcode: UMAOpcode, registers: (u8, u8, u8, u8)) -> u64 {
+    let mut flags = [false; 2];
+    flags[UMA_INCREMENT_FLAG_IDX] = true;
+
+    uma_with_flags(opcode, registers, flags)
+}
+
+fn uma_with_flags(opcode: UMAOpcode, registers: (u8, u8, u8, u8), flags: [bool; 2]) -> u64 {
+    // the heap offsets can also be immediates
+    let src0_operand_type = match opcode {
+        UMAOpcode::FatPointerRead => Operand::RegOnly,
+        _ => Operand::RegOrImm(RegOrImmFlags::UseRegOnly),
+    };
+
+    DecodedOpcode::<8, EncodingModeProduction> {
+        variant: OpcodeVariant {
+            opcode: Opcode::UMA(opcode),
+            src0_operand_type,
+            dst0_operand_type: Operand::RegOnly,
+            flags,
+        },
+        condition: Condition::Always,
+        src0_reg_idx: registers.0,
+        src1_reg_idx: registers.1,
+        dst0_reg_idx: registers.2,
+        dst1_reg_idx: registers.3,
+        imm_0: 0,
+        imm_1: 0,
+    }
+    .serialize_as_integer()
+}
+
+/// `near_call ergs, target, exception_handler`, passing the ergs in the `ergs` register.
+pub fn near_call(ergs: u8, target: u16, exception_handler: u16) -> u64 {
+    DecodedOpcode::<8, EncodingModeProduction> {
+        variant: OpcodeVariant {
+            opcode: Opcode::NearCall(NearCallOpcode),
+            src0_operand_type: Operand::RegOnly,
+            dst0_operand_type: Operand::RegOnly,
+            flags: [false; 2],
+        },
+        condition: Condition::Always,
+        src0_reg_idx: ergs,
+        imm_0: target,
+        imm_1: exception_handler,
+        ..Default::default()
+    }
+    .serialize_as_integer()
+}
+
+/// `add imm, r0, dst`
+pub fn add_imm(imm: u16, dst: u8) -> u64 {
+    instruction(
+        Opcode::Add(AddOpcode::Add),
+        Operand::Full(ImmMemHandlerFlags::UseImm16Only),
+        Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+        (0, 0, dst),
+        imm,
+    )
+}
+
+/// `ret.ok r0`, returning nothing from a far call frame.
+pub fn ret_ok() -> u64 {
+    instruction(
+        Opcode::Ret(RetOpcode::Ok),
+        Operand::RegOnly,
+        Operand::RegOnly,
+        (0, 0, 0),
+        0,
+    )
+}
+
+/// `ret.panic r0`
+pub fn ret_panic() -> u64 {
+    instruction(
+        Opcode::Ret(RetOpcode::Panic),
+        Operand::RegOnly,
+        Operand::RegOnly,
+        (0, 0, 0),
+        0,
+    )
+}
+
+/// Returns the first `length` bytes of the heap, with `r1` and `r2` as the scratch registers.
+pub fn ret_heap(length: u16) -> [u64; 4] {
+    [
+        // r1: the heap slice, with the length in bits 96..128
+        add_imm(length, 1),
+        add_imm(96, 2),
+        instruction(
+            Opcode::Shift(ShiftOpcode::Shl),
+            Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+            Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+            (1, 2, 1),
+            0,
+        ),
+        instruction(
+            Opcode::Ret(RetOpcode::Ok),
+            Operand::RegOnly,
+            Operand::RegOnly,
+            (1, 0, 0),
+            0,
+        ),
+    ]
+}
+
+///
+/// Far calls `callee` with empty calldata, passing all the ergs and going to `exception_handler`
+/// if the callee fails. Uses `r1` and `r2`.
+///
+pub fn far_call(callee: u16, exception_handler: u16) -> [u64; 5] {
+    far_call_with_flags(callee, exception_handler, [false; 2])
+}
+
+/// The same as `far_call`, but in the static context.
+pub fn static_far_call(callee: u16, exception_handler: u16) -> [u64; 5] {
+    let mut flags = [false; 2];
+    flags[FAR_CALL_STATIC_FLAG_IDX] = true;
+
+    far_call_with_flags(callee, exception_handler, flags)
+}
+
+fn far_call_with_flags(callee: u16, exception_handler: u16, flags: [bool; 2]) -> [u64; 5] {
+    let far_call = DecodedOpcode::<8, EncodingModeProduction> {
+        variant: OpcodeVariant {
+            opcode: Opcode::FarCall(FarCallOpcode::Normal),
+            src0_operand_type: Operand::RegOnly,
+            dst0_operand_type: Operand::RegOnly,
+            flags,
+        },
+        condition: Condition::Always,
+        src0_reg_idx: 1,
+        src1_reg_idx: 2,
+        imm_0: exception_handler,
+        ..Default::default()
+    };
+
+    [
+        // r1: the far call ABI with the ergs passed in bits 192..224
+        add_imm(0xffff, 1),
+        add_imm(192, 2),
+        instruction(
+            Opcode::Shift(ShiftOpcode::Shl),
+            Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+            Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+            (1, 2, 1),
+            0,
+        ),
+        add_imm(callee, 2),
+        far_call.serialize_as_integer(),
+    ]
+}
+
+/// Packs the instructions into a bytecode with an odd number of words.
+pub fn assemble(program: &[u64]) -> Vec<u8> {
+    let mut words = program.len().div_ceil(4);
+    if words % 2 == 0 {
+        words += 1;
+    }
+
+    let mut bytecode = vec![0u8; words * 32];
+    for (pc, raw) in program.iter().enumerate() {
+        let offset = (pc / 4) * 32 + (pc % 4) * 8;
+        bytecode[offset..offset + 8].copy_from_slice(&raw.to_be_bytes());
+    }
+
+    bytecode
+}
+
+/// The versioned hash of the bytecode.
+pub fn code_hash(bytecode: &[u8]) -> U256 {
+    let hash = crate::bytecode::bytecode_hash(bytecode).unwrap();
+
+    U256::from_big_endian(hash.as_bytes())
+}
@@ -0,0 +1,180 @@
+use crate::{Address, H256};
+use std::collections::HashMap;
+use zk_evm::zkevm_opcode_defs::decoding::EncodingModeProduction;
+use zk_evm::zkevm_opcode_defs::{ContractCodeSha256Format, VersionedHashLen32};
+
+/// The length of the bytecode in words is stored in the versioned hash as `u16`.
+pub const MAX_BYTECODE_WORDS: usize = u16::MAX as usize;
+
+///
+/// Checks that the EraVM bytecode can be hashed and decommitted: its length must be a multiple
+/// of 32 bytes, and the word count must be odd and fit into the versioned hash.
+///
+pub fn validate_bytecode(bytecode: &[u8]) -> anyhow::Result<()> {
+    anyhow::ensure!(!bytecode.is_empty(), "Bytecode is empty");
+    anyhow::ensure!(
+        bytecode.len() % 32 == 0,
+        "Bytecode length {} is not a multiple of 32",
+        bytecode.len()
+    );
+
+    let words = bytecode.len() / 32;
+    anyhow::ensure!(words % 2 == 1, "Bytecode word count {} is not odd", words);
+    anyhow::ensure!(
+        words <= MAX_BYTECODE_WORDS,
+        "Bytecode word count {} exceeds the maximum of {}",
+        words,
+        MAX_BYTECODE_WORDS
+    );
+
+    Ok(())
+}
+
+/// Validates the bytecode of every contract, reporting all the invalid ones by address.
+pub fn validate_contracts(contracts: &HashMap<Address, Vec<u8>>) -> anyhow::Result<()> {
+    let mut errors: Vec<String> = contracts
+        .iter()
+        .filter_map(|(address, bytecode)| {
+            validate_bytecode(bytecode)
+                .err()
+                .map(|error| format!("{:?}: {}", address, error))
+        })
+        .collect();
+    if errors.is_empty() {
+        return Ok(());
+    }
+
+    errors.sort();
+    anyhow::bail!("Invalid contract bytecode:\n{}", errors.join("\n"))
+}
+
+/// Checks that the versioned hash has the EraVM bytecode version byte.
+pub fn validate_bytecode_hash_version(hash: H256) -> anyhow::Result<()> {
+    let version_byte = hash.as_bytes()[0];
+    anyhow::ensure!(
+        version_byte == ContractCodeSha256Format::VERSION_BYTE,
+        "Bytecode hash {:?} has version byte {}, expected {}",
+        hash,
+        version_byte,
+        ContractCodeSha256Format::VERSION_BYTE
+    );
+
+    Ok(())
+}
+
+/// Splits the validated bytecode into words.
+pub fn bytecode_to_words(bytecode: &[u8]) -> anyhow::Result<Vec<[u8; 32]>> {
+    validate_bytecode(bytecode)?;
+
+    Ok(bytecode
+        .chunks(32)
+        .map(|word| word.try_into().expect("length is a multiple of 32"))
+        .collect())
+}
+
+/// The versioned hash of the EraVM bytecode.
+pub fn bytecode_hash(bytecode: &[u8]) -> anyhow::Result<H256> {
+    let words = bytecode_to_words(bytecode)?;
+    words_hash(&words)
+}
+
+/// The versioned hash of the EraVM bytecode split into words.
+pub fn words_hash(words: &[[u8; 32]]) -> anyhow::Result<H256> {
+    let hash = zk_evm::utils::bytecode_to_code_hash_for_mode::<8, EncodingModeProduction>(words)
+        .map_err(|_| anyhow::anyhow!("Failed to hash the bytecode of {} words", words.len()))?;
+
+    Ok(H256::from(hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_rejected(bytecode: &[u8], message: &str) {
+        let error = validate_bytecode(bytecode).unwrap_err().to_string();
+        assert!(error.contains(message), "unexpected error: {}", error);
+        assert!(bytecode_hash(bytecode).is_err());
+    }
+
+    #[test]
+    fn malformed_bytecode_is_rejected() {
+        assert_rejected(&[], "is empty");
+        assert_rejected(&[0u8; 33], "not a multiple of 32");
+        assert_rejected(&[0u8; 31], "not a multiple of 32");
+        assert_rejected(&[0u8; 64], "not odd");
+        assert_rejected(
+            &vec![0u8; (MAX_BYTECODE_WORDS + 2) * 32],
+            "exceeds the maximum",
+        );
+    }
+
+    #[test]
+    fn valid_bytecode_is_hashed_with_the_eravm_version() {
+        let bytecode = vec![0u8; 3 * 32];
+        validate_bytecode(&bytecode).unwrap();
+        assert_eq!(bytecode_to_words(&bytecode).unwrap().len(), 3);
+
+        let hash = bytecode_hash(&bytecode).unwrap();
+        validate_bytecode_hash_version(hash).unwrap();
+        // the length in words is in the bytes 2..4
+        assert_eq!(&hash.as_bytes()[2..4], &[0, 3]);
+    }
+
+    #[test]
+    fn hash_with_another_version_is_rejected() {
+        let mut hash = bytecode_hash(&[0u8; 32]).unwrap();
+        hash.as_bytes_mut()[0] = 2;
+
+        let error = validate_bytecode_hash_version(hash)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("version byte 2"),
+            "unexpected error: {}",
+            error
+        );
+    }
+
+    #[test]
+    fn all_invalid_contracts_are_reported() {
+        let contracts = HashMap::from([
+            (Address::from_low_u64_be(1), vec![0u8; 32]),
+            (Address::from_low_u64_be(2), vec![]),
+            (Address::from_low_u64_be(3), vec![0u8; 64]),
+        ]);
+
+        let error = validate_contracts(&contracts).unwrap_err().to_string();
+        assert!(!error.contains(&format!("{:?}", Address::from_low_u64_be(1))));
+        assert!(error.contains(&format!(
+            "{:?}: Bytecode is empty",
+            Address::from_low_u64_be(2)
+        )));
+        assert!(error.contains(&format!(
+            "{:?}: Bytecode word count 2 is not odd",
+            Address::from_low_u64_be(3)
+        )));
+    }
+
+    #[test]
+    fn runner_reports_invalid_bytecode_instead_of_panicking() {
+        let (default_code_hash, _) = crate::test_utils::default_code();
+        let error = crate::compiler_tests::run_vm(
+            String::new(),
+            vec![0u8; 64],
+            &[],
+            HashMap::new(),
+            HashMap::new(),
+            None,
+            crate::compiler_tests::VmLaunchOption::Default,
+            1_000,
+            HashMap::new(),
+            HashMap::new(),
+            default_code_hash,
+            default_code_hash,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(error.contains("is not odd"), "unexpected error: {}", error);
+    }
+}
@@ -0,0 +1,339 @@
+use crate::hooks::PostCycleHook;
+use crate::tester_memory::ByteAddressableMemory;
+use crate::vm_session::TesterVmState;
+use crate::{Address, U256};
+use zk_evm::aux_structures::MemoryPage;
+use zk_evm::vm_state::{heap_page_from_base, PrimitiveValue};
+use zk_evm::zkevm_opcode_defs::decoding::EncodingModeProduction;
+use zk_evm::zkevm_opcode_defs::{
+    Condition, DecodedOpcode, FatPointer, Opcode, OpcodeVariant, Operand, RetOpcode,
+    CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER,
+};
+
+/// The page with the code that returns from the mocked frames. Never allocated by the VM.
+const MOCK_CODE_PAGE: u32 = u32::MAX - 1;
+const MOCK_RETURN_PC: u16 = 0;
+const MOCK_REVERT_PC: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CalldataMatcher {
+    Any,
+    Selector([u8; 4]),
+    Exact(Vec<u8>),
+}
+
+impl CalldataMatcher {
+    fn matches(&self, calldata: &[u8]) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Selector(selector) => calldata.starts_with(selector),
+            Self::Exact(expected) => calldata == expected.as_slice(),
+        }
+    }
+
+    fn specificity(&self) -> u8 {
+        match self {
+            Self::Any => 0,
+            Self::Selector(_) => 1,
+            Self::Exact(_) => 2,
+        }
+    }
+}
+
+///
+/// The far calls to `address` matching `calldata` return `returndata` without executing the code.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CallMock {
+    /// The code address of the callee, so that delegate calls to the address are mocked too.
+    pub address: Address,
+    pub calldata: CalldataMatcher,
+    pub returndata: Vec<u8>,
+    /// Whether to revert with `returndata` instead of returning it.
+    pub revert: bool,
+}
+
+impl CallMock {
+    pub fn new(address: Address, calldata: CalldataMatcher, returndata: Vec<u8>) -> Self {
+        Self {
+            address,
+            calldata,
+            returndata,
+            revert: false,
+        }
+    }
+
+    pub fn reverting(address: Address, calldata: CalldataMatcher, revert_data: Vec<u8>) -> Self {
+        Self {
+            address,
+            calldata,
+            returndata: revert_data,
+            revert: true,
+        }
+    }
+}
+
+///
+/// Replaces the execution of the mocked frames with an immediate return.
+///
+/// Right after a far call pushes a mocked frame, the returndata is written to the frame heap and
+/// the frame is switched to the code that returns it, so the caller observes a regular return,
+/// with the ergs of the frame refunded.
+///
+#[derive(Debug, Clone, Default)]
+pub struct CallMocker {
+    pub mocks: Vec<CallMock>,
+    /// The callstack depth after the previous cycle.
+    last_depth: usize,
+    code_is_written: bool,
+}
+
+impl CallMocker {
+    /// Creates the mocker for the VM currently at the callstack depth `depth`.
+    pub fn new(mocks: Vec<CallMock>, depth: usize) -> Self {
+        Self {
+            mocks,
+            last_depth: depth,
+            ..Default::default()
+        }
+    }
+
+    /// The most specific mock matching the call.
+    fn find(&self, address: Address, calldata: &[u8]) -> Option<&CallMock> {
+        self.mocks
+            .iter()
+            .filter(|mock| mock.address == address && mock.calldata.matches(calldata))
+            .max_by_key(|mock| mock.calldata.specificity())
+    }
+
+    /// `ret.ok r1` or `ret.revert r1`, without the label and with the ABI in `r1`.
+    fn ret_instruction(kind: RetOpcode) -> u64 {
+        DecodedOpcode::<8, EncodingModeProduction> {
+            variant: OpcodeVariant {
+                opcode: Opcode::Ret(kind),
+                src0_operand_type: Operand::RegOnly,
+                dst0_operand_type: Operand::RegOnly,
+                // neither `to_label` nor the other non-exclusive flags are set
+                flags: [false; 2],
+            },
+            condition: Condition::Always,
+            src0_reg_idx: 1,
+            src1_reg_idx: 0,
+            dst0_reg_idx: 0,
+            dst1_reg_idx: 0,
+            imm_0: 0,
+            imm_1: 0,
+        }
+        .serialize_as_integer()
+    }
+
+    /// The code word with the return at `MOCK_RETURN_PC` and the revert at `MOCK_REVERT_PC`.
+    fn code_word() -> U256 {
+        // the instruction with the lowest pc is the most significant one
+        let mut limbs = [0u64; 4];
+        limbs[3 - MOCK_RETURN_PC as usize] = Self::ret_instruction(RetOpcode::Ok);
+        limbs[3 - MOCK_REVERT_PC as usize] = Self::ret_instruction(RetOpcode::Revert);
+
+        U256(limbs)
+    }
+
+    fn write_code(vm: &mut TesterVmState) {
+        vm.memory.write_slot(
+            MOCK_CODE_PAGE,
+            0,
+            PrimitiveValue::from_value(Self::code_word()),
+        );
+    }
+}
+
+impl PostCycleHook for CallMocker {
+    fn after_cycle(&mut self, vm: &mut TesterVmState) -> anyhow::Result<()> {
+        let depth = vm.local_state.callstack.depth();
+        let is_new_frame = depth > self.last_depth;
+        self.last_depth = depth;
+
+        let current_frame = vm.local_state.callstack.get_current_stack();
+        if !is_new_frame || current_frame.is_local_frame || current_frame.pc != 0 {
+            return Ok(());
+        }
+        let code_address = current_frame.code_address;
+        let heap_page = heap_page_from_base(current_frame.base_memory_page).0;
+
+        let calldata_ptr =
+            vm.local_state.registers[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize];
+        let calldata = if calldata_ptr.is_pointer {
+            vm.memory
+                .read_fat_pointer(FatPointer::from_u256(calldata_ptr.value))
+        } else {
+            vec![]
+        };
+        let Some(mock) = self.find(code_address, &calldata) else {
+            return Ok(());
+        };
+        let returndata = mock.returndata.clone();
+        let pc = if mock.revert {
+            MOCK_REVERT_PC
+        } else {
+            MOCK_RETURN_PC
+        };
+
+        if !self.code_is_written {
+            Self::write_code(vm);
+            self.code_is_written = true;
+        }
+
+        vm.memory.write_bytes(heap_page, 0, &returndata);
+
+        // r1: the heap slice to return, in the `RetABI` layout with the `UseHeap` forwarding mode
+        let ret_abi = FatPointer {
+            offset: 0,
+            memory_page: 0,
+            start: 0,
+            length: returndata.len() as u32,
+        }
+        .to_u256();
+        vm.local_state.registers[0] = PrimitiveValue::from_value(ret_abi);
+
+        let current_frame = &mut vm.local_state.callstack.current;
+        current_frame.code_page = MemoryPage(MOCK_CODE_PAGE);
+        current_frame.pc = pc;
+
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn PostCycleHook> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::call_tree::CallOutcome;
+    use crate::compiler_tests::{VmExecutionResult, VmRunOptions, VmSnapshot};
+    use crate::disassembler::decode_instruction_from_word;
+    use crate::test_utils::{assemble, far_call, ret_ok, run, session};
+    use std::collections::HashMap;
+
+    const CALLEE: u64 = 0x1234;
+    const CALLER: u64 = 0x10000;
+
+    #[test]
+    fn mock_code_decodes_to_ret_from_r1() {
+        let word = CallMocker::code_word();
+        for (pc, kind) in [
+            (MOCK_RETURN_PC, RetOpcode::Ok),
+            (MOCK_REVERT_PC, RetOpcode::Revert),
+        ] {
+            let instruction = decode_instruction_from_word(word, pc);
+            assert!(!instruction.is_invalid(), "{}", instruction);
+            assert_eq!(instruction.decoded.variant.opcode, Opcode::Ret(kind));
+            assert_eq!(
+                instruction.decoded.variant.src0_operand_type,
+                Operand::RegOnly
+            );
+            assert_eq!(instruction.decoded.src0_reg_idx, 1);
+            assert_eq!(instruction.decoded.condition, Condition::Always);
+        }
+    }
+
+    ///
+    /// The contract that far calls `CALLEE` with empty calldata and returns nothing, whatever
+    /// the outcome of the call.
+    ///
+    fn caller_bytecode() -> Vec<u8> {
+        // the exception handler is at pc 6
+        let mut program = far_call(CALLEE as u16, 6).to_vec();
+        program.extend([ret_ok(), ret_ok(), ret_ok()]);
+
+        assemble(&program)
+    }
+
+    fn contracts() -> HashMap<Address, Vec<u8>> {
+        let bytecode = caller_bytecode();
+        HashMap::from([
+            (Address::from_low_u64_be(CALLER), bytecode.clone()),
+            (Address::from_low_u64_be(CALLEE), bytecode),
+        ])
+    }
+
+    fn run_with_mock(mock: CallMock) -> VmSnapshot {
+        run(
+            contracts(),
+            Address::from_low_u64_be(CALLER),
+            VmRunOptions {
+                call_mocks: vec![mock],
+                ..Default::default()
+            },
+        )
+    }
+
+    fn mocked_call(snapshot: &VmSnapshot) -> (Option<CallOutcome>, Vec<u8>) {
+        let calls: Vec<_> = snapshot.call_tree.far_calls().collect();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].code_address, Address::from_low_u64_be(CALLEE));
+
+        (calls[0].outcome, calls[0].returndata.clone())
+    }
+
+    #[test]
+    fn mocked_call_returns_the_configured_data() {
+        let returndata = (1..=40).collect::<Vec<u8>>();
+        let snapshot = run_with_mock(CallMock::new(
+            Address::from_low_u64_be(CALLEE),
+            CalldataMatcher::Any,
+            returndata.clone(),
+        ));
+
+        assert!(matches!(
+            snapshot.execution_result,
+            VmExecutionResult::Ok(_)
+        ));
+        assert_eq!(mocked_call(&snapshot), (Some(CallOutcome::Ok), returndata));
+    }
+
+    #[test]
+    fn reverting_mock_reverts_with_the_configured_data() {
+        let revert_data = vec![0xde, 0xad, 0xbe, 0xef];
+        let snapshot = run_with_mock(CallMock::reverting(
+            Address::from_low_u64_be(CALLEE),
+            CalldataMatcher::Any,
+            revert_data.clone(),
+        ));
+
+        assert!(matches!(
+            snapshot.execution_result,
+            VmExecutionResult::Ok(_)
+        ));
+        assert_eq!(
+            mocked_call(&snapshot),
+            (Some(CallOutcome::Revert), revert_data)
+        );
+    }
+
+    #[test]
+    fn far_call_in_the_first_resumed_cycle_is_mocked() {
+        let returndata = vec![0x42; 8];
+        let mut session = session(
+            contracts(),
+            Address::from_low_u64_be(CALLER),
+            VmRunOptions::default(),
+        );
+        // stop right before the far call
+        session.run(4).unwrap();
+        assert_eq!(session.vm.local_state.callstack.get_current_stack().pc, 4);
+
+        let mut resumed = session.checkpoint().resume(VmRunOptions {
+            call_mocks: vec![CallMock::new(
+                Address::from_low_u64_be(CALLEE),
+                CalldataMatcher::Any,
+                returndata.clone(),
+            )],
+            ..Default::default()
+        });
+        assert!(resumed.run(1_000).unwrap());
+        let snapshot = resumed.finish().unwrap();
+
+        assert_eq!(mocked_call(&snapshot), (Some(CallOutcome::Ok), returndata));
+    }
+}
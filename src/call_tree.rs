@@ -0,0 +1,437 @@
+use crate::debug_info::{DebugInfo, ExecutionLocation};
+use crate::evm_deploy::read_pointer;
+use crate::tester_memory::TesterMemory;
+use crate::Address;
+use std::collections::HashMap;
+use zk_evm::vm_state::VmLocalState;
+use zk_evm::zkevm_opcode_defs::decoding::EncodingModeProduction;
+use zk_evm::zkevm_opcode_defs::definitions::ret::RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER;
+use zk_evm::zkevm_opcode_defs::{
+    FarCallABI, FarCallOpcode, FatPointer, Opcode, RetOpcode,
+    CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CallType {
+    Near,
+    Normal,
+    Delegate,
+    Mimic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CallOutcome {
+    Ok,
+    Revert,
+    Panic,
+}
+
+#[derive(Debug, Clone)]
+pub struct CallEntry {
+    pub call_type: CallType,
+    /// Set for the far calls with the system call flag in the ABI.
+    pub is_system_call: bool,
+    pub caller: Address,
+    pub callee: Address,
+    pub code_address: Address,
+    pub value: u128,
+    pub ergs_passed: u32,
+    /// The ergs left in the frame when it returned.
+    pub ergs_returned: u32,
+    pub calldata: Vec<u8>,
+    pub returndata: Vec<u8>,
+    /// `None` if the frame has not returned by the end of the run.
+    pub outcome: Option<CallOutcome>,
+    /// The place of the call instruction in the caller's code.
+    pub call_site: ExecutionLocation,
+    /// The base page of the callee frame's memory.
+    pub base_memory_page: u32,
+    pub depth: usize,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+///
+/// The calls made during a run. The entries are stored in the order the calls were made,
+/// the entry frame of the run itself is not included.
+///
+#[derive(Debug, Clone, Default)]
+pub struct CallTree {
+    pub calls: Vec<CallEntry>,
+}
+
+impl CallTree {
+    /// The calls made directly by the entry frame.
+    pub fn roots(&self) -> impl Iterator<Item = (usize, &CallEntry)> {
+        self.calls
+            .iter()
+            .enumerate()
+            .filter(|(_, call)| call.parent.is_none())
+    }
+
+    pub fn far_calls(&self) -> impl Iterator<Item = &CallEntry> {
+        self.calls
+            .iter()
+            .filter(|call| call.call_type != CallType::Near)
+    }
+
+    pub fn annotate(&mut self, debug_info: &HashMap<Address, DebugInfo>) {
+        for call in self.calls.iter_mut() {
+            call.call_site.annotate(debug_info);
+        }
+    }
+
+    fn format_call(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        index: usize,
+        indent: usize,
+    ) -> std::fmt::Result {
+        let call = &self.calls[index];
+        write!(f, "{:indent$}{:?}", "", call.call_type, indent = indent * 2)?;
+        if call.is_system_call {
+            write!(f, " (system)")?;
+        }
+        write!(f, " {:?} -> {:?}", call.caller, call.callee)?;
+        if call.code_address != call.callee {
+            write!(f, " (code of {:?})", call.code_address)?;
+        }
+        if call.value != 0 {
+            write!(f, ", value {}", call.value)?;
+        }
+        write!(f, ", ergs {} -> {}", call.ergs_passed, call.ergs_returned)?;
+        if call.call_type != CallType::Near {
+            write!(
+                f,
+                ", calldata 0x{}, returndata 0x{}",
+                hex::encode(&call.calldata),
+                hex::encode(&call.returndata)
+            )?;
+        }
+        match call.outcome {
+            Some(outcome) => write!(f, ": {:?}", outcome)?,
+            None => write!(f, ": did not return")?,
+        }
+        writeln!(f, " [at {}]", call.call_site)?;
+
+        for child in call.children.iter() {
+            self.format_call(f, *child, indent + 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for CallTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, _) in self.roots() {
+            self.format_call(f, index, 0)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PendingCall {
+    call_type: CallType,
+    is_system_call: bool,
+    caller: Address,
+    call_site: ExecutionLocation,
+    depth: usize,
+}
+
+///
+/// Builds the call tree from the instructions the VM executes.
+///
+#[derive(Debug, Default, Clone)]
+pub struct CallTreeTracer {
+    pub call_tree: CallTree,
+    pub trace_near_calls: bool,
+    /// The calls that have not returned yet, innermost last.
+    open_calls: Vec<usize>,
+    pending_call: Option<PendingCall>,
+    pending_return: Option<(CallOutcome, u32, bool)>,
+}
+
+impl CallTreeTracer {
+    pub fn new(trace_near_calls: bool) -> Self {
+        Self {
+            trace_near_calls,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn before_execution(
+        &mut self,
+        state: &VmLocalState<8, EncodingModeProduction>,
+        opcode: &Opcode,
+        src0_value: zk_evm::vm_state::PrimitiveValue,
+        call_site: &ExecutionLocation,
+    ) {
+        let current_frame = state.callstack.get_current_stack();
+        let (call_type, is_system_call) = match opcode {
+            Opcode::FarCall(kind) => {
+                let call_type = match kind {
+                    FarCallOpcode::Normal => CallType::Normal,
+                    FarCallOpcode::Delegate => CallType::Delegate,
+                    FarCallOpcode::Mimic => CallType::Mimic,
+                };
+                let abi = FarCallABI::from_u256(src0_value.value);
+                (call_type, abi.to_system)
+            }
+            Opcode::NearCall(_) if self.trace_near_calls => (CallType::Near, false),
+            Opcode::Ret(kind) => {
+                if current_frame.is_local_frame && !self.trace_near_calls {
+                    return;
+                }
+                let outcome = match kind {
+                    RetOpcode::Ok => CallOutcome::Ok,
+                    RetOpcode::Revert => CallOutcome::Revert,
+                    RetOpcode::Panic => CallOutcome::Panic,
+                };
+                self.pending_return = Some((
+                    outcome,
+                    current_frame.ergs_remaining,
+                    current_frame.is_local_frame,
+                ));
+                return;
+            }
+            _ => return,
+        };
+
+        self.pending_call = Some(PendingCall {
+            call_type,
+            is_system_call,
+            caller: current_frame.this_address,
+            call_site: call_site.clone(),
+            depth: state.callstack.depth(),
+        });
+    }
+
+    pub(crate) fn after_execution(
+        &mut self,
+        state: &VmLocalState<8, EncodingModeProduction>,
+        memory: &TesterMemory,
+    ) {
+        if let Some(pending) = self.pending_call.take() {
+            if state.callstack.depth() <= pending.depth {
+                // the frame was not pushed
+                return;
+            }
+
+            let new_frame = state.callstack.get_current_stack();
+            let calldata = if pending.call_type == CallType::Near {
+                vec![]
+            } else {
+                let calldata_ptr =
+                    state.registers[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize];
+                if calldata_ptr.is_pointer {
+                    read_pointer(memory, FatPointer::from_u256(calldata_ptr.value))
+                } else {
+                    vec![]
+                }
+            };
+
+            let index = self.call_tree.calls.len();
+            let parent = self.open_calls.last().copied();
+            self.call_tree.calls.push(CallEntry {
+                call_type: pending.call_type,
+                is_system_call: pending.is_system_call,
+                caller: pending.caller,
+                callee: new_frame.this_address,
+                code_address: new_frame.code_address,
+                value: new_frame.context_u128_value,
+                ergs_passed: new_frame.ergs_remaining,
+                ergs_returned: 0,
+                calldata,
+                returndata: vec![],
+                outcome: None,
+                call_site: pending.call_site,
+                base_memory_page: new_frame.base_memory_page.0,
+                depth: pending.depth + 1,
+                parent,
+                children: vec![],
+            });
+            if let Some(parent) = parent {
+                self.call_tree.calls[parent].children.push(index);
+            }
+            self.open_calls.push(index);
+        }
+
+        if let Some((outcome, ergs_remaining, is_near)) = self.pending_return.take() {
+            // the return of the entry frame
+            let Some(index) = self.open_calls.pop() else {
+                return;
+            };
+
+            let returndata = if is_near {
+                vec![]
+            } else {
+                let returndata_ptr =
+                    state.registers[RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER as usize];
+                if returndata_ptr.is_pointer {
+                    read_pointer(memory, FatPointer::from_u256(returndata_ptr.value))
+                } else {
+                    vec![]
+                }
+            };
+
+            let call = &mut self.call_tree.calls[index];
+            call.outcome = Some(outcome);
+            call.ergs_returned = ergs_remaining;
+            call.returndata = returndata;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler_tests::{VmExecutionResult, VmRunOptions};
+    use crate::test_utils::{add_imm, assemble, far_call, near_call, ret_ok, ret_panic, run};
+
+    const CALLER: u64 = 0x10000;
+    const MIDDLE: u64 = 0x2222;
+    const LEAF: u64 = 0x3333;
+    const PANICKER: u64 = 0x4444;
+
+    ///
+    /// The caller far calls the middle contract, which far calls the leaf and the panicking
+    /// contract, then near calls itself.
+    ///
+    fn contracts() -> HashMap<Address, Vec<u8>> {
+        let mut caller = far_call(MIDDLE as u16, 6).to_vec();
+        caller.extend([ret_ok(), ret_panic()]);
+
+        // the failure of the panicking contract is handled at pc 10
+        let mut middle = far_call(LEAF as u16, 10).to_vec();
+        middle.extend(far_call(PANICKER as u16, 10));
+        middle.extend([near_call(0, 12, 13), ret_ok(), ret_ok(), ret_panic()]);
+
+        HashMap::from([
+            (Address::from_low_u64_be(CALLER), assemble(&caller)),
+            (Address::from_low_u64_be(MIDDLE), assemble(&middle)),
+            (
+                Address::from_low_u64_be(LEAF),
+                assemble(&[add_imm(1, 3), ret_ok()]),
+            ),
+            (Address::from_low_u64_be(PANICKER), assemble(&[ret_panic()])),
+        ])
+    }
+
+    #[test]
+    fn nested_calls_form_a_tree() {
+        let snapshot = run(
+            contracts(),
+            Address::from_low_u64_be(CALLER),
+            VmRunOptions {
+                trace_near_calls: true,
+                ..Default::default()
+            },
+        );
+        assert!(matches!(
+            snapshot.execution_result,
+            VmExecutionResult::Ok(_)
+        ));
+        let tree = &snapshot.call_tree;
+
+        let summary: Vec<_> = tree
+            .calls
+            .iter()
+            .map(|call| {
+                (
+                    call.call_type,
+                    call.caller.to_low_u64_be(),
+                    call.callee.to_low_u64_be(),
+                    call.outcome,
+                    call.depth,
+                    call.parent,
+                    call.children.clone(),
+                    call.call_site.pc,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    CallType::Normal,
+                    CALLER,
+                    MIDDLE,
+                    Some(CallOutcome::Ok),
+                    2,
+                    None,
+                    vec![1, 2, 3],
+                    4
+                ),
+                (
+                    CallType::Normal,
+                    MIDDLE,
+                    LEAF,
+                    Some(CallOutcome::Ok),
+                    3,
+                    Some(0),
+                    vec![],
+                    4
+                ),
+                (
+                    CallType::Normal,
+                    MIDDLE,
+                    PANICKER,
+                    Some(CallOutcome::Panic),
+                    3,
+                    Some(0),
+                    vec![],
+                    9
+                ),
+                (
+                    CallType::Near,
+                    MIDDLE,
+                    MIDDLE,
+                    Some(CallOutcome::Ok),
+                    3,
+                    Some(0),
+                    vec![],
+                    10
+                ),
+            ]
+        );
+        assert_eq!(
+            tree.roots().map(|(index, _)| index).collect::<Vec<_>>(),
+            [0]
+        );
+        assert_eq!(tree.far_calls().count(), 3);
+        for call in tree.calls.iter() {
+            assert!(call.ergs_returned <= call.ergs_passed);
+        }
+
+        let formatted = tree.to_string();
+        let lines: Vec<_> = formatted.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("Normal "));
+        assert!(lines[1].starts_with("  Normal "));
+        assert!(lines[2].ends_with(&format!(
+            ": Panic [at {:?} pc 0x0009]",
+            Address::from_low_u64_be(MIDDLE)
+        )));
+        assert!(lines[3].starts_with("  Near "));
+    }
+
+    #[test]
+    fn near_calls_are_only_traced_on_request() {
+        let snapshot = run(
+            contracts(),
+            Address::from_low_u64_be(CALLER),
+            VmRunOptions::default(),
+        );
+
+        let callees: Vec<_> = snapshot
+            .call_tree
+            .calls
+            .iter()
+            .map(|call| call.callee.to_low_u64_be())
+            .collect();
+        assert_eq!(callees, [MIDDLE, LEAF, PANICKER]);
+    }
+}
@@ -1,28 +1,34 @@
+use crate::bytecode::{bytecode_to_words, validate_bytecode_hash_version, validate_contracts};
+use crate::call_mocks::CallMock;
+use crate::call_tree::CallTree;
+use crate::debug_info::{DebugInfo, ExecutionLocation};
+use crate::decommitter::TesterDecommitter;
 use crate::default_environment::*;
+use crate::disassembler::{decode_instruction_from_word, Instruction, INSTRUCTIONS_PER_WORD};
 use crate::events::SolidityLikeEvent;
-use crate::hashmap_based_memory::SimpleHashmapMemory;
-use crate::simple_witness_tracer::MemoryLogWitnessTracer;
-use crate::utils::IntoFixedLengthByteIterator;
+use crate::execution_tracer::ExecutionTracer;
+use crate::full_witness_tracer::FullWitnessTracer;
+use crate::hooks::PostCycleHook;
+use crate::panic_info::{PanicInfo, PanicReason};
+use crate::precompiles::TesterPrecompilesProcessor;
+use crate::revert_reason::RevertReason;
+use crate::simple_witness_tracer::{MemoryLogWitnessTracer, MemoryQueryFilter};
+use crate::system_contracts::{AccountNonces, BlockContext};
+use crate::tester_memory::{MemoryBackend, TesterMemory};
+use crate::vm_session::VmSession;
+use crate::watchpoints::{Watchpoint, WatchpointAction, WatchpointHit};
 use crate::{Address, H256, U256};
 use std::collections::HashMap;
 use std::hash::Hash;
+use zk_evm::aux_structures::*;
 use zk_evm::block_properties::*;
-use zk_evm::reference_impls::decommitter::SimpleDecommitter;
 use zk_evm::reference_impls::event_sink::{EventMessage, InMemoryEventSink};
 use zk_evm::testing::storage::InMemoryStorage;
 use zk_evm::vm_state::*;
-use zk_evm::zk_evm_abstractions::precompiles::DefaultPrecompilesProcessor;
 use zk_evm::zkevm_opcode_defs::decoding::AllowedPcOrImm;
 use zk_evm::zkevm_opcode_defs::decoding::VmEncodingMode;
 use zk_evm::zkevm_opcode_defs::definitions::ret::RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER;
-use zk_evm::zkevm_opcode_defs::system_params::{
-    DEPLOYER_SYSTEM_CONTRACT_ADDRESS, DEPLOYER_SYSTEM_CONTRACT_ADDRESS_LOW,
-    KNOWN_CODE_FACTORY_SYSTEM_CONTRACT_ADDRESS,
-};
-use zk_evm::zkevm_opcode_defs::{
-    BlobSha256Format, ContractCodeSha256Format, FatPointer, VersionedHashLen32,
-};
-use zk_evm::{aux_structures::*, GenericNoopTracer};
+use zk_evm::zkevm_opcode_defs::FatPointer;
 
 use sha2::{Digest, Sha256};
 
@@ -41,12 +47,54 @@ pub enum VmLaunchOption {
     ManualCallABI(FullABIParams),
 }
 
-#[derive(Debug)]
+#[derive(Clone)]
 pub enum VmExecutionResult {
     Ok(Vec<u8>),
     Revert(Vec<u8>),
-    Panic,
-    MostLikelyDidNotFinish(Address, u64),
+    Panic(PanicInfo),
+    /// The address and the pc execution stopped at, with the instruction at that pc.
+    MostLikelyDidNotFinish(Address, u64, Instruction),
+}
+
+impl VmExecutionResult {
+    /// Decodes the revert data as `Error(string)` or `Panic(uint256)`.
+    pub fn revert_reason(&self) -> Option<RevertReason> {
+        self.revert_reason_with_abi(None)
+    }
+
+    /// Decodes the revert data, additionally looking up custom errors in `contract`.
+    pub fn revert_reason_with_abi(
+        &self,
+        contract: Option<&ethabi::Contract>,
+    ) -> Option<RevertReason> {
+        match self {
+            Self::Revert(data) => Some(RevertReason::decode_with_abi(data, contract)),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for VmExecutionResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ok(data) => f.debug_tuple("Ok").field(data).finish(),
+            Self::Revert(data) => match RevertReason::decode(data) {
+                reason @ (RevertReason::Error(_) | RevertReason::Panic(_)) => f
+                    .debug_tuple("Revert")
+                    .field(data)
+                    .field(&format_args!("{}", reason))
+                    .finish(),
+                _ => f.debug_tuple("Revert").field(data).finish(),
+            },
+            Self::Panic(info) => f.debug_tuple("Panic").field(info).finish(),
+            Self::MostLikelyDidNotFinish(address, pc, instruction) => f
+                .debug_tuple("MostLikelyDidNotFinish")
+                .field(address)
+                .field(pc)
+                .field(&format_args!("{}", instruction))
+                .finish(),
+        }
+    }
 }
 
 #[derive(Debug, Default)]
@@ -73,6 +121,45 @@ impl VmExecutionContext {
     }
 }
 
+///
+/// The optional settings of a run.
+///
+#[derive(Debug, Default, Clone)]
+pub struct VmRunOptions {
+    /// The debug info of the contracts, keyed by the code address.
+    pub debug_info: HashMap<Address, DebugInfo>,
+    /// Whether to include the near calls into the call tree. Far calls are always included.
+    pub trace_near_calls: bool,
+    /// The memory implementation to run the VM with.
+    pub memory_backend: MemoryBackend,
+    /// Whether to track the pages of the returned frames and fail the run on reads from them.
+    pub track_page_lifecycle: bool,
+    /// Whether to also remove the released pages from the memory. Implies `track_page_lifecycle`.
+    pub free_released_pages: bool,
+    /// If set, the memory queries matching the filter are collected into the snapshot.
+    pub memory_queries: Option<MemoryQueryFilter>,
+    /// Whether to record all the witness queries into the snapshot.
+    pub record_full_witness: bool,
+    /// The storage slots and memory words to watch the writes to.
+    pub watchpoints: HashMap<Watchpoint, WatchpointAction>,
+    /// If set, the block is written into the SystemContext storage before the run.
+    pub block_context: Option<BlockContext>,
+    /// The base token balances written into the storage before the run.
+    pub balances: HashMap<Address, U256>,
+    /// The account nonces written into the NonceHolder storage before the run.
+    pub nonces: HashMap<Address, AccountNonces>,
+    ///
+    /// Whether to register the code hash of every contract in `contracts` in the
+    /// AccountCodeStorage and KnownCodesStorage. The addresses that already have a code hash in
+    /// the storage are left as is.
+    ///
+    pub register_contracts: bool,
+    /// The hooks invoked after every cycle, in order.
+    pub post_cycle_hooks: Vec<Box<dyn PostCycleHook>>,
+    /// The far calls that return the configured data without executing the callee code.
+    pub call_mocks: Vec<CallMock>,
+}
+
 #[derive(Debug)]
 pub struct MemoryArea {
     pub words: Vec<U256>,
@@ -83,54 +170,27 @@ impl MemoryArea {
         Self { words: vec![] }
     }
 
+    /// Returns the big-endian bytes in the byte `range`, which does not have to be word-aligned.
+    /// The bytes beyond the stored words are zeroes.
     pub fn dump_be_bytes(&self, range: std::ops::Range<usize>) -> Vec<u8> {
         if range.is_empty() {
             return vec![];
         }
 
         let mut result = Vec::with_capacity(range.len());
-
-        let starting_word = range.start % 32;
-        let start_bytes = range.start / 32;
-        if start_bytes != 0 {
+        let mut buffer = [0u8; 32];
+        let mut position = range.start;
+        while position < range.end {
+            let in_word = position % 32;
+            let to_take = std::cmp::min(32 - in_word, range.end - position);
             let el = self
                 .words
-                .get(starting_word)
+                .get(position / 32)
                 .copied()
                 .unwrap_or(U256::zero());
-            let mut buffer = [0u8; 32];
             el.to_big_endian(&mut buffer);
-            result.extend_from_slice(&buffer[(32 - start_bytes)..]);
-        }
-
-        let end_cap = range.end % 32;
-        let end_word = range.end / 32;
-
-        // now just iterate aligned
-        let range_start = if start_bytes == 0 {
-            starting_word
-        } else {
-            starting_word + 1
-        };
-
-        let range_end = if end_cap == 0 || end_word == 0 {
-            end_word
-        } else {
-            end_word - 1
-        };
-
-        for i in range_start..range_end {
-            let el = self.words.get(i).copied().unwrap_or(U256::zero());
-            let mut buffer = [0u8; 32];
-            el.to_big_endian(&mut buffer);
-            result.extend_from_slice(&buffer[..]);
-        }
-
-        if end_cap != 0 {
-            let el = self.words.get(end_word).copied().unwrap_or(U256::zero());
-            let mut buffer = [0u8; 32];
-            el.to_big_endian(&mut buffer);
-            result.extend_from_slice(&buffer[..end_cap]);
+            result.extend_from_slice(&buffer[in_word..(in_word + to_take)]);
+            position += to_take;
         }
 
         result
@@ -175,9 +235,7 @@ impl StorageKey {
 
         let result = hasher.finalize();
 
-        let key = U256::from_big_endian(result.as_slice());
-
-        key
+        U256::from_big_endian(&result[..])
     }
 
     fn format_as_hex(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -225,6 +283,30 @@ pub struct VmSnapshot {
     pub num_cycles_used: usize,
     pub num_ergs_used: u32,
     pub published_sha256_blobs: HashMap<U256, Vec<U256>>,
+    /// The place the result originates from: the final return, the origin of the revert or panic,
+    /// or the instruction execution stopped at.
+    pub result_location: Option<ExecutionLocation>,
+    pub call_tree: CallTree,
+    /// The memory queries collected if requested by `VmRunOptions::memory_queries`.
+    pub memory_queries: Vec<MemoryQuery>,
+    /// The witness queries recorded if requested by `VmRunOptions::record_full_witness`.
+    pub full_witness: Option<FullWitnessTracer>,
+    /// The writes to the locations watched with `VmRunOptions::watchpoints`.
+    pub watchpoint_hits: Vec<WatchpointHit>,
+    /// The final base token balances of the accounts that took part in the run.
+    pub balances: HashMap<Address, U256>,
+}
+
+impl VmSnapshot {
+    /// The final base token balance of any address, including the ones not in `balances`.
+    pub fn balance_of(&self, address: Address) -> U256 {
+        crate::system_contracts::read_balance(&self.storage, address)
+    }
+
+    /// The final nonces of the address.
+    pub fn nonces_of(&self, address: Address) -> AccountNonces {
+        crate::system_contracts::read_nonces(&self.storage, address)
+    }
 }
 
 #[derive(Debug)]
@@ -276,27 +358,30 @@ pub fn run_vm(
     )
 }
 
-pub struct ExtendedTestingTools<const B: bool> {
+pub struct ExtendedTestingTools {
     pub storage: InMemoryStorage,
-    pub memory: SimpleHashmapMemory,
+    pub memory: TesterMemory,
     pub event_sink: InMemoryEventSink,
-    pub precompiles_processor: DefaultPrecompilesProcessor<B>,
-    pub decommittment_processor: SimpleDecommitter<B>,
+    pub precompiles_processor: TesterPrecompilesProcessor,
+    pub decommittment_processor: TesterDecommitter,
     pub witness_tracer: MemoryLogWitnessTracer,
 }
 
-pub fn create_default_testing_tools() -> ExtendedTestingTools<false> {
+pub fn create_default_testing_tools() -> ExtendedTestingTools {
     let storage = InMemoryStorage::new();
-    let memory = SimpleHashmapMemory::default();
+    let memory = TesterMemory::default();
     let event_sink = InMemoryEventSink::new();
-    let precompiles_processor = DefaultPrecompilesProcessor::<false>;
-    let decommittment_processor = SimpleDecommitter::<false>::new();
+    let precompiles_processor = TesterPrecompilesProcessor::default();
+    let decommittment_processor = TesterDecommitter::new(false);
     let witness_tracer = MemoryLogWitnessTracer {
         is_dummy: false,
         queries: vec![],
+        pages: None,
+        full_witness: None,
+        watchpoints: None,
     };
 
-    ExtendedTestingTools::<false> {
+    ExtendedTestingTools {
         storage,
         memory,
         event_sink,
@@ -306,39 +391,35 @@ pub fn create_default_testing_tools() -> ExtendedTestingTools<false> {
     }
 }
 
-pub fn create_vm<const B: bool>(
-    mut tools: ExtendedTestingTools<B>,
+pub fn create_vm(
+    mut tools: ExtendedTestingTools,
     block_properties: BlockProperties,
     context: VmExecutionContext,
     contracts: &HashMap<Address, Vec<[u8; 32]>>,
     known_contracts: HashMap<U256, Vec<[u8; 32]>>,
-) -> (
+) -> anyhow::Result<(
     VmState<
         InMemoryStorage,
-        SimpleHashmapMemory,
+        TesterMemory,
         InMemoryEventSink,
-        DefaultPrecompilesProcessor<B>,
-        SimpleDecommitter<B>,
+        TesterPrecompilesProcessor,
+        TesterDecommitter,
         MemoryLogWitnessTracer,
         8,
         zk_evm::zkevm_opcode_defs::decoding::EncodingModeProduction,
     >,
     HashMap<U256, Vec<[u8; 32]>>,
-) {
+)> {
     use zk_evm::contract_bytecode_to_words;
-    use zk_evm::utils::bytecode_to_code_hash_for_mode;
     // fill the decommitter
 
     let mut factory_deps: HashMap<U256, Vec<U256>> = HashMap::new();
     let mut reverse_lookup_for_bytecode = HashMap::new();
 
-    for (_address, bytecode) in contracts.iter() {
-        let bytecode_hash = bytecode_to_code_hash_for_mode::<
-            8,
-            zk_evm::zkevm_opcode_defs::decoding::EncodingModeProduction,
-        >(bytecode)
-        .unwrap();
-        let bytecode_hash_as_u256 = U256::from_big_endian(bytecode_hash.as_slice());
+    for (address, bytecode) in contracts.iter() {
+        let bytecode_hash = crate::bytecode::words_hash(bytecode)
+            .map_err(|error| anyhow::anyhow!("Invalid bytecode of {:?}: {}", address, error))?;
+        let bytecode_hash_as_u256 = U256::from_big_endian(bytecode_hash.as_bytes());
 
         reverse_lookup_for_bytecode.insert(bytecode_hash_as_u256, bytecode.to_owned());
 
@@ -397,7 +478,7 @@ pub fn create_vm<const B: bool>(
     vm.local_state.tx_number_in_block = context.transaction_index as u16;
     // (50 gwei(l1 gas price) * 17(l1 gas per pubdata byte)) / 250000000 (l2 base fee)
 
-    (vm, reverse_lookup_for_bytecode)
+    Ok((vm, reverse_lookup_for_bytecode))
 }
 
 ///
@@ -419,26 +500,63 @@ pub fn run_vm_multi_contracts(
     default_aa_code_hash: U256,
     evm_simulator_code_hash: U256,
 ) -> anyhow::Result<VmSnapshot> {
+    run_vm_multi_contracts_with_options(
+        test_name,
+        contracts,
+        calldata,
+        storage,
+        storage_transient,
+        entry_address,
+        context,
+        vm_launch_option,
+        cycles_limit,
+        known_contracts,
+        known_sha256_blobs,
+        default_aa_code_hash,
+        evm_simulator_code_hash,
+        VmRunOptions::default(),
+    )
+}
+
+///
+/// Used for testing the compiler with multiple contracts and extra run options.
+///
+#[allow(clippy::too_many_arguments)]
+pub fn run_vm_multi_contracts_with_options(
+    test_name: String,
+    contracts: HashMap<Address, Vec<u8>>,
+    calldata: &[u8],
+    storage: HashMap<StorageKey, H256>,
+    storage_transient: HashMap<StorageKey, H256>,
+    entry_address: Address,
+    context: Option<VmExecutionContext>,
+    vm_launch_option: VmLaunchOption,
+    cycles_limit: usize,
+    known_contracts: HashMap<U256, Vec<u8>>,
+    known_sha256_blobs: HashMap<U256, Vec<U256>>,
+    default_aa_code_hash: U256,
+    evm_simulator_code_hash: U256,
+    options: VmRunOptions,
+) -> anyhow::Result<VmSnapshot> {
+    validate_contracts(&contracts)?;
     let contracts = contracts
         .into_iter()
-        .map(|(address, bytecode)| {
-            let bytecode = bytecode
-                .chunks(32)
-                .map(|word| word.try_into().unwrap())
-                .collect();
-            (address, bytecode)
-        })
-        .collect();
+        .map(|(address, bytecode)| Ok((address, bytecode_to_words(&bytecode)?)))
+        .collect::<anyhow::Result<_>>()?;
     let known_contracts = known_contracts
         .into_iter()
-        .map(|(address, bytecode)| {
-            let bytecode = bytecode
-                .chunks(32)
-                .map(|word| word.try_into().unwrap())
-                .collect();
-            (address, bytecode)
+        .map(|(hash, bytecode)| {
+            let mut hash_bytes = [0u8; 32];
+            hash.to_big_endian(&mut hash_bytes);
+            validate_bytecode_hash_version(H256::from(hash_bytes)).map_err(|error| {
+                anyhow::anyhow!("Invalid known bytecode 0x{:064x}: {}", hash, error)
+            })?;
+            let bytecode = bytecode_to_words(&bytecode).map_err(|error| {
+                anyhow::anyhow!("Invalid known bytecode 0x{:064x}: {}", hash, error)
+            })?;
+            Ok((hash, bytecode))
         })
-        .collect();
+        .collect::<anyhow::Result<_>>()?;
     run_vm_multi_contracts_inner(
         test_name,
         contracts,
@@ -453,6 +571,7 @@ pub fn run_vm_multi_contracts(
         known_sha256_blobs,
         default_aa_code_hash,
         evm_simulator_code_hash,
+        options,
     )
 }
 
@@ -474,275 +593,70 @@ fn run_vm_multi_contracts_inner(
     known_sha256_blobs: HashMap<U256, Vec<U256>>,
     default_aa_code_hash: U256,
     evm_simulator_code_hash: U256,
+    options: VmRunOptions,
 ) -> anyhow::Result<VmSnapshot> {
-    let (set_far_call_props, extra_props) = match &vm_launch_option {
-        VmLaunchOption::Default => (true, None),
-        VmLaunchOption::ManualCallABI(value) => (true, Some(value.clone())),
-    };
-
-    let mut tools = create_default_testing_tools();
-    let mut block_properties = create_default_block_properties();
-    block_properties.default_aa_code_hash = default_aa_code_hash;
-    // we can always pretend it to be empty account
-    block_properties.evm_simulator_code_hash = evm_simulator_code_hash;
-
-    let calldata_length = calldata.len();
-
-    // fill the calldata
-    let aligned_calldata = calldata_to_aligned_data(calldata);
-    let initial_bytecode = {
-        let hash = storage
-            .get(&StorageKey {
-                address: Address::from_low_u64_be(DEPLOYER_SYSTEM_CONTRACT_ADDRESS_LOW.into()),
-                key: U256::from_big_endian(entry_address.as_bytes()),
-            })
-            .ok_or_else(|| anyhow::anyhow!("Entry address code hash not found in the storage"))?;
-
-        // If it's an EVM contract, we should run the EVM simulator
-        if hash.as_bytes()[0] == BlobSha256Format::VERSION_BYTE {
-            known_contracts
-                .get(&evm_simulator_code_hash)
-                .cloned()
-                .ok_or_else(|| {
-                    anyhow::anyhow!("EVM simulator bytecode not found in the known contracts")
-                })?
-        } else {
-            contracts
-                .get(&entry_address)
-                .cloned()
-                .ok_or_else(|| anyhow::anyhow!("Initial bytecode not found"))?
-        }
-    };
-    let initial_bytecode_as_memory = zk_evm::contract_bytecode_to_words(&initial_bytecode);
-
-    tools.memory.populate(vec![
-        (CALLDATA_PAGE, aligned_calldata),
-        (ENTRY_POINT_PAGE, initial_bytecode_as_memory),
-    ]);
-
-    tools
-        .decommittment_processor
-        .populate(known_sha256_blobs.into_iter().collect());
-
-    // fill the storage. Only rollup shard for now
-    for (key, value) in storage.into_iter() {
-        let per_address_entry = tools.storage.inner[0].entry(key.address).or_default();
-        per_address_entry.insert(key.key, U256::from_big_endian(value.as_bytes()));
-    }
-
-    // fill the transient storage. Only rollup shard for now
-    for (key, value) in storage_transient.into_iter() {
-        let per_address_entry = tools.storage.inner_transient[0]
-            .entry(key.address)
-            .or_default();
-        per_address_entry.insert(key.key, U256::from_big_endian(value.as_bytes()));
-    }
-
-    // some context notion
-    let context = context.unwrap_or_else(|| VmExecutionContext {
-        this_address: entry_address,
-        ..Default::default()
-    });
-
-    // fill the rest
-    let (mut vm, reverse_lookup_for_bytecode) = create_vm::<false>(
-        tools,
-        block_properties,
+    let mut session = VmSession::new(
+        contracts,
+        calldata,
+        storage,
+        storage_transient,
+        entry_address,
         context,
-        &contracts,
+        vm_launch_option,
         known_contracts,
-    );
-
-    if set_far_call_props {
-        // we need to properly set calldata abi
-        vm.local_state.registers[0] =
-            crate::utils::form_initial_calldata_ptr(CALLDATA_PAGE, calldata_length as u32);
-
-        vm.local_state.registers[1] = PrimitiveValue::empty();
-        vm.local_state.registers[2] = PrimitiveValue::empty();
-        vm.local_state.registers[3] = PrimitiveValue::empty();
-
-        if let Some(extra_props) = extra_props {
-            let mut r2_value = U256::zero();
-            if extra_props.is_constructor {
-                r2_value += U256::from(1u64 << 0);
-            }
-            if extra_props.is_system_call {
-                r2_value += U256::from(1u64 << 1);
-            }
-
-            let r3_value = extra_props.r3_value.unwrap_or(U256::zero());
-            let r4_value = extra_props.r4_value.unwrap_or(U256::zero());
-            let r5_value = extra_props.r5_value.unwrap_or(U256::zero());
-
-            vm.local_state.registers[1] = PrimitiveValue::from_value(r2_value);
-            vm.local_state.registers[2] = PrimitiveValue::from_value(r3_value);
-            vm.local_state.registers[3] = PrimitiveValue::from_value(r4_value);
-            vm.local_state.registers[4] = PrimitiveValue::from_value(r5_value);
-        }
-    }
-
-    let mut result = None;
-
-    let mut cycles_used = 0;
-    vm.witness_tracer.is_dummy = true;
-    let mut tracer = GenericNoopTracer::new();
-    for _ in 0..cycles_limit {
-        vm.cycle(&mut tracer)?;
-        super::evm_deploy::record_deployed_evm_bytecode(&mut vm);
-        cycles_used += 1;
-
-        // early return
-        if let Some(end_result) = vm_may_have_ended(&vm) {
-            result = Some(end_result);
-            break;
-        }
-    }
-
-    let execution_result = if let Some(result) = result {
-        result
-    } else {
-        let current_address = vm.local_state.callstack.get_current_stack().this_address;
-        let pc = vm.local_state.callstack.get_current_stack().pc.as_u64();
-        VmExecutionResult::MostLikelyDidNotFinish(current_address, pc)
-    };
-
-    let execution_has_ended = vm.execution_has_ended();
-
-    let VmState {
-        local_state,
-        block_properties: _,
-        storage,
-        event_sink,
-        decommittment_processor,
-        ..
-    } = vm;
-
-    let mut result_storage = HashMap::new();
-    let mut deployed_contracts = HashMap::new();
-
-    let (_full_history, raw_events, l1_messages) = event_sink.flatten();
-    let events = crate::events::merge_events(raw_events.clone());
-
-    let storage = storage.inner;
-    let storage = storage.into_iter().next().unwrap();
-    let mut published_sha256_blobs = HashMap::new();
-
-    for (address, inner) in storage.into_iter() {
-        for (key, value) in inner.into_iter() {
-            let storage_key = StorageKey { address, key };
-            let mut buffer = [0u8; 32];
-            value.to_big_endian(&mut buffer);
-            let value_h256 = H256::from_slice(&buffer);
-            result_storage.insert(storage_key, value_h256);
-
-            if address == *DEPLOYER_SYSTEM_CONTRACT_ADDRESS {
-                let mut buffer = [0u8; 32];
-                key.to_big_endian(&mut buffer);
-                let deployed_address = Address::from_slice(&buffer[12..]);
-                if let Some(bytecode) = reverse_lookup_for_bytecode.get(&value) {
-                    deployed_contracts.insert(
-                        deployed_address,
-                        bytecode.iter().copied().flatten().collect(),
-                    );
-                }
-            }
-
-            let mut key_buffer = [0u8; 32];
-            key.to_big_endian(&mut key_buffer);
-
-            // This is an EVM blob hash that has been set as known.
-            if address == *KNOWN_CODE_FACTORY_SYSTEM_CONTRACT_ADDRESS
-                && value == 1.into()
-                && key_buffer[0] == BlobSha256Format::VERSION_BYTE
-            {
-                let (_, normalized_hash) =
-                    ContractCodeSha256Format::normalize_for_decommitment(&key_buffer);
-
-                published_sha256_blobs.insert(
-                    key,
-                    decommittment_processor
-                        .get_preimage_by_hash(normalized_hash)
-                        .ok_or_else(|| anyhow::anyhow!("Published hash is unknown"))?
-                        .clone(),
-                );
-            }
-        }
-    }
-
-    // memory dump for returndata
-    let returndata_page_content = vec![];
-
-    let returndata_mem = MemoryArea {
-        words: returndata_page_content,
-    };
-
-    let calldata_page_content = vec![];
-
-    let calldata_mem = MemoryArea {
-        words: calldata_page_content,
-    };
-
-    let returndata_bytes = match &execution_result {
-        VmExecutionResult::Ok(ref res) => res.clone(),
-        VmExecutionResult::Revert(ref res) => res.clone(),
-        VmExecutionResult::Panic => vec![],
-        VmExecutionResult::MostLikelyDidNotFinish(..) => vec![],
-    };
-
-    let compiler_tests_events: Vec<crate::events::Event> =
-        events.iter().cloned().map(|el| el.into()).collect();
+        known_sha256_blobs,
+        default_aa_code_hash,
+        evm_simulator_code_hash,
+        options,
+    )?;
+    session.run(cycles_limit)?;
 
-    let serialized_events = serde_json::to_string_pretty(&compiler_tests_events).unwrap();
+    session.finish()
+}
 
-    let did_call_or_ret_recently = local_state.previous_code_memory_page.0
-        != local_state.callstack.get_current_stack().code_page.0;
+pub(crate) fn current_instruction(
+    vm: &VmState<
+        InMemoryStorage,
+        TesterMemory,
+        InMemoryEventSink,
+        TesterPrecompilesProcessor,
+        TesterDecommitter,
+        MemoryLogWitnessTracer,
+        8,
+        zk_evm::zkevm_opcode_defs::decoding::EncodingModeProduction,
+    >,
+) -> Instruction {
+    let current_frame = vm.local_state.callstack.get_current_stack();
+    let pc = current_frame.pc;
+    let word = vm
+        .memory
+        .read_slot(
+            current_frame.code_page.0,
+            pc as u32 / INSTRUCTIONS_PER_WORD as u32,
+        )
+        .value;
+
+    decode_instruction_from_word(word, pc)
+}
 
-    Ok(VmSnapshot {
-        registers: local_state.registers,
-        flags: local_state.flags,
-        timestamp: local_state.timestamp,
-        memory_page_counter: local_state.memory_page_counter,
-        tx_number_in_block: local_state.tx_number_in_block,
-        previous_super_pc: local_state.previous_super_pc.as_u64() as u32,
-        did_call_or_ret_recently,
-        calldata_area_dump: calldata_mem,
-        returndata_area_dump: returndata_mem,
-        execution_has_ended,
-        stack_dump: MemoryArea::empty(),
-        heap_dump: MemoryArea::empty(),
-        storage: result_storage,
-        deployed_contracts,
-        execution_result,
-        returndata_bytes,
-        raw_events,
-        to_l1_messages: l1_messages,
-        events,
-        serialized_events,
-        num_cycles_used: cycles_used,
-        // All the ergs from the empty frame should be passed into the root(bootloader) and unused ergs will be returned.
-        num_ergs_used: zk_evm::zkevm_opcode_defs::system_params::VM_INITIAL_FRAME_ERGS
-            - local_state.callstack.current.ergs_remaining,
-        published_sha256_blobs,
-    })
-}
-
-pub(crate) fn vm_may_have_ended<const B: bool>(
+pub(crate) fn vm_may_have_ended(
     vm: &VmState<
         InMemoryStorage,
-        SimpleHashmapMemory,
+        TesterMemory,
         InMemoryEventSink,
-        DefaultPrecompilesProcessor<B>,
-        SimpleDecommitter<B>,
+        TesterPrecompilesProcessor,
+        TesterDecommitter,
         MemoryLogWitnessTracer,
         8,
         zk_evm::zkevm_opcode_defs::decoding::EncodingModeProduction,
     >,
+    tracer: &ExecutionTracer,
 ) -> Option<VmExecutionResult> {
     let execution_has_ended = vm.execution_has_ended();
 
     let r1 = vm.local_state.registers[RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER as usize];
-    let current_address = vm.local_state.callstack.get_current_stack().this_address;
+    let current_frame = vm.local_state.callstack.get_current_stack();
+    let current_address = current_frame.this_address;
 
     let outer_eh_location = <<zk_evm::zkevm_opcode_defs::decoding::EncodingModeProduction as VmEncodingMode<8>>::PcOrImm as AllowedPcOrImm>::max().as_u64();
     match (
@@ -758,7 +672,16 @@ pub(crate) fn vm_may_have_ended<const B: bool>(
         (true, l) if l == outer_eh_location => {
             // check r1,r2,r3
             if vm.local_state.flags.overflow_or_less_than_flag {
-                Some(VmExecutionResult::Panic)
+                let panic_info = tracer.panic_info().unwrap_or_else(|| PanicInfo {
+                    reason: PanicReason::Unknown,
+                    location: ExecutionLocation::new(
+                        current_address,
+                        current_frame.code_address,
+                        current_frame.pc,
+                    ),
+                    frame_depth: vm.local_state.callstack.depth(),
+                });
+                Some(VmExecutionResult::Panic(panic_info))
             } else {
                 let returndata = dump_memory_page_using_primitive_value(&vm.memory, r1);
                 Some(VmExecutionResult::Revert(returndata))
@@ -767,12 +690,13 @@ pub(crate) fn vm_may_have_ended<const B: bool>(
         (_, a) => Some(VmExecutionResult::MostLikelyDidNotFinish(
             current_address,
             a,
+            current_instruction(vm),
         )),
     }
 }
 
 pub(crate) fn dump_memory_page_using_primitive_value(
-    memory: &SimpleHashmapMemory,
+    memory: &TesterMemory,
     ptr: PrimitiveValue,
 ) -> Vec<u8> {
     if !ptr.is_pointer {
@@ -783,7 +707,7 @@ pub(crate) fn dump_memory_page_using_primitive_value(
 }
 
 pub(crate) fn dump_memory_page_using_fat_pointer(
-    memory: &SimpleHashmapMemory,
+    memory: &TesterMemory,
     fat_ptr: FatPointer,
 ) -> Vec<u8> {
     dump_memory_page_by_offset_and_length(
@@ -795,14 +719,13 @@ pub(crate) fn dump_memory_page_using_fat_pointer(
 }
 
 pub(crate) fn dump_memory_page_by_offset_and_length(
-    memory: &SimpleHashmapMemory,
+    memory: &TesterMemory,
     page: u32,
     offset: usize,
     length: usize,
 ) -> Vec<u8> {
-    let mut dump = Vec::with_capacity(length);
     if length == 0 {
-        return dump;
+        return vec![];
     }
 
     let first_word = offset / 32;
@@ -816,29 +739,7 @@ pub(crate) fn dump_memory_page_by_offset_and_length(
 
     let page_part =
         memory.dump_page_content_as_u256_words(page, (first_word as u32)..(last_word as u32));
-
-    let mut is_first = true;
-    let mut remaining = length;
-    for word in page_part.into_iter() {
-        let it = word.into_be_iter();
-        if is_first {
-            is_first = false;
-            let it = it.skip(unalignment);
-            for next in it {
-                if remaining > 0 {
-                    dump.push(next);
-                    remaining -= 1;
-                }
-            }
-        } else {
-            for next in it {
-                if remaining > 0 {
-                    dump.push(next);
-                    remaining -= 1;
-                }
-            }
-        }
-    }
+    let dump = MemoryArea { words: page_part }.dump_be_bytes(unalignment..(unalignment + length));
 
     assert_eq!(
         dump.len(),
@@ -851,3 +752,154 @@ pub(crate) fn dump_memory_page_by_offset_and_length(
 
     dump
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{add_imm, assemble, ret_ok};
+    use crate::tester_memory::ByteAddressableMemory;
+    use zk_evm::zkevm_opcode_defs::system_params::DEPLOYER_SYSTEM_CONTRACT_ADDRESS_LOW;
+
+    const PAGE: u32 = 5;
+
+    /// A xorshift generator, so that the cases are random but reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
+        }
+
+        fn word(&mut self) -> U256 {
+            U256([self.next(), self.next(), self.next(), self.next()])
+        }
+    }
+
+    /// All the words as big-endian bytes, padded with zeros up to `len` bytes.
+    fn flatten(words: &[U256], len: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; std::cmp::max(words.len() * 32, len)];
+        for (word, chunk) in words.iter().zip(bytes.chunks_mut(32)) {
+            word.to_big_endian(chunk);
+        }
+
+        bytes
+    }
+
+    fn check(words: &[U256], range: std::ops::Range<usize>) {
+        let mut memory = TesterMemory::default();
+        memory.populate(vec![(PAGE, words.to_vec())]);
+        // the unaligned memory reads go through neither of the dumps
+        let expected = memory.read_bytes(PAGE, range.start as u32, range.len() as u32);
+        assert_eq!(expected, flatten(words, range.end)[range.clone()]);
+
+        let area_dump = MemoryArea {
+            words: words.to_vec(),
+        }
+        .dump_be_bytes(range.clone());
+        assert_eq!(
+            area_dump,
+            expected,
+            "area dump mismatch for {} words and range {:?}",
+            words.len(),
+            range
+        );
+
+        let page_dump =
+            dump_memory_page_by_offset_and_length(&memory, PAGE, range.start, range.len());
+        assert_eq!(
+            page_dump,
+            expected,
+            "page dump mismatch for {} words and range {:?}",
+            words.len(),
+            range
+        );
+    }
+
+    #[test]
+    fn dumps_match_the_flattened_words_on_edge_cases() {
+        let mut rng = Rng(0x5eed);
+        let words: Vec<U256> = (0..4).map(|_| rng.word()).collect();
+
+        // empty
+        check(&words, 0..0);
+        check(&words, 37..37);
+        // within one word
+        check(&words, 0..32);
+        check(&words, 3..17);
+        check(&words, 33..64);
+        // across the word boundaries
+        check(&words, 31..33);
+        check(&words, 5..100);
+        check(&words, 0..128);
+        // past the stored words
+        check(&words, 120..200);
+        check(&words, 128..160);
+        check(&[], 7..70);
+    }
+
+    #[test]
+    fn dumps_match_the_flattened_words_on_random_ranges() {
+        let mut rng = Rng(0x1234_5678_9abc_def1);
+        for _ in 0..1000 {
+            let words: Vec<U256> = (0..rng.below(8)).map(|_| rng.word()).collect();
+            let start = rng.below(words.len() * 32 + 64);
+            let length = rng.below(160);
+            check(&words, start..(start + length));
+        }
+    }
+
+    #[test]
+    fn unfinished_run_shows_the_instruction_it_stopped_at() {
+        let bytecode = assemble(&[add_imm(1, 1), add_imm(2, 2), add_imm(3, 3), ret_ok()]);
+        // a well-formed code hash, since the VM checks the default account and the simulator ones
+        let code_hash = U256::one() << 248;
+        // only the entry contract code hash has to be registered, as there are no far calls
+        let storage = HashMap::from([(
+            StorageKey {
+                address: Address::from_low_u64_be(DEPLOYER_SYSTEM_CONTRACT_ADDRESS_LOW.into()),
+                key: U256::from_big_endian(default_entry_point_contract_address().as_bytes()),
+            },
+            H256(code_hash.into()),
+        )]);
+
+        let snapshot = run_vm(
+            String::new(),
+            bytecode,
+            &[],
+            storage,
+            HashMap::new(),
+            None,
+            VmLaunchOption::Default,
+            2,
+            HashMap::new(),
+            HashMap::new(),
+            code_hash,
+            code_hash,
+        )
+        .unwrap();
+
+        let VmExecutionResult::MostLikelyDidNotFinish(address, pc, instruction) =
+            &snapshot.execution_result
+        else {
+            panic!("the run has finished: {:?}", snapshot.execution_result);
+        };
+        assert_eq!(*address, default_entry_point_contract_address());
+        assert_eq!(*pc, 2);
+        assert_eq!(instruction.raw, add_imm(3, 3));
+        assert_eq!(
+            format!("{:?}", snapshot.execution_result),
+            format!(
+                "MostLikelyDidNotFinish({:?}, 2, 0002: Add(Add) 0x3, r0 -> r3, r0 ; 0x{:016x})",
+                address,
+                add_imm(3, 3)
+            )
+        );
+    }
+}
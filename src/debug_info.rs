@@ -0,0 +1,229 @@
+use crate::Address;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+///
+/// The source position of an instruction.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SourceLocation {
+    /// The source file path.
+    pub file: String,
+    /// The 1-based line number.
+    pub line: u32,
+    /// The 1-based column number, if known.
+    #[serde(default)]
+    pub column: Option<u32>,
+    /// The name of the enclosing function, if known.
+    #[serde(default)]
+    pub function: Option<String>,
+}
+
+impl std::fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)?;
+        if let Some(column) = self.column {
+            write!(f, ":{}", column)?;
+        }
+        if let Some(function) = self.function.as_ref() {
+            write!(f, " in {}", function)?;
+        }
+
+        Ok(())
+    }
+}
+
+///
+/// The debug info entry as it is serialized by the compiler tooling.
+///
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DebugInfoEntry {
+    pub pc: u16,
+    #[serde(flatten)]
+    pub location: SourceLocation,
+}
+
+///
+/// Mapping from the pc of a contract to the source positions.
+///
+/// Every entry covers all the instructions starting from its pc up to the pc of the next entry,
+/// which matches the line tables emitted by LLVM.
+///
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo {
+    pub locations: BTreeMap<u16, SourceLocation>,
+}
+
+impl DebugInfo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, pc: u16, location: SourceLocation) {
+        self.locations.insert(pc, location);
+    }
+
+    pub fn location_for_pc(&self, pc: u16) -> Option<&SourceLocation> {
+        self.locations
+            .range(..=pc)
+            .next_back()
+            .map(|(_, location)| location)
+    }
+
+    /// Parses a JSON array of `{ "pc", "file", "line", "column", "function" }` objects.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let entries: Vec<DebugInfoEntry> = serde_json::from_str(json)?;
+
+        Ok(entries.into_iter().collect())
+    }
+}
+
+impl FromIterator<DebugInfoEntry> for DebugInfo {
+    fn from_iter<T: IntoIterator<Item = DebugInfoEntry>>(iter: T) -> Self {
+        let locations = iter
+            .into_iter()
+            .map(|entry| (entry.pc, entry.location))
+            .collect();
+
+        Self { locations }
+    }
+}
+
+///
+/// The place in the contract code, optionally resolved to the source position.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionLocation {
+    pub address: Address,
+    pub code_address: Address,
+    pub pc: u16,
+    pub source: Option<SourceLocation>,
+}
+
+impl ExecutionLocation {
+    pub fn new(address: Address, code_address: Address, pc: u16) -> Self {
+        Self {
+            address,
+            code_address,
+            pc,
+            source: None,
+        }
+    }
+
+    /// Resolves the source position using the debug info of the executed code.
+    pub fn annotate(&mut self, debug_info: &HashMap<Address, DebugInfo>) {
+        self.source = debug_info
+            .get(&self.code_address)
+            .and_then(|info| info.location_for_pc(self.pc))
+            .cloned();
+    }
+}
+
+impl std::fmt::Display for ExecutionLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} pc 0x{:04x}", self.address, self.pc)?;
+        if self.code_address != self.address {
+            write!(f, " (code of {:?})", self.code_address)?;
+        }
+        if let Some(source) = self.source.as_ref() {
+            write!(f, " at {}", source)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler_tests::{VmExecutionResult, VmRunOptions};
+    use crate::test_utils::{add_imm, assemble, ret_panic, run};
+
+    const CONTRACT: u64 = 0x10000;
+
+    fn debug_info() -> DebugInfo {
+        DebugInfo::from_json(
+            r#"[
+                { "pc": 0, "file": "Test.sol", "line": 3 },
+                { "pc": 2, "file": "Test.sol", "line": 7, "column": 9, "function": "fail" }
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn entries_cover_the_pcs_up_to_the_next_entry() {
+        let mut info = debug_info();
+        info.insert(
+            5,
+            SourceLocation {
+                file: "Other.sol".to_owned(),
+                line: 1,
+                column: None,
+                function: None,
+            },
+        );
+
+        assert_eq!(info.location_for_pc(0).unwrap().line, 3);
+        assert_eq!(info.location_for_pc(1).unwrap().line, 3);
+        assert_eq!(
+            info.location_for_pc(4).unwrap().to_string(),
+            "Test.sol:7:9 in fail"
+        );
+        assert_eq!(
+            info.location_for_pc(100).unwrap().to_string(),
+            "Other.sol:1"
+        );
+        assert!(DebugInfo::new().location_for_pc(0).is_none());
+        assert!(DebugInfo::from_json(r#"[{ "pc": 0 }]"#).is_err());
+    }
+
+    #[test]
+    fn locations_are_annotated_with_the_code_address() {
+        let address = Address::from_low_u64_be(1);
+        let code_address = Address::from_low_u64_be(2);
+        let debug_info = HashMap::from([(code_address, debug_info())]);
+
+        let mut location = ExecutionLocation::new(address, code_address, 2);
+        location.annotate(&debug_info);
+        assert_eq!(
+            location.to_string(),
+            format!(
+                "{:?} pc 0x0002 (code of {:?}) at Test.sol:7:9 in fail",
+                address, code_address
+            )
+        );
+
+        let mut location = ExecutionLocation::new(code_address, address, 2);
+        location.annotate(&debug_info);
+        assert!(location.source.is_none());
+    }
+
+    #[test]
+    fn panic_is_resolved_to_the_source_position() {
+        let address = Address::from_low_u64_be(CONTRACT);
+        let snapshot = run(
+            HashMap::from([(
+                address,
+                assemble(&[add_imm(1, 1), add_imm(2, 2), add_imm(3, 3), ret_panic()]),
+            )]),
+            address,
+            VmRunOptions {
+                debug_info: HashMap::from([(address, debug_info())]),
+                ..Default::default()
+            },
+        );
+
+        assert!(
+            matches!(snapshot.execution_result, VmExecutionResult::Panic(_)),
+            "unexpected result: {:?}",
+            snapshot.execution_result
+        );
+        let location = snapshot.result_location.unwrap();
+        assert_eq!(location.pc, 3);
+        assert_eq!(
+            location.source.as_ref().unwrap().to_string(),
+            "Test.sol:7:9 in fail"
+        );
+    }
+}
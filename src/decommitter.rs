@@ -0,0 +1,153 @@
+use crate::U256;
+use std::collections::HashMap;
+use zk_evm::abstractions::{DecommittmentProcessor, Memory, MemoryType};
+use zk_evm::aux_structures::{
+    DecommittmentQuery, MemoryIndex, MemoryLocation, MemoryPage, MemoryQuery,
+};
+use zk_evm::zkevm_opcode_defs::{
+    BlobSha256Format, ContractCodeSha256Format, VersionedHashHeader, VersionedHashLen32,
+    VersionedHashNormalizedPreimage,
+};
+
+///
+/// The decommitter that behaves like `SimpleDecommitter`, but can be cloned, so that the
+/// session checkpoints own the known code and the set of already decommitted hashes.
+///
+/// It returns the decommitted code as the witness only if `record_witness` is set, so that
+/// the runs that do not record the full witness do not copy the code.
+///
+#[derive(Debug, Default, Clone)]
+pub struct TesterDecommitter {
+    pub known_hashes: HashMap<VersionedHashNormalizedPreimage, Vec<U256>>,
+    /// The page and the length in words of every decommitted hash.
+    pub history: HashMap<VersionedHashNormalizedPreimage, (u32, u16)>,
+    pub record_witness: bool,
+}
+
+impl TesterDecommitter {
+    pub fn new(record_witness: bool) -> Self {
+        Self {
+            record_witness,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_preimage_by_hash(
+        &self,
+        hash: VersionedHashNormalizedPreimage,
+    ) -> Option<&Vec<U256>> {
+        self.known_hashes.get(&hash)
+    }
+
+    pub fn populate(&mut self, elements: Vec<(U256, Vec<U256>)>) {
+        let mut buffer = [0u8; 32];
+        for (hash, values) in elements.into_iter() {
+            hash.to_big_endian(&mut buffer);
+            let normalized = if ContractCodeSha256Format::is_valid(&buffer) {
+                let (_, normalized) = ContractCodeSha256Format::normalize_for_decommitment(&buffer);
+                normalized
+            } else if BlobSha256Format::is_valid(&buffer) {
+                let (_, normalized) = BlobSha256Format::normalize_for_decommitment(&buffer);
+                normalized
+            } else {
+                panic!("Unknown versioned hash format {:?}", hash);
+            };
+            assert!(!self.known_hashes.contains_key(&normalized));
+            self.known_hashes.insert(normalized, values);
+        }
+    }
+
+    fn known_code(&self, query: &DecommittmentQuery) -> anyhow::Result<&Vec<U256>> {
+        self.known_hashes
+            .get(&query.normalized_preimage)
+            .ok_or_else(|| {
+                anyhow::anyhow!("Code hash {:?} must be known", query.normalized_preimage)
+            })
+    }
+}
+
+/// The length in words of the preimage, as encoded in the versioned hash header.
+fn preimage_length_in_words(header: VersionedHashHeader) -> Option<u16> {
+    let mut buffer = [0u8; 32];
+    buffer[..4].copy_from_slice(&header.0);
+
+    if ContractCodeSha256Format::is_valid(&buffer) {
+        Some(ContractCodeSha256Format::code_length_in_bytes32_words(
+            &buffer,
+        ))
+    } else if BlobSha256Format::is_valid(&buffer) {
+        Some(BlobSha256Format::get_len_in_bytes32_words(&buffer))
+    } else {
+        None
+    }
+}
+
+impl DecommittmentProcessor for TesterDecommitter {
+    fn prepare_to_decommit(
+        &mut self,
+        _monotonic_cycle_counter: u32,
+        mut partial_query: DecommittmentQuery,
+    ) -> anyhow::Result<DecommittmentQuery> {
+        if let Some((old_page, old_len)) = self
+            .history
+            .get(&partial_query.normalized_preimage)
+            .copied()
+        {
+            partial_query.is_fresh = false;
+            partial_query.memory_page = MemoryPage(old_page);
+            partial_query.decommitted_length = old_len;
+        } else {
+            let length = self.known_code(&partial_query)?.len();
+            if let Some(expected) = preimage_length_in_words(partial_query.header) {
+                anyhow::ensure!(
+                    length == expected as usize,
+                    "The preimage of {:?} has {} words, while its versioned hash requires {}",
+                    partial_query.normalized_preimage,
+                    length,
+                    expected
+                );
+            }
+            partial_query.decommitted_length = length as u16;
+            partial_query.is_fresh = true;
+        }
+
+        Ok(partial_query)
+    }
+
+    fn decommit_into_memory<M: Memory>(
+        &mut self,
+        monotonic_cycle_counter: u32,
+        partial_query: DecommittmentQuery,
+        memory: &mut M,
+    ) -> anyhow::Result<Option<Vec<U256>>> {
+        assert!(partial_query.is_fresh);
+        let values = self.known_code(&partial_query)?.clone();
+        assert_eq!(partial_query.decommitted_length, values.len() as u16);
+
+        let existing = self.history.insert(
+            partial_query.normalized_preimage,
+            (
+                partial_query.memory_page.0,
+                partial_query.decommitted_length,
+            ),
+        );
+        assert!(existing.is_none());
+
+        for (index, value) in values.iter().enumerate() {
+            let query = MemoryQuery {
+                timestamp: partial_query.timestamp,
+                location: MemoryLocation {
+                    memory_type: MemoryType::Code,
+                    page: partial_query.memory_page,
+                    index: MemoryIndex(index as u32),
+                },
+                value: *value,
+                value_is_pointer: false,
+                rw_flag: true,
+            };
+            memory.specialized_code_query(monotonic_cycle_counter, query);
+        }
+
+        Ok(self.record_witness.then_some(values))
+    }
+}
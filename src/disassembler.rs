@@ -0,0 +1,235 @@
+use crate::U256;
+use zk_evm::zkevm_opcode_defs::decoding::{EncodingModeProduction, VmEncodingMode};
+use zk_evm::zkevm_opcode_defs::{
+    Condition, DecodedOpcode, ImmMemHandlerFlags, Opcode, Operand, RegOrImmFlags,
+};
+
+/// Number of instructions packed into a single 32-byte word in the production encoding.
+pub const INSTRUCTIONS_PER_WORD: usize = 4;
+
+#[derive(Clone, Debug)]
+pub struct Instruction {
+    pub pc: u16,
+    pub raw: u64,
+    pub decoded: DecodedOpcode<8, EncodingModeProduction>,
+}
+
+impl Instruction {
+    pub fn decode(pc: u16, raw: u64) -> Self {
+        let (decoded, _) =
+            <EncodingModeProduction as VmEncodingMode<8>>::parse_preliminary_variant_and_absolute_number(raw);
+
+        Self { pc, raw, decoded }
+    }
+
+    pub fn is_invalid(&self) -> bool {
+        matches!(self.decoded.variant.opcode, Opcode::Invalid(_))
+    }
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let decoded = &self.decoded;
+        write!(f, "{:04x}: {:?}", self.pc, decoded.variant.opcode)?;
+        if decoded.condition != Condition::Always {
+            write!(f, ".{:?}", decoded.condition)?;
+        }
+        write!(
+            f,
+            " {}, r{} -> {}, r{}",
+            format_operand(
+                decoded.variant.src0_operand_type,
+                decoded.src0_reg_idx,
+                decoded.imm_0
+            ),
+            decoded.src1_reg_idx,
+            format_operand(
+                decoded.variant.dst0_operand_type,
+                decoded.dst0_reg_idx,
+                decoded.imm_1
+            ),
+            decoded.dst1_reg_idx,
+        )?;
+        write!(f, " ; 0x{:016x}", self.raw)
+    }
+}
+
+fn format_operand(operand: Operand, reg_idx: u8, imm: u16) -> String {
+    match operand {
+        Operand::RegOnly
+        | Operand::RegOrImm(RegOrImmFlags::UseRegOnly)
+        | Operand::Full(ImmMemHandlerFlags::UseRegOnly) => format!("r{}", reg_idx),
+        Operand::RegOrImm(RegOrImmFlags::UseImm16Only)
+        | Operand::Full(ImmMemHandlerFlags::UseImm16Only) => format!("0x{:x}", imm),
+        Operand::Full(ImmMemHandlerFlags::UseStackWithPushPop) => {
+            format!("stack-=[r{} + 0x{:x}]", reg_idx, imm)
+        }
+        Operand::Full(ImmMemHandlerFlags::UseStackWithOffset) => {
+            format!("stack-[r{} + 0x{:x}]", reg_idx, imm)
+        }
+        Operand::Full(ImmMemHandlerFlags::UseAbsoluteOnStack) => {
+            format!("stack[r{} + 0x{:x}]", reg_idx, imm)
+        }
+        Operand::Full(ImmMemHandlerFlags::UseCodePage) => {
+            format!("code[r{} + 0x{:x}]", reg_idx, imm)
+        }
+    }
+}
+
+///
+/// Bytecode split into the instructions and the constants that follow them.
+///
+/// There is no explicit marker of the code section length in the bytecode, so the code section
+/// is considered to end at the word that contains the first invalid instruction (the compilers
+/// pad the code with those up to the word boundary).
+///
+#[derive(Clone, Debug)]
+pub struct Disassembly {
+    pub instructions: Vec<Instruction>,
+    /// Constants as `(word index in the code page, value)`.
+    pub constants: Vec<(u16, U256)>,
+}
+
+impl Disassembly {
+    pub fn instruction_at(&self, pc: u16) -> Option<&Instruction> {
+        self.instructions.get(pc as usize)
+    }
+
+    pub fn code_section_words(&self) -> usize {
+        self.instructions.len() / INSTRUCTIONS_PER_WORD
+    }
+}
+
+impl std::fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, ".code")?;
+        for instruction in self.instructions.iter() {
+            writeln!(f, "    {}", instruction)?;
+        }
+        writeln!(f, ".constants")?;
+        for (index, value) in self.constants.iter() {
+            writeln!(f, "    {:04x}: 0x{:064x}", index, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the raw instruction at the given position of a code word.
+pub fn raw_instruction_from_word(word: U256, pc: u16) -> u64 {
+    // the first instruction in the word is the most significant one
+    let sub_pc = pc as usize % INSTRUCTIONS_PER_WORD;
+    word.0[INSTRUCTIONS_PER_WORD - 1 - sub_pc]
+}
+
+/// Decodes the instruction at `pc` given the code word `pc` belongs to.
+pub fn decode_instruction_from_word(word: U256, pc: u16) -> Instruction {
+    Instruction::decode(pc, raw_instruction_from_word(word, pc))
+}
+
+pub fn disassemble(bytecode: &[u8]) -> anyhow::Result<Disassembly> {
+    if bytecode.len() % 32 != 0 {
+        anyhow::bail!(
+            "Bytecode length {} is not a multiple of 32 bytes",
+            bytecode.len()
+        );
+    }
+    let words: Vec<U256> = bytecode.chunks(32).map(U256::from_big_endian).collect();
+
+    Ok(disassemble_words(&words))
+}
+
+pub fn disassemble_words(words: &[U256]) -> Disassembly {
+    let mut instructions = Vec::new();
+    let mut code_words = words.len();
+    'outer: for (word_index, word) in words.iter().enumerate() {
+        for sub_pc in 0..INSTRUCTIONS_PER_WORD {
+            let pc = (word_index * INSTRUCTIONS_PER_WORD + sub_pc) as u16;
+            let instruction = decode_instruction_from_word(*word, pc);
+            if instruction.is_invalid() {
+                code_words = word_index + 1;
+                // keep the padding so that the code section is whole words
+                for sub_pc in sub_pc..INSTRUCTIONS_PER_WORD {
+                    let pc = (word_index * INSTRUCTIONS_PER_WORD + sub_pc) as u16;
+                    instructions.push(decode_instruction_from_word(*word, pc));
+                }
+                break 'outer;
+            }
+            instructions.push(instruction);
+        }
+    }
+
+    let constants = words
+        .iter()
+        .enumerate()
+        .skip(code_words)
+        .map(|(index, word)| (index as u16, *word))
+        .collect();
+
+    Disassembly {
+        instructions,
+        constants,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{add_imm, assemble, ret_ok};
+    use zk_evm::zkevm_opcode_defs::{AddOpcode, RetOpcode};
+
+    #[test]
+    fn known_instruction_word_is_decoded() {
+        // `add 0x2a, r0, r3`: the immediate, the registers, then the opcode variant
+        let raw = 0x0000_002a_0300_0039;
+        assert_eq!(raw, add_imm(0x2a, 3));
+        let instruction = Instruction::decode(7, raw);
+
+        assert!(!instruction.is_invalid());
+        assert_eq!(
+            instruction.decoded.variant.opcode,
+            Opcode::Add(AddOpcode::Add)
+        );
+        assert_eq!(
+            instruction.decoded.variant.src0_operand_type,
+            Operand::Full(ImmMemHandlerFlags::UseImm16Only)
+        );
+        assert_eq!(instruction.decoded.imm_0, 0x2a);
+        assert_eq!(instruction.decoded.dst0_reg_idx, 3);
+        assert_eq!(
+            instruction.to_string(),
+            "0007: Add(Add) 0x2a, r0 -> r3, r0 ; 0x0000002a03000039"
+        );
+    }
+
+    #[test]
+    fn code_is_split_from_the_constants() {
+        let mut bytecode = assemble(&[add_imm(1, 1), add_imm(2, 2), ret_ok()]);
+        bytecode.truncate(32);
+        let constant = U256::from(0xc0ffeeu64);
+        let mut word = [0u8; 32];
+        constant.to_big_endian(&mut word);
+        bytecode.extend(word);
+        bytecode.extend([0u8; 32]);
+
+        let disassembly = disassemble(&bytecode).unwrap();
+        assert_eq!(disassembly.code_section_words(), 1);
+        assert_eq!(
+            disassembly
+                .instruction_at(2)
+                .unwrap()
+                .decoded
+                .variant
+                .opcode,
+            Opcode::Ret(RetOpcode::Ok)
+        );
+        assert!(disassembly.instruction_at(3).unwrap().is_invalid());
+        assert!(disassembly.instruction_at(4).is_none());
+        assert_eq!(
+            disassembly.constants,
+            vec![(1, constant), (2, U256::zero())]
+        );
+
+        assert!(disassemble(&bytecode[..40]).is_err());
+    }
+}
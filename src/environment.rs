@@ -0,0 +1,733 @@
+use crate::bytecode::bytecode_hash;
+use crate::compiler_tests::{
+    run_vm_multi_contracts_with_options, FullABIParams, StorageKey, VmExecutionContext,
+    VmExecutionResult, VmLaunchOption, VmRunOptions, VmSnapshot,
+};
+use crate::evm_deploy::{evm_bytecode_to_words, h256_to_u256, hash_evm_bytecode};
+use crate::system_contracts::{
+    account_code_key, create2_address, immutable_key, known_code_key, register_code_hash,
+    SystemContracts, CONTRACT_DEPLOYER_ADDRESS,
+};
+use crate::{Address, H256, U256};
+use std::collections::HashMap;
+
+///
+/// The state the runs happen in. Unlike the plain runner functions, it keeps the storage and
+/// the deployed contracts between the runs.
+///
+#[derive(Debug, Clone, Default)]
+pub struct VmEnvironment {
+    pub contracts: HashMap<Address, Vec<u8>>,
+    pub storage: HashMap<StorageKey, H256>,
+    pub known_contracts: HashMap<U256, Vec<u8>>,
+    pub known_sha256_blobs: HashMap<U256, Vec<U256>>,
+    pub default_aa_code_hash: U256,
+    pub evm_simulator_code_hash: U256,
+    pub cycles_limit: usize,
+}
+
+///
+/// The result of a deployment.
+///
+#[derive(Debug)]
+pub struct Deployment {
+    pub address: Address,
+    pub snapshot: VmSnapshot,
+}
+
+impl VmEnvironment {
+    pub fn new(cycles_limit: usize) -> Self {
+        Self {
+            cycles_limit,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the system contracts from the directory of the compiled artifacts.
+    pub fn load_system_contracts(&mut self, directory: &std::path::Path) -> anyhow::Result<()> {
+        let system_contracts = SystemContracts::load(directory)?;
+        self.contracts.extend(system_contracts.contracts);
+        self.storage.extend(system_contracts.storage);
+
+        Ok(())
+    }
+
+    /// Sets the EraVM bytecode of the EVM simulator the EVM contracts are executed with.
+    pub fn set_evm_simulator(&mut self, bytecode: Vec<u8>) -> anyhow::Result<()> {
+        let hash = U256::from_big_endian(bytecode_hash(&bytecode)?.as_bytes());
+        self.known_contracts.insert(hash, bytecode);
+        self.evm_simulator_code_hash = hash;
+
+        Ok(())
+    }
+
+    ///
+    /// Deploys the raw EVM bytecode at the address, returning its versioned hash.
+    ///
+    /// The bytecode is padded, made available to the decommitter as a SHA256 blob, and its
+    /// hash is registered in the deployer storage, so that calls to the address run the EVM
+    /// simulator set with `set_evm_simulator`.
+    ///
+    pub fn add_evm_contract(
+        &mut self,
+        address: Address,
+        raw_evm_bytecode: &[u8],
+    ) -> anyhow::Result<H256> {
+        anyhow::ensure!(
+            self.known_contracts
+                .contains_key(&self.evm_simulator_code_hash),
+            "The EVM simulator is not set"
+        );
+
+        let hash = hash_evm_bytecode(raw_evm_bytecode)?;
+        self.known_sha256_blobs
+            .insert(h256_to_u256(hash), evm_bytecode_to_words(raw_evm_bytecode)?);
+        register_code_hash(&mut self.storage, address, hash);
+
+        Ok(hash)
+    }
+
+    /// Whether the ContractDeployer system contract is loaded.
+    pub fn has_system_contracts(&self) -> bool {
+        self.contracts.contains_key(&CONTRACT_DEPLOYER_ADDRESS)
+    }
+
+    /// Runs the call without changing the environment.
+    pub fn run(
+        &self,
+        entry_address: Address,
+        calldata: &[u8],
+        context: Option<VmExecutionContext>,
+        vm_launch_option: VmLaunchOption,
+        options: VmRunOptions,
+    ) -> anyhow::Result<VmSnapshot> {
+        run_vm_multi_contracts_with_options(
+            String::new(),
+            self.contracts.clone(),
+            calldata,
+            self.storage.clone(),
+            HashMap::new(),
+            entry_address,
+            context,
+            vm_launch_option,
+            self.cycles_limit,
+            self.known_contracts.clone(),
+            self.known_sha256_blobs.clone(),
+            self.default_aa_code_hash,
+            self.evm_simulator_code_hash,
+            options,
+        )
+    }
+
+    /// Takes over the final storage and the contracts deployed in the run.
+    pub fn apply(&mut self, snapshot: &VmSnapshot) {
+        self.storage = snapshot.storage.clone();
+        for (address, bytecode) in snapshot.deployed_contracts.iter() {
+            self.contracts.insert(*address, bytecode.clone());
+        }
+        for (hash, blob) in snapshot.published_sha256_blobs.iter() {
+            self.known_sha256_blobs.insert(*hash, blob.clone());
+        }
+    }
+
+    /// Runs the call and applies its results to the environment.
+    pub fn execute(
+        &mut self,
+        entry_address: Address,
+        calldata: &[u8],
+        context: Option<VmExecutionContext>,
+        vm_launch_option: VmLaunchOption,
+        options: VmRunOptions,
+    ) -> anyhow::Result<VmSnapshot> {
+        let snapshot = self.run(entry_address, calldata, context, vm_launch_option, options)?;
+        self.apply(&snapshot);
+
+        Ok(snapshot)
+    }
+
+    ///
+    /// Deploys the contract with `create2` on behalf of `deployer`.
+    ///
+    /// With the system contracts loaded, the deployment goes through the ContractDeployer.
+    /// Otherwise, the constructor is run directly and the code hash is recorded in the
+    /// deployer storage, along with the immutables the constructor returned.
+    ///
+    /// The environment is only changed if the deployment succeeds.
+    ///
+    pub fn deploy(
+        &mut self,
+        bytecode: Vec<u8>,
+        constructor_calldata: &[u8],
+        deployer: Address,
+        salt: H256,
+        options: VmRunOptions,
+    ) -> anyhow::Result<Deployment> {
+        let bytecode_hash = bytecode_hash(&bytecode)?;
+        let bytecode_hash_as_u256 = U256::from_big_endian(bytecode_hash.as_bytes());
+
+        if self.has_system_contracts() {
+            self.deploy_with_contract_deployer(
+                bytecode,
+                bytecode_hash,
+                constructor_calldata,
+                deployer,
+                salt,
+                options,
+            )
+        } else {
+            let address = create2_address(deployer, salt, bytecode_hash, constructor_calldata);
+            let mut environment = self.clone();
+            environment
+                .known_contracts
+                .entry(bytecode_hash_as_u256)
+                .or_insert_with(|| bytecode.clone());
+            environment.contracts.insert(address, bytecode);
+
+            let mut constructing_hash = bytecode_hash;
+            constructing_hash.as_bytes_mut()[1] = 1;
+            environment
+                .storage
+                .insert(account_code_key(address), constructing_hash);
+
+            let context = VmExecutionContext::new(address, deployer, 0, 0);
+            let snapshot = environment.execute(
+                address,
+                constructor_calldata,
+                Some(context),
+                VmLaunchOption::ManualCallABI(FullABIParams {
+                    is_constructor: true,
+                    is_system_call: false,
+                    r3_value: None,
+                    r4_value: None,
+                    r5_value: None,
+                }),
+                options,
+            )?;
+            let VmExecutionResult::Ok(returndata) = &snapshot.execution_result else {
+                anyhow::bail!(
+                    "Constructor of {:?} failed: {:?}",
+                    address,
+                    snapshot.execution_result
+                );
+            };
+
+            environment
+                .storage
+                .insert(account_code_key(address), bytecode_hash);
+            for (index, value) in decode_immutables(returndata)? {
+                environment
+                    .storage
+                    .insert(immutable_key(address, index), value);
+            }
+
+            *self = environment;
+
+            Ok(Deployment { address, snapshot })
+        }
+    }
+
+    fn deploy_with_contract_deployer(
+        &mut self,
+        bytecode: Vec<u8>,
+        bytecode_hash: H256,
+        constructor_calldata: &[u8],
+        deployer: Address,
+        salt: H256,
+        options: VmRunOptions,
+    ) -> anyhow::Result<Deployment> {
+        let mut environment = self.clone();
+        environment
+            .known_contracts
+            .insert(U256::from_big_endian(bytecode_hash.as_bytes()), bytecode);
+        environment
+            .storage
+            .insert(known_code_key(bytecode_hash), H256::from_low_u64_be(1));
+
+        let mut calldata = ethabi::short_signature(
+            "create2",
+            &[
+                ethabi::ParamType::FixedBytes(32),
+                ethabi::ParamType::FixedBytes(32),
+                ethabi::ParamType::Bytes,
+            ],
+        )
+        .to_vec();
+        calldata.extend(ethabi::encode(&[
+            ethabi::Token::FixedBytes(salt.as_bytes().to_vec()),
+            ethabi::Token::FixedBytes(bytecode_hash.as_bytes().to_vec()),
+            ethabi::Token::Bytes(constructor_calldata.to_vec()),
+        ]));
+
+        let context = VmExecutionContext::new(CONTRACT_DEPLOYER_ADDRESS, deployer, 0, 0);
+        let snapshot = environment.execute(
+            CONTRACT_DEPLOYER_ADDRESS,
+            &calldata,
+            Some(context),
+            VmLaunchOption::ManualCallABI(FullABIParams {
+                is_constructor: false,
+                is_system_call: true,
+                r3_value: None,
+                r4_value: None,
+                r5_value: None,
+            }),
+            options,
+        )?;
+        let address = match &snapshot.execution_result {
+            VmExecutionResult::Ok(returndata) if returndata.len() >= 32 => {
+                Address::from_slice(&returndata[12..32])
+            }
+            result => anyhow::bail!("ContractDeployer `create2` failed: {:?}", result),
+        };
+
+        *self = environment;
+
+        Ok(Deployment { address, snapshot })
+    }
+}
+
+/// Decodes the `ImmutableData[]` returned by the constructor.
+fn decode_immutables(returndata: &[u8]) -> anyhow::Result<Vec<(U256, H256)>> {
+    if returndata.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let tokens = ethabi::decode(
+        &[ethabi::ParamType::Array(Box::new(
+            ethabi::ParamType::Tuple(vec![
+                ethabi::ParamType::Uint(256),
+                ethabi::ParamType::FixedBytes(32),
+            ]),
+        ))],
+        returndata,
+    )
+    .map_err(|error| anyhow::anyhow!("Invalid constructor returndata: {}", error))?;
+
+    let immutables = tokens
+        .into_iter()
+        .next()
+        .and_then(|token| token.into_array())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|token| {
+            let mut fields = token.into_tuple()?.into_iter();
+            let mut index = [0u8; 32];
+            fields.next()?.into_uint()?.to_big_endian(&mut index);
+            let value = fields.next()?.into_fixed_bytes()?;
+            Some((U256::from_big_endian(&index), H256::from_slice(&value)))
+        })
+        .collect();
+
+    Ok(immutables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        add_imm, assemble, default_code, far_call, instruction, ret_ok, ret_panic, uma,
+    };
+    use zk_evm::zkevm_opcode_defs::{
+        BlobSha256Format, ImmMemHandlerFlags, LogOpcode, Opcode, Operand, PtrOpcode, RetOpcode,
+        ShiftOpcode, SubOpcode, UMAOpcode,
+    };
+
+    const IMMUTABLE_INDEX: u16 = 0x24;
+    const IMMUTABLE_VALUE: u16 = 0x4242;
+
+    fn environment() -> VmEnvironment {
+        let (default_code_hash, default_code) = default_code();
+        let mut environment = VmEnvironment::new(10_000);
+        environment
+            .known_contracts
+            .insert(default_code_hash, default_code);
+        environment.default_aa_code_hash = default_code_hash;
+        environment.evm_simulator_code_hash = default_code_hash;
+
+        environment
+    }
+
+    ///
+    /// The contract whose constructor returns a single immutable, encoded as `ImmutableData[]`:
+    /// the array offset, the length, then the index and the value.
+    ///
+    fn contract_with_an_immutable() -> Vec<u8> {
+        let mut program = vec![];
+        for (offset, value) in [
+            (0, 32),
+            (32, 1),
+            (64, IMMUTABLE_INDEX),
+            (96, IMMUTABLE_VALUE),
+        ] {
+            // heap[r1] = r2
+            program.extend([
+                add_imm(offset, 1),
+                add_imm(value, 2),
+                uma(UMAOpcode::HeapWrite, (1, 2, 0, 0)),
+            ]);
+        }
+        program.extend(return_heap(128));
+
+        assemble(&program)
+    }
+
+    /// Returns the first `length` bytes of the heap, with `r1` and `r2` as the scratch registers.
+    fn return_heap(length: u16) -> [u64; 4] {
+        [
+            // r1: the heap slice, with the length in bits 96..128
+            add_imm(length, 1),
+            add_imm(96, 2),
+            instruction(
+                Opcode::Shift(ShiftOpcode::Shl),
+                Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+                Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+                (1, 2, 1),
+                0,
+            ),
+            instruction(
+                Opcode::Ret(RetOpcode::Ok),
+                Operand::RegOnly,
+                Operand::RegOnly,
+                (1, 0, 0),
+                0,
+            ),
+        ]
+    }
+
+    #[test]
+    fn deployment_runs_the_constructor_and_records_the_immutables() {
+        let mut environment = environment();
+        let bytecode = contract_with_an_immutable();
+        let bytecode_hash = bytecode_hash(&bytecode).unwrap();
+        let deployer = Address::from_low_u64_be(0xde910e5);
+        let salt = H256::from_low_u64_be(7);
+        let constructor_calldata = [0xaa; 36];
+
+        let deployment = environment
+            .deploy(
+                bytecode.clone(),
+                &constructor_calldata,
+                deployer,
+                salt,
+                VmRunOptions::default(),
+            )
+            .unwrap();
+
+        let address = deployment.address;
+        assert_eq!(
+            address,
+            create2_address(deployer, salt, bytecode_hash, &constructor_calldata)
+        );
+        assert_ne!(
+            address,
+            create2_address(deployer, H256::zero(), bytecode_hash, &constructor_calldata)
+        );
+        assert_eq!(environment.contracts[&address], bytecode);
+
+        // the constructor ran with the constructing hash, which is finalized afterwards
+        let mut constructing_hash = bytecode_hash;
+        constructing_hash.as_bytes_mut()[1] = 1;
+        assert_eq!(
+            deployment.snapshot.storage[&account_code_key(address)],
+            constructing_hash
+        );
+        assert_eq!(
+            environment.storage[&account_code_key(address)],
+            bytecode_hash
+        );
+
+        let immutable = environment.storage[&immutable_key(address, U256::from(IMMUTABLE_INDEX))];
+        assert_eq!(immutable, H256::from_low_u64_be(IMMUTABLE_VALUE.into()));
+
+        // the deployed contract can be called
+        let snapshot = environment
+            .run(
+                address,
+                &[],
+                None,
+                VmLaunchOption::Default,
+                VmRunOptions::default(),
+            )
+            .unwrap();
+        assert!(matches!(
+            snapshot.execution_result,
+            VmExecutionResult::Ok(_)
+        ));
+    }
+
+    const EVM_CONTRACT: u64 = 0xe0e0;
+    const SIMULATOR_MARKER: u16 = 0x5151;
+
+    /// The EVM simulator that ignores the EVM bytecode and returns `SIMULATOR_MARKER`.
+    fn evm_simulator() -> Vec<u8> {
+        let mut program = vec![
+            add_imm(0, 1),
+            add_imm(SIMULATOR_MARKER, 2),
+            uma(UMAOpcode::HeapWrite, (1, 2, 0, 0)),
+        ];
+        program.extend(return_heap(32));
+
+        assemble(&program)
+    }
+
+    fn simulator_returndata() -> Vec<u8> {
+        H256::from_low_u64_be(SIMULATOR_MARKER.into())
+            .as_bytes()
+            .to_vec()
+    }
+
+    #[test]
+    fn evm_contracts_require_the_simulator() {
+        let mut environment = environment();
+        environment.known_contracts.clear();
+
+        assert!(environment
+            .add_evm_contract(Address::from_low_u64_be(EVM_CONTRACT), &[0x00])
+            .is_err());
+    }
+
+    #[test]
+    fn evm_contracts_are_run_by_the_simulator() {
+        let mut environment = environment();
+        environment.set_evm_simulator(evm_simulator()).unwrap();
+        let evm_address = Address::from_low_u64_be(EVM_CONTRACT);
+        // PUSH1 0x01 PUSH1 0x00 SSTORE STOP
+        let evm_bytecode = [0x60, 0x01, 0x60, 0x00, 0x55, 0x00];
+        let hash = environment
+            .add_evm_contract(evm_address, &evm_bytecode)
+            .unwrap();
+
+        assert_eq!(hash, hash_evm_bytecode(&evm_bytecode).unwrap());
+        assert_eq!(environment.storage[&account_code_key(evm_address)], hash);
+        assert_eq!(
+            environment.known_sha256_blobs[&h256_to_u256(hash)],
+            evm_bytecode_to_words(&evm_bytecode).unwrap()
+        );
+
+        // called directly
+        let snapshot = environment
+            .run(
+                evm_address,
+                &[],
+                None,
+                VmLaunchOption::Default,
+                VmRunOptions::default(),
+            )
+            .unwrap();
+        let VmExecutionResult::Ok(returndata) = &snapshot.execution_result else {
+            panic!(
+                "the EVM contract has failed: {:?}",
+                snapshot.execution_result
+            );
+        };
+        assert_eq!(returndata, &simulator_returndata());
+
+        // far called from an EraVM contract
+        let caller = Address::from_low_u64_be(0x10000);
+        let mut program = far_call(EVM_CONTRACT as u16, 6).to_vec();
+        program.extend([ret_ok(), ret_panic()]);
+        environment.contracts.insert(caller, assemble(&program));
+        let snapshot = environment
+            .run(
+                caller,
+                &[],
+                None,
+                VmLaunchOption::Default,
+                VmRunOptions {
+                    register_contracts: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(matches!(
+            snapshot.execution_result,
+            VmExecutionResult::Ok(_)
+        ));
+        let calls: Vec<_> = snapshot.call_tree.far_calls().collect();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].code_address, evm_address);
+        assert_eq!(calls[0].returndata, simulator_returndata());
+    }
+
+    /// The kernel space address, so that the simulator running its code may decommit.
+    const KERNEL_EVM_CONTRACT: u64 = 0x9e0e;
+
+    ///
+    /// The EVM simulator that decommits the versioned hash passed in the calldata and returns
+    /// the first `length` bytes of the decommitted blob.
+    ///
+    fn decommitting_evm_simulator(length: u16) -> Vec<u8> {
+        let shl = |registers| {
+            instruction(
+                Opcode::Shift(ShiftOpcode::Shl),
+                Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+                Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+                registers,
+                0,
+            )
+        };
+        let ptr = |opcode| {
+            instruction(
+                Opcode::Ptr(opcode),
+                Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+                Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+                (1, 4, 1),
+                0,
+            )
+        };
+
+        assemble(&[
+            // r3: the versioned hash
+            uma(UMAOpcode::FatPointerRead, (1, 0, 3, 0)),
+            // r1: the pointer to the whole kernel heap stipend the blob is decommitted to
+            instruction(
+                Opcode::Log(LogOpcode::Decommit),
+                Operand::RegOnly,
+                Operand::RegOnly,
+                (3, 0, 1),
+                0,
+            ),
+            // shrink the pointer by `(1 << 21) - length`
+            add_imm(1, 4),
+            add_imm(21, 5),
+            shl((4, 5, 4)),
+            add_imm(length, 5),
+            instruction(
+                Opcode::Sub(SubOpcode::Sub),
+                Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+                Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+                (4, 5, 4),
+                0,
+            ),
+            ptr(PtrOpcode::Shrink),
+            // forward the pointer
+            add_imm(1, 4),
+            add_imm(224, 5),
+            shl((4, 5, 4)),
+            ptr(PtrOpcode::Pack),
+            instruction(
+                Opcode::Ret(RetOpcode::Ok),
+                Operand::RegOnly,
+                Operand::RegOnly,
+                (1, 0, 0),
+                0,
+            ),
+        ])
+    }
+
+    #[test]
+    fn evm_simulator_decommits_the_padded_bytecode() {
+        // 40 bytes, i.e. two words, which are padded to three
+        let evm_bytecode: Vec<u8> = (1..=40).collect();
+        let blob_length = 3 * 32;
+
+        let mut environment = environment();
+        environment
+            .set_evm_simulator(decommitting_evm_simulator(blob_length))
+            .unwrap();
+        let evm_address = Address::from_low_u64_be(KERNEL_EVM_CONTRACT);
+        let hash = environment
+            .add_evm_contract(evm_address, &evm_bytecode)
+            .unwrap();
+        assert_eq!(
+            BlobSha256Format::get_len_in_bytes32_words(hash.as_fixed_bytes()),
+            blob_length / 32
+        );
+
+        let snapshot = environment
+            .run(
+                evm_address,
+                hash.as_bytes(),
+                None,
+                VmLaunchOption::Default,
+                VmRunOptions::default(),
+            )
+            .unwrap();
+        let VmExecutionResult::Ok(returndata) = &snapshot.execution_result else {
+            panic!(
+                "the EVM simulator has failed: {:?}",
+                snapshot.execution_result
+            );
+        };
+
+        let mut expected = evm_bytecode;
+        expected.resize(blob_length as usize, 0);
+        assert_eq!(returndata, &expected);
+    }
+
+    #[test]
+    fn blobs_not_matching_their_hash_are_not_decommitted() {
+        let evm_bytecode: Vec<u8> = (1..=40).collect();
+
+        let mut environment = environment();
+        environment
+            .set_evm_simulator(decommitting_evm_simulator(96))
+            .unwrap();
+        let evm_address = Address::from_low_u64_be(KERNEL_EVM_CONTRACT);
+        let hash = environment
+            .add_evm_contract(evm_address, &evm_bytecode)
+            .unwrap();
+        // the two words of the bytecode without the odd word padding
+        environment
+            .known_sha256_blobs
+            .get_mut(&h256_to_u256(hash))
+            .unwrap()
+            .pop();
+
+        let error = environment
+            .run(
+                evm_address,
+                hash.as_bytes(),
+                None,
+                VmLaunchOption::Default,
+                VmRunOptions::default(),
+            )
+            .unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("while its versioned hash requires 3"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[test]
+    fn failed_deployment_leaves_the_environment_untouched() {
+        let mut environment = environment();
+        let storage = environment.storage.clone();
+
+        let result = environment.deploy(
+            assemble(&[ret_panic()]),
+            &[],
+            Address::from_low_u64_be(0xde910e5),
+            H256::zero(),
+            VmRunOptions::default(),
+        );
+
+        assert!(result.is_err());
+        assert!(environment.contracts.is_empty());
+        assert_eq!(environment.storage, storage);
+    }
+
+    #[test]
+    fn immutables_are_decoded_from_the_constructor_returndata() {
+        let returndata = ethabi::encode(&[ethabi::Token::Array(vec![
+            ethabi::Token::Tuple(vec![
+                ethabi::Token::Uint(U256::from(3u64)),
+                ethabi::Token::FixedBytes(vec![0x11; 32]),
+            ]),
+            ethabi::Token::Tuple(vec![
+                ethabi::Token::Uint(U256::from(9u64)),
+                ethabi::Token::FixedBytes(vec![0x22; 32]),
+            ]),
+        ])]);
+
+        assert_eq!(
+            decode_immutables(&returndata).unwrap(),
+            vec![
+                (U256::from(3u64), H256::repeat_byte(0x11)),
+                (U256::from(9u64), H256::repeat_byte(0x22)),
+            ]
+        );
+        assert!(decode_immutables(&[]).unwrap().is_empty());
+        assert!(decode_immutables(&[1, 2, 3]).is_err());
+    }
+}
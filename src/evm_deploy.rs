@@ -1,9 +1,8 @@
 use zk_evm::{
-    ethereum_types::{Address, H160, H256, U256},
-    reference_impls::{decommitter::SimpleDecommitter, event_sink::InMemoryEventSink},
+    ethereum_types::{H256, U256},
+    reference_impls::event_sink::InMemoryEventSink,
     testing::storage::InMemoryStorage,
     vm_state::VmState,
-    zk_evm_abstractions::precompiles::DefaultPrecompilesProcessor,
     zkevm_opcode_defs::{
         decoding::VmEncodingMode, BlobSha256Format, FatPointer, VersionedHashLen32,
         CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER,
@@ -11,7 +10,11 @@ use zk_evm::{
 };
 
 use crate::{
-    hashmap_based_memory::SimpleHashmapMemory, simple_witness_tracer::MemoryLogWitnessTracer,
+    decommitter::TesterDecommitter,
+    precompiles::TesterPrecompilesProcessor,
+    simple_witness_tracer::MemoryLogWitnessTracer,
+    system_contracts::{CONTRACT_DEPLOYER_ADDRESS, KNOWN_CODES_STORAGE_ADDRESS},
+    tester_memory::{ByteAddressableMemory, TesterMemory},
 };
 
 pub fn publish_evm_bytecode_interface() -> ethabi::Contract {
@@ -36,25 +39,13 @@ pub fn publish_evm_bytecode_interface() -> ethabi::Contract {
     serde_json::from_value(known_code_storage_abi).unwrap()
 }
 
-// In zk_evm@1.5.0 the "deployer address" constant is incorrect and it points to the account code storage.
-// so we duplicate those here.
-const CONTRACT_DEPLOYER_ADDRESS: Address = H160([
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x80, 0x06,
-]);
-
-const KNOWN_CODES_STORAGE_ADDRESS: Address = H160([
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x80, 0x04,
-]);
-
-pub(crate) fn record_deployed_evm_bytecode<const B: bool, const N: usize, E: VmEncodingMode<N>>(
+pub(crate) fn record_deployed_evm_bytecode<const N: usize, E: VmEncodingMode<N>>(
     state: &mut VmState<
         InMemoryStorage,
-        SimpleHashmapMemory,
+        TesterMemory,
         InMemoryEventSink,
-        DefaultPrecompilesProcessor<B>,
-        SimpleDecommitter<B>,
+        TesterPrecompilesProcessor,
+        TesterDecommitter,
         MemoryLogWitnessTracer,
         N,
         E,
@@ -113,8 +104,13 @@ pub(crate) fn record_deployed_evm_bytecode<const B: bool, const N: usize, E: VmE
 
     let published_bytecode = call_params[0].clone().into_bytes().unwrap();
 
-    let hash = hash_evm_bytecode(&published_bytecode);
-    let as_words = bytes_to_be_words(published_bytecode);
+    let (Ok(hash), Ok(as_words)) = (
+        hash_evm_bytecode(&published_bytecode),
+        evm_bytecode_to_words(&published_bytecode),
+    ) else {
+        // Too long to be published
+        return;
+    };
 
     let (_, normalized) = BlobSha256Format::normalize_for_decommitment(hash.as_fixed_bytes());
     if state
@@ -132,107 +128,210 @@ pub fn h256_to_u256(num: H256) -> U256 {
     U256::from_big_endian(num.as_bytes())
 }
 
-pub(crate) fn hash_evm_bytecode(bytecode: &[u8]) -> H256 {
+/// The length of the EVM bytecode is stored in the versioned hash as `u16`.
+pub const MAX_EVM_BYTECODE_LENGTH: usize = u16::MAX as usize;
+
+fn check_evm_bytecode_length(length: usize) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        length <= MAX_EVM_BYTECODE_LENGTH,
+        "EVM bytecode length {} exceeds the maximum of {}",
+        length,
+        MAX_EVM_BYTECODE_LENGTH
+    );
+
+    Ok(())
+}
+
+/// The number of words in the SHA256 blob of the EVM bytecode, which is always odd.
+fn evm_bytecode_length_in_words(length: usize) -> usize {
+    let words = length.div_ceil(32);
+    if words % 2 == 0 {
+        words + 1
+    } else {
+        words
+    }
+}
+
+///
+/// Pads the EVM bytecode with zeroes to an odd number of words, which is the layout of the
+/// SHA256 blob the decommitter serves (see `BlobSha256Format::get_len_in_bytes32_words`).
+///
+pub fn pad_evm_bytecode(bytecode: &[u8]) -> anyhow::Result<Vec<u8>> {
+    check_evm_bytecode_length(bytecode.len())?;
+
+    let mut padded = bytecode.to_vec();
+    padded.resize(evm_bytecode_length_in_words(bytecode.len()) * 32, 0);
+
+    Ok(padded)
+}
+
+///
+/// The versioned hash of the EVM bytecode: the SHA256 of the padded bytecode, with the version
+/// byte and the unpadded length in bytes in place of the first 4 bytes.
+///
+pub fn hash_evm_bytecode(bytecode: &[u8]) -> anyhow::Result<H256> {
     use sha2::{Digest, Sha256};
+
+    let padded = pad_evm_bytecode(bytecode)?;
     let mut hasher = Sha256::new();
-    let len = bytecode.len() as u16;
-    hasher.update(bytecode);
+    hasher.update(&padded);
     let result = hasher.finalize();
 
     let mut output = [0u8; 32];
-    output[..].copy_from_slice(result.as_slice());
+    output[..].copy_from_slice(&result[..]);
     output[0] = BlobSha256Format::VERSION_BYTE;
     output[1] = 0;
-    output[2..4].copy_from_slice(&len.to_be_bytes());
+    output[2..4].copy_from_slice(&(bytecode.len() as u16).to_be_bytes());
+
+    Ok(H256(output))
+}
+
+/// The unpadded length of the EVM bytecode with the versioned hash.
+pub fn evm_bytecode_length(hash: H256) -> anyhow::Result<usize> {
+    let hash = hash.as_bytes();
+    anyhow::ensure!(
+        hash[0] == BlobSha256Format::VERSION_BYTE,
+        "Hash version byte {} is not the EVM blob one {}",
+        hash[0],
+        BlobSha256Format::VERSION_BYTE
+    );
+
+    Ok(u16::from_be_bytes([hash[2], hash[3]]) as usize)
+}
 
-    H256(output)
+/// The padded EVM bytecode as the big-endian words of the blob.
+pub fn evm_bytecode_to_words(bytecode: &[u8]) -> anyhow::Result<Vec<U256>> {
+    Ok(pad_evm_bytecode(bytecode)?
+        .chunks(32)
+        .map(U256::from_big_endian)
+        .collect())
 }
 
-fn bytes_to_be_words(vec: Vec<u8>) -> Vec<U256> {
-    assert!(vec.len() % 32 == 0, "Invalid bytecode length");
+///
+/// Restores the EVM bytecode from the blob words, checking them against the versioned hash.
+///
+pub fn evm_bytecode_from_words(words: &[U256], hash: H256) -> anyhow::Result<Vec<u8>> {
+    let length = evm_bytecode_length(hash)?;
+    anyhow::ensure!(
+        words.len() == evm_bytecode_length_in_words(length),
+        "{} words do not match the EVM bytecode length {}",
+        words.len(),
+        length
+    );
 
-    vec.chunks(32).map(U256::from_big_endian).collect()
+    let mut bytecode = Vec::with_capacity(words.len() * 32);
+    for word in words.iter() {
+        let mut buffer = [0u8; 32];
+        word.to_big_endian(&mut buffer);
+        bytecode.extend(buffer);
+    }
+    anyhow::ensure!(
+        bytecode[length..].iter().all(|byte| *byte == 0),
+        "EVM bytecode padding is not zero"
+    );
+    bytecode.truncate(length);
+
+    let expected = hash_evm_bytecode(&bytecode)?;
+    anyhow::ensure!(
+        expected == hash,
+        "EVM bytecode hash {:?} does not match the expected {:?}",
+        expected,
+        hash
+    );
+
+    Ok(bytecode)
 }
 
 /// Reads the memory slice represented by the fat pointer.
 /// Note, that the fat pointer must point to the accessible memory (i.e. not cleared up yet).
-pub(crate) fn read_pointer(memory: &SimpleHashmapMemory, pointer: FatPointer) -> Vec<u8> {
-    let FatPointer {
-        offset,
-        length,
-        start,
-        memory_page,
-    } = pointer;
-
-    // The actual bounds of the returndata ptr is [start+offset..start+length]
-    let mem_region_start = start + offset;
-    let mem_region_length = length - offset;
+pub(crate) fn read_pointer(memory: &TesterMemory, pointer: FatPointer) -> Vec<u8> {
+    memory.read_fat_pointer(pointer)
+}
 
-    read_unaligned_bytes(memory, memory_page, mem_region_start, mem_region_length)
+pub fn read_unaligned_bytes(memory: &TesterMemory, page: u32, start: u32, length: u32) -> Vec<u8> {
+    memory.read_bytes(page, start, length)
 }
 
-// This method should be used with relatively small lengths, since
-// we don't heavily optimize here for cases with long lengths
-pub fn read_unaligned_bytes(
-    memory: &SimpleHashmapMemory,
-    page: u32,
-    start: u32,
-    length: u32,
-) -> Vec<u8> {
-    if length == 0 {
-        return vec![];
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytecode(length: usize) -> Vec<u8> {
+        (0..length).map(|index| (index % 251) as u8 + 1).collect()
     }
 
-    let end = start + length - 1;
-
-    let mut current_word = start / 32;
-    let mut result = vec![];
-    while current_word * 32 <= end {
-        let word_value = memory.read_slot(page, current_word).value;
-        let word_value = {
-            let mut bytes: Vec<u8> = vec![0u8; 32];
-            word_value.to_big_endian(&mut bytes);
-            bytes
-        };
-
-        result.extend(extract_needed_bytes_from_word(
-            word_value,
-            current_word as usize,
-            start as usize,
-            end as usize,
-        ));
-
-        current_word += 1;
+    #[test]
+    fn evm_bytecode_round_trips_through_words_and_hash() {
+        for length in [
+            0,
+            1,
+            31,
+            32,
+            33,
+            63,
+            64,
+            65,
+            96,
+            100,
+            MAX_EVM_BYTECODE_LENGTH,
+        ] {
+            let raw = bytecode(length);
+            let hash = hash_evm_bytecode(&raw).unwrap();
+            let expected_words =
+                BlobSha256Format::get_len_in_bytes32_words(hash.as_fixed_bytes()) as usize;
+            assert_eq!(expected_words % 2, 1);
+
+            let padded = pad_evm_bytecode(&raw).unwrap();
+            assert_eq!(padded.len(), expected_words * 32);
+            assert_eq!(&padded[..length], raw.as_slice());
+            assert!(padded[length..].iter().all(|byte| *byte == 0));
+
+            let words = evm_bytecode_to_words(&raw).unwrap();
+            assert_eq!(words.len(), expected_words);
+
+            assert_eq!(evm_bytecode_length(hash).unwrap(), length);
+            assert_eq!(evm_bytecode_from_words(&words, hash).unwrap(), raw);
+        }
     }
 
-    assert_eq!(result.len(), length as usize);
+    #[test]
+    fn evm_bytecode_hash_covers_the_padded_preimage() {
+        use sha2::{Digest, Sha256};
 
-    result
-}
+        for length in [0, 32, 40, 64] {
+            let raw = bytecode(length);
+            let hash = hash_evm_bytecode(&raw).unwrap();
 
-// It is expected that there is some intersection between `[word_number*32..word_number*32+31]` and `[start, end]`
-fn extract_needed_bytes_from_word(
-    word_value: Vec<u8>,
-    word_number: usize,
-    start: usize,
-    end: usize,
-) -> Vec<u8> {
-    let word_start = word_number * 32;
-    let word_end = word_start + 31; // Note, that at `word_start + 32` a new word already starts
-
-    let intersection_left = std::cmp::max(word_start, start);
-    let intersection_right = std::cmp::min(word_end, end);
-
-    if intersection_right < intersection_left {
-        vec![]
-    } else {
-        let start_bytes = intersection_left - word_start;
-        let to_take = intersection_right - intersection_left + 1;
-
-        word_value
-            .into_iter()
-            .skip(start_bytes)
-            .take(to_take)
-            .collect()
+            let digest = Sha256::digest(pad_evm_bytecode(&raw).unwrap());
+            assert_eq!(&hash.as_bytes()[4..], &digest[4..]);
+        }
+    }
+
+    #[test]
+    fn evm_bytecode_words_must_have_the_padded_length() {
+        // Two words of bytecode are padded to three.
+        let raw = bytecode(64);
+        let hash = hash_evm_bytecode(&raw).unwrap();
+        let words = evm_bytecode_to_words(&raw).unwrap();
+        assert_eq!(words.len(), 3);
+
+        assert!(evm_bytecode_from_words(&words[..2], hash).is_err());
+
+        let mut extended = words.clone();
+        extended.push(U256::zero());
+        assert!(evm_bytecode_from_words(&extended, hash).is_err());
+
+        let mut dirty = words;
+        dirty[2] = U256::one();
+        assert!(evm_bytecode_from_words(&dirty, hash).is_err());
+    }
+
+    #[test]
+    fn evm_bytecode_longer_than_u16_is_rejected() {
+        let raw = bytecode(MAX_EVM_BYTECODE_LENGTH + 1);
+
+        assert!(pad_evm_bytecode(&raw).is_err());
+        assert!(hash_evm_bytecode(&raw).is_err());
+        assert!(evm_bytecode_to_words(&raw).is_err());
     }
 }
@@ -0,0 +1,900 @@
+use crate::call_tree::CallTreeTracer;
+use crate::debug_info::ExecutionLocation;
+use crate::page_lifecycle::PageLifecycleTracer;
+use crate::panic_info::{PanicInfo, PanicReason};
+use crate::tester_memory::TesterMemory;
+use crate::{Address, U256};
+use zk_evm::opcodes::execution::far_call::FarCallExceptionFlags;
+use zk_evm::opcodes::execution::uma::UMAExceptionFlags;
+use zk_evm::testing::storage::InMemoryStorage;
+use zk_evm::tracing::{
+    AfterDecodingData, AfterExecutionData, BeforeExecutionData, Tracer, VmLocalStateData,
+};
+use zk_evm::vm_state::{
+    address_is_kernel, get_stipend_and_extra_cost, CallStackEntry, ErrorFlags, PrimitiveValue,
+};
+use zk_evm::zkevm_opcode_defs::decoding::{EncodingModeProduction, VmEncodingMode};
+use zk_evm::zkevm_opcode_defs::system_params::DEPLOYER_SYSTEM_CONTRACT_ADDRESS;
+use zk_evm::zkevm_opcode_defs::{
+    erase_fat_pointer_metadata, BlobSha256Format, ContractCodeSha256Format, FarCallABI,
+    FarCallForwardPageType, FatPointer, FatPointerValidationException, Opcode, PtrOpcode,
+    RetOpcode, UMAOpcode, VersionedHashLen32, FAR_CALL_SHARD_FLAG_IDX, MAX_OFFSET_FOR_ADD_SUB,
+    MAX_OFFSET_TO_DEREF, MEMORY_GROWTH_ERGS_PER_BYTE,
+};
+
+///
+/// The location where a revert or panic was raised, tracked while it propagates
+/// to the outer frames.
+///
+#[derive(Debug, Clone)]
+pub(crate) struct FailureOrigin {
+    pub(crate) location: ExecutionLocation,
+    /// The callstack depth of the frame that failed.
+    pub(crate) frame_depth: usize,
+    /// The callstack depth the failure has propagated to so far.
+    pub(crate) depth: usize,
+    /// Set if the failure is a panic.
+    pub(crate) panic_reason: Option<PanicReason>,
+}
+
+///
+/// The tracer used by the runner to collect the execution diagnostics.
+///
+#[derive(Debug, Default, Clone)]
+pub struct ExecutionTracer {
+    pub(crate) failure_origin: Option<FailureOrigin>,
+    pub(crate) last_return: Option<ExecutionLocation>,
+    /// The cause of the panic that the VM is going to execute next.
+    pending_panic: Option<(PanicReason, ExecutionLocation)>,
+    /// The far call that has failed on the code of the callee or on the ergs to decommit it.
+    pending_far_call: Option<FarCallTarget>,
+    current_location: Option<ExecutionLocation>,
+    current_inputs: Option<ExecutionInputs>,
+    pub(crate) call_tree_tracer: CallTreeTracer,
+    pub(crate) page_lifecycle_tracer: Option<PageLifecycleTracer>,
+}
+
+impl ExecutionTracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_near_calls_traced(mut self, trace_near_calls: bool) -> Self {
+        self.call_tree_tracer.trace_near_calls = trace_near_calls;
+        self
+    }
+
+    pub fn with_page_lifecycle_tracked(mut self, track_page_lifecycle: bool) -> Self {
+        self.page_lifecycle_tracer = track_page_lifecycle.then(PageLifecycleTracer::new);
+        self
+    }
+
+    /// The location the final result of the execution originates from.
+    pub fn result_location(&self, is_failure: bool) -> Option<ExecutionLocation> {
+        if is_failure {
+            self.failure_origin
+                .as_ref()
+                .map(|origin| origin.location.clone())
+        } else {
+            self.last_return.clone()
+        }
+    }
+
+    /// The origin of the panic the execution ended with.
+    pub fn panic_info(&self) -> Option<PanicInfo> {
+        let origin = self.failure_origin.as_ref()?;
+
+        Some(PanicInfo {
+            reason: origin.panic_reason.unwrap_or(PanicReason::Unknown),
+            location: origin.location.clone(),
+            frame_depth: origin.frame_depth,
+        })
+    }
+
+    ///
+    /// Tells whether the far call that has just failed could not call the code of the callee,
+    /// or could not pay for decommitting it. It depends on the code hash in `storage`, which the
+    /// tracer does not see, so it must be called after every cycle.
+    ///
+    pub(crate) fn resolve_far_call_panic(&mut self, storage: &InMemoryStorage) {
+        let Some(target) = self.pending_far_call.take() else {
+            return;
+        };
+        let reason = if target.can_call_code(storage) {
+            PanicReason::OutOfErgs
+        } else {
+            PanicReason::InvalidCallTarget
+        };
+        if let Some((pending_reason, _)) = self.pending_panic.as_mut() {
+            *pending_reason = reason;
+        }
+    }
+
+    fn record_failure(
+        &mut self,
+        location: ExecutionLocation,
+        depth: usize,
+        panic_reason: Option<PanicReason>,
+        is_explicit: bool,
+    ) {
+        match self.failure_origin.as_mut() {
+            // the failure of a deeper frame is being propagated. A panic is never a propagated revert
+            Some(origin)
+                if is_explicit
+                    && origin.depth > depth
+                    && (panic_reason.is_none() || origin.panic_reason.is_some()) =>
+            {
+                origin.depth = depth
+            }
+            _ => {
+                self.failure_origin = Some(FailureOrigin {
+                    location,
+                    frame_depth: depth,
+                    depth,
+                    panic_reason,
+                });
+            }
+        }
+    }
+}
+
+fn panic_reason_from_error_flags(flags: ErrorFlags, opcode: &Opcode) -> PanicReason {
+    if flags.contains(ErrorFlags::INVALID_OPCODE) {
+        PanicReason::InvalidOpcode
+    } else if flags.contains(ErrorFlags::PRIVILAGED_ACCESS_NOT_FROM_KERNEL) {
+        PanicReason::KernelModeViolation
+    } else if flags.contains(ErrorFlags::WRITE_IN_STATIC_CONTEXT) {
+        PanicReason::StaticViolation
+    } else if flags.contains(ErrorFlags::CALLSTACK_IS_FULL) {
+        match opcode {
+            Opcode::NearCall(_) => PanicReason::StackOverflow,
+            _ => PanicReason::CallDepth,
+        }
+    } else if flags.contains(ErrorFlags::NOT_ENOUGH_ERGS) {
+        PanicReason::OutOfErgs
+    } else {
+        PanicReason::Unknown
+    }
+}
+
+///
+/// The operands of the instruction being executed and its frame before the execution, enough to
+/// reproduce the checks of the instructions that can fail during the execution.
+///
+#[derive(Debug, Clone, Copy)]
+struct ExecutionInputs {
+    src0: PrimitiveValue,
+    src1: PrimitiveValue,
+    flags: [bool; 2],
+    frame: CallStackEntry<8, EncodingModeProduction>,
+}
+
+// The checks below are copied from `uma_opcode_apply`, `ptr_opcode_apply` and
+// `far_call_opcode_apply` of zk_evm 0.150.6, which does not expose them, and must be updated
+// together with the zk_evm version. They only tell why the VM has failed the instruction, and in
+// the tests the tracer asserts they agree with the VM on every instruction they cover.
+
+/// The callee of a failed far call, whose code hash tells why the call has failed.
+#[derive(Debug, Clone, Copy)]
+struct FarCallTarget {
+    address: Address,
+    shard_id: u8,
+    constructor_call: bool,
+}
+
+impl FarCallTarget {
+    /// Whether the VM would call the code at the address, possibly masked into the default account.
+    fn can_call_code(&self, storage: &InMemoryStorage) -> bool {
+        let code_hash = storage.inner[self.shard_id as usize]
+            .get(&*DEPLOYER_SYSTEM_CONTRACT_ADDRESS)
+            .and_then(|slots| slots.get(&U256::from_big_endian(self.address.as_bytes())))
+            .copied()
+            .unwrap_or_default();
+        let mut buffer = [0u8; 32];
+        code_hash.to_big_endian(&mut buffer);
+
+        let can_call_as_is = if ContractCodeSha256Format::is_valid(&buffer) {
+            if self.constructor_call {
+                ContractCodeSha256Format::is_in_construction_if_valid(&buffer)
+            } else {
+                ContractCodeSha256Format::is_code_at_rest_if_valid(&buffer)
+            }
+        } else if BlobSha256Format::is_valid(&buffer) {
+            if self.constructor_call {
+                BlobSha256Format::is_in_construction_if_valid(&buffer)
+            } else {
+                BlobSha256Format::is_code_at_rest_if_valid(&buffer)
+            }
+        } else {
+            false
+        };
+        // the code that is not callable as is gets masked into the default account, unless it
+        // belongs to a system contract
+        let is_known_format = code_hash.is_zero()
+            || ContractCodeSha256Format::is_valid(&buffer)
+            || BlobSha256Format::is_valid(&buffer);
+
+        can_call_as_is || (is_known_format && !address_is_kernel(&self.address))
+    }
+}
+
+/// The exceptions `uma_opcode_apply` raises for the instruction.
+fn uma_exception_flags(opcode: UMAOpcode, inputs: &ExecutionInputs) -> UMAExceptionFlags {
+    let mut exceptions = UMAExceptionFlags::empty();
+    let is_ptr_read = opcode == UMAOpcode::FatPointerRead;
+    if is_ptr_read && !inputs.src0.is_pointer {
+        exceptions.set(UMAExceptionFlags::INPUT_IS_NOT_POINTER_WHEN_EXPECTED, true);
+    }
+    if !is_ptr_read && inputs.src0.value > MAX_OFFSET_TO_DEREF {
+        exceptions.set(UMAExceptionFlags::DEREF_BEYOND_HEAP_RANGE, true);
+    }
+
+    let fat_ptr = FatPointer::from_u256(inputs.src0.value);
+    let (incremented_offset, is_overflow) = fat_ptr.offset.overflowing_add(32);
+    if is_overflow {
+        exceptions.set(UMAExceptionFlags::OVERFLOW_ON_INCREMENT, true);
+    }
+
+    let current_bound = match opcode {
+        UMAOpcode::HeapRead | UMAOpcode::HeapWrite => inputs.frame.heap_bound,
+        UMAOpcode::AuxHeapRead | UMAOpcode::AuxHeapWrite => inputs.frame.aux_heap_bound,
+        // the other memories never grow
+        _ => u32::MAX,
+    };
+    let cost_of_memory_growth = if exceptions.contains(UMAExceptionFlags::DEREF_BEYOND_HEAP_RANGE) {
+        u32::MAX
+    } else {
+        incremented_offset
+            .saturating_sub(current_bound)
+            .wrapping_mul(MEMORY_GROWTH_ERGS_PER_BYTE)
+    };
+    if inputs.frame.ergs_remaining < cost_of_memory_growth {
+        exceptions.set(UMAExceptionFlags::NOT_ENOUGH_ERGS_TO_GROW_MEMORY, true);
+    }
+
+    exceptions
+}
+
+fn panic_reason_from_uma_exceptions(exceptions: UMAExceptionFlags) -> PanicReason {
+    if exceptions.contains(UMAExceptionFlags::INPUT_IS_NOT_POINTER_WHEN_EXPECTED) {
+        PanicReason::NotAPointer
+    } else if exceptions.contains(UMAExceptionFlags::DEREF_BEYOND_HEAP_RANGE) {
+        PanicReason::HeapBound
+    } else if exceptions.contains(UMAExceptionFlags::OVERFLOW_ON_INCREMENT) {
+        PanicReason::PointerOverflow
+    } else if exceptions.contains(UMAExceptionFlags::NOT_ENOUGH_ERGS_TO_GROW_MEMORY) {
+        PanicReason::OutOfErgs
+    } else {
+        PanicReason::Unknown
+    }
+}
+
+/// The reason `ptr_opcode_apply` panics for the instruction, if it does.
+fn ptr_panic_reason(opcode: PtrOpcode, inputs: &ExecutionInputs) -> Option<PanicReason> {
+    if !inputs.src0.is_pointer {
+        return Some(PanicReason::NotAPointer);
+    }
+    if inputs.src1.is_pointer {
+        // only possible in the kernel mode, as the pointer is erased otherwise
+        return Some(PanicReason::UnexpectedPointer);
+    }
+
+    let fat_ptr = FatPointer::from_u256(inputs.src0.value);
+    let offset = inputs.src1.value.low_u32();
+    match opcode {
+        PtrOpcode::Add | PtrOpcode::Sub if inputs.src1.value >= MAX_OFFSET_FOR_ADD_SUB => {
+            Some(PanicReason::PointerOverflow)
+        }
+        PtrOpcode::Add => fat_ptr
+            .offset
+            .checked_add(offset)
+            .is_none()
+            .then_some(PanicReason::PointerOverflow),
+        PtrOpcode::Sub => fat_ptr
+            .offset
+            .checked_sub(offset)
+            .is_none()
+            .then_some(PanicReason::PointerOverflow),
+        // the packed value must leave the pointer part of the word empty
+        PtrOpcode::Pack => {
+            (inputs.src1.value.low_u128() != 0).then_some(PanicReason::MalformedPointer)
+        }
+        PtrOpcode::Shrink => fat_ptr
+            .length
+            .checked_sub(offset)
+            .is_none()
+            .then_some(PanicReason::PointerOverflow),
+    }
+}
+
+///
+/// The exceptions `far_call_opcode_apply` raises for the instruction, except for the ones that
+/// depend on the code hash of the callee.
+///
+fn far_call_exception_flags(inputs: &ExecutionInputs) -> FarCallExceptionFlags {
+    let mut exceptions = FarCallExceptionFlags::empty();
+    let far_call_abi = FarCallABI::from_u256(inputs.src0.value);
+    let is_forwarding_pointer =
+        far_call_abi.forwarding_mode == FarCallForwardPageType::ForwardFatPointer;
+    if is_forwarding_pointer && !inputs.src0.is_pointer {
+        exceptions.set(
+            FarCallExceptionFlags::INPUT_IS_NOT_POINTER_WHEN_EXPECTED,
+            true,
+        );
+    }
+    if !is_forwarding_pointer && inputs.src0.is_pointer {
+        exceptions.set(
+            FarCallExceptionFlags::INPUT_IS_POINTER_WHEN_NOT_EXPECTED,
+            true,
+        );
+    }
+
+    let pointer = far_call_abi.memory_quasi_fat_pointer;
+    let pointer_validation_exceptions = pointer.validate(!is_forwarding_pointer);
+    if !pointer_validation_exceptions.is_empty() || !pointer.validate_as_slice() {
+        exceptions.set(FarCallExceptionFlags::MALFORMED_ABI_QUASI_POINTER, true);
+    }
+
+    let current_bound = match far_call_abi.forwarding_mode {
+        FarCallForwardPageType::UseHeap => inputs.frame.heap_bound,
+        FarCallForwardPageType::UseAuxHeap => inputs.frame.aux_heap_bound,
+        FarCallForwardPageType::ForwardFatPointer => u32::MAX,
+    };
+    // the slice of a failing call is masked into an empty one, unless it is out of range
+    let upper_bound = if pointer_validation_exceptions
+        .contains(FatPointerValidationException::DEREF_BEYOND_HEAP_RANGE)
+    {
+        u32::MAX
+    } else if exceptions.is_empty() {
+        pointer.start.wrapping_add(pointer.length)
+    } else {
+        0
+    };
+    let cost_of_memory_growth = upper_bound
+        .saturating_sub(current_bound)
+        .wrapping_mul(MEMORY_GROWTH_ERGS_PER_BYTE);
+    let Some(remaining_ergs) = inputs
+        .frame
+        .ergs_remaining
+        .checked_sub(cost_of_memory_growth)
+    else {
+        exceptions.set(FarCallExceptionFlags::NOT_ENOUGH_ERGS_TO_GROW_MEMORY, true);
+        return exceptions;
+    };
+
+    let called_address = zk_evm::u256_to_address_unchecked(&inputs.src1.value);
+    let to_system = far_call_abi.to_system && address_is_kernel(&called_address);
+    let (_, extra_ergs_from_caller_to_callee) =
+        get_stipend_and_extra_cost(&called_address, to_system);
+    if remaining_ergs < extra_ergs_from_caller_to_callee {
+        exceptions.set(
+            FarCallExceptionFlags::NOT_ENOUGH_ERGS_FOR_EXTRA_FAR_CALL_COSTS,
+            true,
+        );
+    }
+
+    exceptions
+}
+
+fn panic_reason_from_far_call_exceptions(exceptions: FarCallExceptionFlags) -> PanicReason {
+    if exceptions.contains(FarCallExceptionFlags::INPUT_IS_NOT_POINTER_WHEN_EXPECTED) {
+        PanicReason::NotAPointer
+    } else if exceptions.contains(FarCallExceptionFlags::INPUT_IS_POINTER_WHEN_NOT_EXPECTED) {
+        PanicReason::UnexpectedPointer
+    } else if exceptions.contains(FarCallExceptionFlags::MALFORMED_ABI_QUASI_POINTER) {
+        PanicReason::MalformedPointer
+    } else if exceptions.intersects(
+        FarCallExceptionFlags::NOT_ENOUGH_ERGS_TO_GROW_MEMORY
+            | FarCallExceptionFlags::NOT_ENOUGH_ERGS_FOR_EXTRA_FAR_CALL_COSTS,
+    ) {
+        PanicReason::OutOfErgs
+    } else {
+        PanicReason::Unknown
+    }
+}
+
+impl Tracer<8, EncodingModeProduction> for ExecutionTracer {
+    const CALL_AFTER_DECODING: bool = true;
+    const CALL_BEFORE_EXECUTION: bool = true;
+    const CALL_AFTER_EXECUTION: bool = true;
+
+    type SupportedMemory = TesterMemory;
+
+    fn before_decoding(
+        &mut self,
+        _state: VmLocalStateData<'_, 8, EncodingModeProduction>,
+        _memory: &Self::SupportedMemory,
+    ) {
+    }
+
+    fn after_decoding(
+        &mut self,
+        state: VmLocalStateData<'_, 8, EncodingModeProduction>,
+        data: AfterDecodingData<8, EncodingModeProduction>,
+        _memory: &Self::SupportedMemory,
+    ) {
+        // a pending exception is the panic of the previous instruction, recorded after its execution
+        if data.error_flags_accumulated.is_empty() || state.vm_local_state.pending_exception {
+            return;
+        }
+
+        // the opcode is masked into a panic and will be executed as `ret.panic`
+        let current_frame = state.vm_local_state.callstack.get_current_stack();
+        let (decoded, _) =
+            <EncodingModeProduction as VmEncodingMode<8>>::parse_preliminary_variant_and_absolute_number(
+                data.raw_opcode_unmasked,
+            );
+        self.pending_panic = Some((
+            panic_reason_from_error_flags(data.error_flags_accumulated, &decoded.variant.opcode),
+            ExecutionLocation::new(
+                current_frame.this_address,
+                current_frame.code_address,
+                current_frame.pc,
+            ),
+        ));
+    }
+
+    fn before_execution(
+        &mut self,
+        state: VmLocalStateData<'_, 8, EncodingModeProduction>,
+        data: BeforeExecutionData<8, EncodingModeProduction>,
+        _memory: &Self::SupportedMemory,
+    ) {
+        let callstack = &state.vm_local_state.callstack;
+        let current_frame = callstack.get_current_stack();
+        let location = ExecutionLocation::new(
+            current_frame.this_address,
+            current_frame.code_address,
+            current_frame.pc,
+        );
+        self.call_tree_tracer.before_execution(
+            state.vm_local_state,
+            &data.opcode.variant.opcode,
+            data.src0_value,
+            &location,
+        );
+        if let Some(page_lifecycle_tracer) = self.page_lifecycle_tracer.as_mut() {
+            page_lifecycle_tracer.before_execution(
+                state.vm_local_state,
+                &data.opcode.variant.opcode,
+                data.src0_value,
+                &location,
+            );
+        }
+        self.current_location = Some(location.clone());
+        // the VM erases the pointer metadata of the operands right after this call
+        let opcode = data.opcode.variant.opcode;
+        let (mut src0, mut src1) = (data.src0_value, data.src1_value);
+        if !current_frame.is_kernel_mode() {
+            for (operand, can_be_pointer) in [
+                (&mut src0, opcode.src0_can_be_pointer()),
+                (&mut src1, opcode.src1_can_be_pointer()),
+            ] {
+                if operand.is_pointer && !can_be_pointer {
+                    erase_fat_pointer_metadata(&mut operand.value);
+                    operand.is_pointer = false;
+                }
+            }
+        }
+        self.current_inputs = Some(ExecutionInputs {
+            src0,
+            src1,
+            flags: data.opcode.variant.flags,
+            frame: *current_frame,
+        });
+
+        match data.opcode.variant.opcode {
+            Opcode::FarCall(_) => {
+                // a new call starts, so whatever failed before was handled
+                self.failure_origin = None;
+            }
+            Opcode::Ret(RetOpcode::Ok) => {
+                if !current_frame.is_local_frame {
+                    self.failure_origin = None;
+                }
+                self.last_return = Some(location);
+            }
+            Opcode::Ret(RetOpcode::Revert) => {
+                self.record_failure(location, callstack.depth(), None, true);
+            }
+            Opcode::Ret(RetOpcode::Panic) => match self.pending_panic.take() {
+                Some((reason, origin)) => {
+                    self.record_failure(origin, callstack.depth(), Some(reason), false);
+                }
+                None => {
+                    self.record_failure(
+                        location,
+                        callstack.depth(),
+                        Some(PanicReason::ExplicitPanic),
+                        true,
+                    );
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn after_execution(
+        &mut self,
+        state: VmLocalStateData<'_, 8, EncodingModeProduction>,
+        data: AfterExecutionData<8, EncodingModeProduction>,
+        memory: &Self::SupportedMemory,
+    ) {
+        self.call_tree_tracer
+            .after_execution(state.vm_local_state, memory);
+        if let Some(page_lifecycle_tracer) = self.page_lifecycle_tracer.as_mut() {
+            page_lifecycle_tracer.after_execution(state.vm_local_state);
+        }
+
+        let is_failed = state.vm_local_state.pending_exception;
+        // the copied checks are only needed to tell why the instruction has failed, unless they
+        // are compared to the VM
+        if !is_failed && !cfg!(test) {
+            return;
+        }
+        let Some(inputs) = self.current_inputs else {
+            return;
+        };
+
+        // the reason the copied checks expect the instruction to fail with
+        let expected = match data.opcode.variant.opcode {
+            Opcode::UMA(opcode) => {
+                let exceptions = uma_exception_flags(opcode, &inputs);
+                (!exceptions.is_empty()).then(|| panic_reason_from_uma_exceptions(exceptions))
+            }
+            Opcode::Ptr(opcode) => ptr_panic_reason(opcode, &inputs),
+            Opcode::FarCall(_) => {
+                let exceptions = far_call_exception_flags(&inputs);
+                (!exceptions.is_empty()).then(|| panic_reason_from_far_call_exceptions(exceptions))
+            }
+            _ => None,
+        };
+        #[cfg(test)]
+        {
+            // a far call can also fail on the code of the callee, which the checks do not see
+            let is_checked = matches!(
+                data.opcode.variant.opcode,
+                Opcode::UMA(_) | Opcode::Ptr(_) | Opcode::FarCall(_)
+            );
+            let agrees = match data.opcode.variant.opcode {
+                Opcode::FarCall(_) => is_failed || expected.is_none(),
+                _ => !is_checked || is_failed == expected.is_some(),
+            };
+            assert!(
+                agrees,
+                "the checks copied from zk_evm 0.150.6 expect {:?} from {:?}, while the VM has {}",
+                expected,
+                data.opcode.variant.opcode,
+                if is_failed { "failed" } else { "not failed" },
+            );
+        }
+        if !is_failed {
+            return;
+        }
+
+        // the instruction failed during the execution, so the next cycle is a panic
+        let reason = match (expected, data.opcode.variant.opcode) {
+            (Some(reason), _) => reason,
+            (None, Opcode::FarCall(_)) => {
+                // resolved with the code hash by `resolve_far_call_panic`
+                let far_call_abi = FarCallABI::from_u256(inputs.src0.value);
+                self.pending_far_call = Some(FarCallTarget {
+                    address: zk_evm::u256_to_address_unchecked(&inputs.src1.value),
+                    shard_id: if inputs.flags[FAR_CALL_SHARD_FLAG_IDX] {
+                        far_call_abi.shard_id
+                    } else {
+                        inputs.frame.this_shard_id
+                    },
+                    constructor_call: far_call_abi.constructor_call
+                        && inputs.frame.is_kernel_mode(),
+                });
+                PanicReason::Unknown
+            }
+            // the VM has failed for a reason the copied checks do not know of
+            (None, _) => PanicReason::Unknown,
+        };
+        if let Some(location) = self.current_location.clone() {
+            self.pending_panic = Some((reason, location));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler_tests::{VmExecutionResult, VmRunOptions};
+    use crate::test_utils::{
+        add_imm, assemble, far_call, instruction, near_call, ret_ok, ret_panic, run,
+        static_far_call, uma,
+    };
+    use std::collections::HashMap;
+    use zk_evm::zkevm_opcode_defs::{
+        AddOpcode, ContextOpcode, FarCallOpcode, ImmMemHandlerFlags, InvalidOpcode, LogOpcode,
+        NearCallOpcode, Operand, ShiftOpcode,
+    };
+
+    const CALLEE: u64 = 0x1234;
+    const CALLER: u64 = 0x10000;
+
+    /// `shl r1, r2, r1`
+    fn shl() -> u64 {
+        instruction(
+            Opcode::Shift(ShiftOpcode::Shl),
+            Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+            Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+            (1, 2, 1),
+            0,
+        )
+    }
+
+    /// `opcode r1, src1, r3`, where `r1` holds the calldata pointer.
+    fn ptr(opcode: PtrOpcode, src1: u8) -> u64 {
+        instruction(
+            Opcode::Ptr(opcode),
+            Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+            Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+            (1, src1, 3),
+            0,
+        )
+    }
+
+    fn panic_info(contracts: HashMap<Address, Vec<u8>>) -> PanicInfo {
+        let snapshot = run(
+            contracts,
+            Address::from_low_u64_be(CALLER),
+            VmRunOptions::default(),
+        );
+        match snapshot.execution_result {
+            VmExecutionResult::Panic(panic_info) => panic_info,
+            _ => panic!("the execution has not panicked"),
+        }
+    }
+
+    /// The reason and the pc of the panic of the caller running `program`.
+    fn panic_of(program: &[u64]) -> (PanicReason, u16) {
+        let panic_info = panic_info(HashMap::from([(
+            Address::from_low_u64_be(CALLER),
+            assemble(program),
+        )]));
+        assert_eq!(
+            panic_info.location.code_address,
+            Address::from_low_u64_be(CALLER)
+        );
+
+        (panic_info.reason, panic_info.location.pc)
+    }
+
+    ///
+    /// The caller that far calls `CALLEE` with `call` and propagates its panic. The exception
+    /// handler of the far call must be right after `call`.
+    ///
+    fn far_call_panic_of(call: &[u64], callee: Option<Vec<u8>>) -> PanicInfo {
+        let mut program = call.to_vec();
+        program.push(ret_panic());
+
+        let mut contracts = HashMap::from([(Address::from_low_u64_be(CALLER), assemble(&program))]);
+        if let Some(callee) = callee {
+            contracts.insert(Address::from_low_u64_be(CALLEE), callee);
+        }
+
+        panic_info(contracts)
+    }
+
+    // the failing instructions are checked by the tests of each reason
+    #[test]
+    fn copied_checks_agree_with_the_vm() {
+        let mut program = vec![
+            uma(UMAOpcode::HeapWrite, (0, 0, 0, 0)),
+            uma(UMAOpcode::HeapRead, (0, 0, 2, 0)),
+            uma(UMAOpcode::FatPointerRead, (1, 0, 2, 0)),
+            ptr(PtrOpcode::Add, 0),
+            ptr(PtrOpcode::Sub, 0),
+            ptr(PtrOpcode::Pack, 0),
+            ptr(PtrOpcode::Shrink, 0),
+        ];
+        program.extend(far_call(CALLEE as u16, 13));
+        program.extend([ret_ok(), ret_panic()]);
+
+        let snapshot = run(
+            HashMap::from([
+                (Address::from_low_u64_be(CALLER), assemble(&program)),
+                (Address::from_low_u64_be(CALLEE), assemble(&[ret_ok()])),
+            ]),
+            Address::from_low_u64_be(CALLER),
+            VmRunOptions::default(),
+        );
+
+        assert!(matches!(
+            snapshot.execution_result,
+            VmExecutionResult::Ok(_)
+        ));
+    }
+
+    #[test]
+    fn explicit_panic() {
+        assert_eq!(
+            panic_of(&[add_imm(1, 1), ret_panic()]),
+            (PanicReason::ExplicitPanic, 1)
+        );
+    }
+
+    #[test]
+    fn invalid_opcode() {
+        let invalid = instruction(
+            Opcode::Invalid(InvalidOpcode),
+            Operand::RegOnly,
+            Operand::RegOnly,
+            (0, 0, 0),
+            0,
+        );
+
+        assert_eq!(
+            panic_of(&[add_imm(1, 1), invalid]),
+            (PanicReason::InvalidOpcode, 1)
+        );
+    }
+
+    #[test]
+    fn kernel_mode_violation() {
+        let set_context_u128 = instruction(
+            Opcode::Context(ContextOpcode::SetContextU128),
+            Operand::RegOnly,
+            Operand::RegOnly,
+            (0, 0, 0),
+            0,
+        );
+
+        assert_eq!(
+            panic_of(&[set_context_u128]),
+            (PanicReason::KernelModeViolation, 0)
+        );
+    }
+
+    #[test]
+    fn static_violation() {
+        let storage_write = instruction(
+            Opcode::Log(LogOpcode::StorageWrite),
+            Operand::RegOnly,
+            Operand::RegOnly,
+            (0, 0, 0),
+            0,
+        );
+        let panic_info = far_call_panic_of(
+            &static_far_call(CALLEE as u16, 5),
+            Some(assemble(&[storage_write, ret_ok()])),
+        );
+
+        assert_eq!(panic_info.reason, PanicReason::StaticViolation);
+        assert_eq!(
+            panic_info.location.code_address,
+            Address::from_low_u64_be(CALLEE)
+        );
+        assert_eq!(panic_info.location.pc, 0);
+        assert_eq!(panic_info.frame_depth, 2);
+    }
+
+    // the callstack limit is out of reach in a test, so only the classification is checked
+    #[test]
+    fn full_callstack_reason_depends_on_the_call() {
+        assert_eq!(
+            panic_reason_from_error_flags(
+                ErrorFlags::CALLSTACK_IS_FULL,
+                &Opcode::NearCall(NearCallOpcode)
+            ),
+            PanicReason::StackOverflow
+        );
+        assert_eq!(
+            panic_reason_from_error_flags(
+                ErrorFlags::CALLSTACK_IS_FULL,
+                &Opcode::FarCall(FarCallOpcode::Normal)
+            ),
+            PanicReason::CallDepth
+        );
+    }
+
+    #[test]
+    fn heap_growth_out_of_ergs() {
+        // r1 = 0xffff0000, within the addressable range, but too expensive to grow the heap to
+        let program = [
+            add_imm(0xffff, 1),
+            add_imm(16, 2),
+            shl(),
+            uma(UMAOpcode::HeapWrite, (1, 0, 0, 0)),
+        ];
+
+        assert_eq!(panic_of(&program), (PanicReason::OutOfErgs, 3));
+    }
+
+    #[test]
+    fn heap_access_beyond_the_addressable_range() {
+        // r1 = 2^32
+        let program = [
+            add_imm(1, 1),
+            add_imm(32, 2),
+            shl(),
+            uma(UMAOpcode::HeapRead, (1, 0, 2, 0)),
+        ];
+
+        assert_eq!(panic_of(&program), (PanicReason::HeapBound, 3));
+    }
+
+    #[test]
+    fn fat_pointer_read_from_an_integer() {
+        assert_eq!(
+            panic_of(&[uma(UMAOpcode::FatPointerRead, (0, 0, 2, 0))]),
+            (PanicReason::NotAPointer, 0)
+        );
+    }
+
+    #[test]
+    fn pointer_offset_underflow() {
+        assert_eq!(
+            panic_of(&[add_imm(1, 2), ptr(PtrOpcode::Sub, 2)]),
+            (PanicReason::PointerOverflow, 1)
+        );
+    }
+
+    #[test]
+    fn far_call_with_a_pointer_instead_of_the_abi() {
+        // passes the calldata pointer in `r1` as the ABI to use the heap
+        let call = far_call(CALLEE as u16, 2);
+        let panic_info = far_call_panic_of(&call[3..], Some(assemble(&[ret_ok()])));
+
+        assert_eq!(panic_info.reason, PanicReason::UnexpectedPointer);
+        assert_eq!(
+            panic_info.location.code_address,
+            Address::from_low_u64_be(CALLER)
+        );
+        assert_eq!(panic_info.location.pc, 1);
+    }
+
+    #[test]
+    fn far_call_with_a_malformed_slice() {
+        let mut call = far_call(CALLEE as u16, 6).to_vec();
+        // r1 += 1, a non-zero offset of the heap slice to pass
+        call.insert(
+            3,
+            instruction(
+                Opcode::Add(AddOpcode::Add),
+                Operand::Full(ImmMemHandlerFlags::UseImm16Only),
+                Operand::Full(ImmMemHandlerFlags::UseRegOnly),
+                (0, 1, 1),
+                1,
+            ),
+        );
+        let panic_info = far_call_panic_of(&call, Some(assemble(&[ret_ok()])));
+
+        assert_eq!(panic_info.reason, PanicReason::MalformedPointer);
+        assert_eq!(panic_info.location.pc, 5);
+    }
+
+    #[test]
+    fn far_call_to_a_system_contract_without_code() {
+        let panic_info = far_call_panic_of(&far_call(0x8fff, 5), None);
+
+        assert_eq!(panic_info.reason, PanicReason::InvalidCallTarget);
+        assert_eq!(panic_info.location.pc, 4);
+    }
+
+    #[test]
+    fn far_call_out_of_ergs_to_decommit() {
+        // the near call gets just enough ergs to execute the far call, but not to decommit
+        let ergs = 4 * Opcode::Add(AddOpcode::Add).ergs_price()
+            + Opcode::FarCall(FarCallOpcode::Normal).ergs_price()
+            + 100;
+        let mut program = vec![add_imm(ergs as u16, 3), near_call(3, 3, 2), ret_panic()];
+        program.extend(far_call(CALLEE as u16, 8));
+        program.push(ret_panic());
+        // 4 ergs per word to decommit
+        let mut callee = assemble(&[ret_ok()]);
+        callee.resize(501 * 32, 0);
+
+        let panic_info = panic_info(HashMap::from([
+            (Address::from_low_u64_be(CALLER), assemble(&program)),
+            (Address::from_low_u64_be(CALLEE), callee),
+        ]));
+
+        assert_eq!(panic_info.reason, PanicReason::OutOfErgs);
+        assert_eq!(panic_info.location.pc, 7);
+    }
+}
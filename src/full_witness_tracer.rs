@@ -0,0 +1,411 @@
+use crate::{Address, U256};
+use zk_evm::aux_structures::{DecommittmentQuery, LogQuery, MemoryQuery};
+use zk_evm::vm_state::CallStackEntry;
+use zk_evm::witness_trace::VmWitnessTracer;
+use zk_evm::zk_evm_abstractions::vm::PrecompileCyclesWitness;
+use zk_evm::zkevm_opcode_defs::decoding::VmEncodingMode;
+use zk_evm::zkevm_opcode_defs::system_params::{
+    EVENT_AUX_BYTE, L1_MESSAGE_AUX_BYTE, PRECOMPILE_AUX_BYTE, STORAGE_AUX_BYTE,
+    TRANSIENT_STORAGE_AUX_BYTE,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecommittmentRecord {
+    pub cycle: u32,
+    pub query: DecommittmentQuery,
+    /// The number of code words written into the code page.
+    pub code_words: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrecompileCallRecord {
+    pub cycle: u32,
+    pub call_params: LogQuery,
+    pub memory_reads: Vec<MemoryQuery>,
+    pub memory_writes: Vec<MemoryQuery>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallstackAction {
+    Push {
+        this_address: Address,
+        code_address: Address,
+        base_memory_page: u32,
+        is_local_frame: bool,
+    },
+    Pop {
+        panicked: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallstackRecord {
+    pub cycle: u32,
+    pub action: CallstackAction,
+}
+
+///
+/// The witness tracer that records every query the VM reports, along with the cycle it
+/// happened at. The layout follows what the witness generator consumes, so the result
+/// can be checked for completeness without running the prover.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FullWitnessTracer {
+    pub memory_queries: Vec<(u32, MemoryQuery)>,
+    pub decommittment_queries: Vec<DecommittmentRecord>,
+    /// Storage, transient storage, event, L1 message and precompile log queries.
+    pub log_queries: Vec<(u32, LogQuery)>,
+    pub precompile_calls: Vec<PrecompileCallRecord>,
+    pub callstack_actions: Vec<CallstackRecord>,
+    /// The writes undone by the panics of their frames, in the order they are undone, with the
+    /// cycle of the panic and the `rollback` flag set.
+    pub log_rollbacks: Vec<(u32, LogQuery)>,
+    /// The indices of the writes in `log_queries` made by every frame, innermost last.
+    frame_writes: Vec<Vec<usize>>,
+}
+
+impl FullWitnessTracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn current_frame_writes(&mut self) -> &mut Vec<usize> {
+        // the entry frame is already on the callstack when the recording starts
+        if self.frame_writes.is_empty() {
+            self.frame_writes.push(vec![]);
+        }
+
+        self.frame_writes.last_mut().unwrap()
+    }
+
+    fn log_queries_by_aux_byte(&self, aux_byte: u8) -> impl Iterator<Item = &(u32, LogQuery)> {
+        self.log_queries
+            .iter()
+            .filter(move |(_, query)| query.aux_byte == aux_byte)
+    }
+
+    pub fn storage_queries(&self) -> impl Iterator<Item = &(u32, LogQuery)> {
+        self.log_queries_by_aux_byte(STORAGE_AUX_BYTE)
+    }
+
+    pub fn transient_storage_queries(&self) -> impl Iterator<Item = &(u32, LogQuery)> {
+        self.log_queries_by_aux_byte(TRANSIENT_STORAGE_AUX_BYTE)
+    }
+
+    pub fn event_queries(&self) -> impl Iterator<Item = &(u32, LogQuery)> {
+        self.log_queries_by_aux_byte(EVENT_AUX_BYTE)
+    }
+
+    pub fn l1_message_queries(&self) -> impl Iterator<Item = &(u32, LogQuery)> {
+        self.log_queries_by_aux_byte(L1_MESSAGE_AUX_BYTE)
+    }
+
+    pub fn precompile_queries(&self) -> impl Iterator<Item = &(u32, LogQuery)> {
+        self.log_queries_by_aux_byte(PRECOMPILE_AUX_BYTE)
+    }
+
+    ///
+    /// Checks that the callstack pops match the pushes and that the cycles are monotonic.
+    ///
+    /// `initial_frames` is the number of frames on the callstack when the recording started,
+    /// e.g. one for the entry frame, and `remaining_frames` is the number of frames
+    /// expected to be still there when the trace ends, e.g. zero for a run that returned from
+    /// the entry frame.
+    ///
+    pub fn check_consistency(
+        &self,
+        initial_frames: usize,
+        remaining_frames: usize,
+    ) -> anyhow::Result<()> {
+        let mut depth = initial_frames;
+        for record in self.callstack_actions.iter() {
+            match record.action {
+                CallstackAction::Push { .. } => depth += 1,
+                CallstackAction::Pop { .. } => {
+                    depth = depth.checked_sub(1).ok_or_else(|| {
+                        anyhow::anyhow!("Callstack pop without a push at cycle {}", record.cycle)
+                    })?;
+                }
+            }
+        }
+        anyhow::ensure!(
+            depth == remaining_frames,
+            "{} frames are left on the callstack, expected {}",
+            depth,
+            remaining_frames
+        );
+
+        let cycles = [
+            self.memory_queries
+                .iter()
+                .map(|(cycle, _)| *cycle)
+                .collect::<Vec<_>>(),
+            self.log_queries.iter().map(|(cycle, _)| *cycle).collect(),
+            self.decommittment_queries
+                .iter()
+                .map(|record| record.cycle)
+                .collect(),
+            self.callstack_actions
+                .iter()
+                .map(|record| record.cycle)
+                .collect(),
+        ];
+        for (kind, cycles) in ["memory", "log", "decommittment", "callstack"]
+            .iter()
+            .zip(cycles.iter())
+        {
+            if let Some(window) = cycles.windows(2).find(|window| window[0] > window[1]) {
+                anyhow::bail!(
+                    "Non-monotonic {} queries: cycle {} is followed by cycle {}",
+                    kind,
+                    window[0],
+                    window[1]
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<const N: usize, E: VmEncodingMode<N>> VmWitnessTracer<N, E> for FullWitnessTracer {
+    fn add_memory_query(&mut self, monotonic_cycle_counter: u32, memory_query: MemoryQuery) {
+        self.memory_queries
+            .push((monotonic_cycle_counter, memory_query));
+    }
+
+    fn add_log_query(&mut self, monotonic_cycle_counter: u32, log_query: LogQuery) {
+        if log_query.rw_flag {
+            let index = self.log_queries.len();
+            self.current_frame_writes().push(index);
+        }
+        self.log_queries.push((monotonic_cycle_counter, log_query));
+    }
+
+    fn execute_decommittment(
+        &mut self,
+        monotonic_cycle_counter: u32,
+        decommittment_query: DecommittmentQuery,
+        mem_witness: Vec<U256>,
+    ) {
+        self.decommittment_queries.push(DecommittmentRecord {
+            cycle: monotonic_cycle_counter,
+            query: decommittment_query,
+            code_words: mem_witness.len(),
+        });
+    }
+
+    fn add_precompile_call_result(
+        &mut self,
+        monotonic_cycle_counter: u32,
+        call_params: LogQuery,
+        mem_witness_in: Vec<MemoryQuery>,
+        memory_witness_out: Vec<MemoryQuery>,
+        _round_witness: PrecompileCyclesWitness,
+    ) {
+        self.precompile_calls.push(PrecompileCallRecord {
+            cycle: monotonic_cycle_counter,
+            call_params,
+            memory_reads: mem_witness_in,
+            memory_writes: memory_witness_out,
+        });
+    }
+
+    fn start_new_execution_context(
+        &mut self,
+        monotonic_cycle_counter: u32,
+        _previous_context: &CallStackEntry<N, E>,
+        new_context: &CallStackEntry<N, E>,
+    ) {
+        self.current_frame_writes();
+        self.frame_writes.push(vec![]);
+        self.callstack_actions.push(CallstackRecord {
+            cycle: monotonic_cycle_counter,
+            action: CallstackAction::Push {
+                this_address: new_context.this_address,
+                code_address: new_context.code_address,
+                base_memory_page: new_context.base_memory_page.0,
+                is_local_frame: new_context.is_local_frame,
+            },
+        });
+    }
+
+    fn finish_execution_context(&mut self, monotonic_cycle_counter: u32, panicked: bool) {
+        let writes = if self.frame_writes.len() > 1 {
+            self.frame_writes.pop().unwrap()
+        } else {
+            std::mem::take(self.current_frame_writes())
+        };
+        if panicked {
+            for index in writes.into_iter().rev() {
+                let (_, mut query) = self.log_queries[index];
+                query.rollback = true;
+                self.log_rollbacks.push((monotonic_cycle_counter, query));
+            }
+        } else {
+            // the writes are undone if the caller panics
+            self.current_frame_writes().extend(writes);
+        }
+
+        self.callstack_actions.push(CallstackRecord {
+            cycle: monotonic_cycle_counter,
+            action: CallstackAction::Pop { panicked },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler_tests::{VmExecutionResult, VmRunOptions};
+    use crate::test_utils::{add_imm, assemble, far_call, instruction, ret_ok, ret_panic, run};
+    use std::collections::HashMap;
+    use zk_evm::zkevm_opcode_defs::system_params::KECCAK256_ROUND_FUNCTION_PRECOMPILE_ADDRESS;
+    use zk_evm::zkevm_opcode_defs::{LogOpcode, Opcode, Operand};
+
+    const CALLER: u64 = 0x10000;
+    const CALLEE: u64 = 0x1234;
+    // the events and the L1 messages can only be sent by the kernel contracts
+    const KEPT: u64 = 0x9001;
+    const ROLLED_BACK: u64 = 0x9002;
+
+    /// `log r1, r2` of the `variant`.
+    fn log(variant: LogOpcode) -> u64 {
+        instruction(
+            Opcode::Log(variant),
+            Operand::RegOnly,
+            Operand::RegOnly,
+            (1, 2, 0),
+            0,
+        )
+    }
+
+    /// Writes the storage slot 1, sends an event and an L1 message, then returns with `ret`.
+    fn writer(ret: u64) -> Vec<u8> {
+        assemble(&[
+            add_imm(1, 1),
+            add_imm(2, 2),
+            log(LogOpcode::StorageWrite),
+            log(LogOpcode::Event),
+            log(LogOpcode::ToL1Message),
+            ret,
+        ])
+    }
+
+    fn writes_of(queries: impl Iterator<Item = (u32, LogQuery)>, address: u64) -> Vec<LogQuery> {
+        queries
+            .map(|(_, query)| query)
+            .filter(|query| query.address == Address::from_low_u64_be(address) && query.rw_flag)
+            .collect()
+    }
+
+    #[test]
+    fn log_queries_of_panicked_frames_are_rolled_back() {
+        // the second call panics and goes to the handler at pc 10
+        let mut caller = far_call(KEPT as u16, 10).to_vec();
+        caller.extend(far_call(ROLLED_BACK as u16, 10));
+        caller.push(ret_ok());
+
+        let snapshot = run(
+            HashMap::from([
+                (Address::from_low_u64_be(CALLER), assemble(&caller)),
+                (Address::from_low_u64_be(KEPT), writer(ret_ok())),
+                (Address::from_low_u64_be(ROLLED_BACK), writer(ret_panic())),
+            ]),
+            Address::from_low_u64_be(CALLER),
+            VmRunOptions {
+                record_full_witness: true,
+                ..Default::default()
+            },
+        );
+        assert!(matches!(
+            snapshot.execution_result,
+            VmExecutionResult::Ok(_)
+        ));
+        let witness = snapshot.full_witness.unwrap();
+
+        for address in [KEPT, ROLLED_BACK] {
+            let storage_writes = writes_of(witness.storage_queries().copied(), address);
+            assert_eq!(storage_writes.len(), 1);
+            assert_eq!(storage_writes[0].key, 1.into());
+            assert_eq!(storage_writes[0].written_value, 2.into());
+            assert_eq!(
+                writes_of(witness.event_queries().copied(), address).len(),
+                1
+            );
+            assert_eq!(
+                writes_of(witness.l1_message_queries().copied(), address).len(),
+                1
+            );
+        }
+
+        // the writes of the panicked frame are undone in the reverse order
+        let rolled_back = writes_of(witness.log_queries.iter().copied(), ROLLED_BACK);
+        let expected: Vec<_> = rolled_back
+            .into_iter()
+            .rev()
+            .map(|query| LogQuery {
+                rollback: true,
+                ..query
+            })
+            .collect();
+        let panic_cycle = witness
+            .callstack_actions
+            .iter()
+            .find(|record| record.action == CallstackAction::Pop { panicked: true })
+            .unwrap()
+            .cycle;
+        assert_eq!(
+            witness.log_rollbacks,
+            expected
+                .into_iter()
+                .map(|query| (panic_cycle, query))
+                .collect::<Vec<_>>()
+        );
+        witness.check_consistency(1, 0).unwrap();
+    }
+
+    #[test]
+    fn precompile_calls_and_decommittments_are_recorded() {
+        // the precompile is called by the kernel contract at its address
+        let precompile =
+            Address::from_low_u64_be(KECCAK256_ROUND_FUNCTION_PRECOMPILE_ADDRESS as u64);
+        let precompile_call = instruction(
+            Opcode::Log(LogOpcode::PrecompileCall),
+            Operand::RegOnly,
+            Operand::RegOnly,
+            (0, 0, 3),
+            0,
+        );
+        let mut program = vec![precompile_call];
+        // the exception handler is at pc 7
+        program.extend(far_call(CALLEE as u16, 7));
+        program.extend([ret_ok(), ret_ok()]);
+
+        let snapshot = run(
+            HashMap::from([
+                (precompile, assemble(&program)),
+                (Address::from_low_u64_be(CALLEE), assemble(&[ret_ok()])),
+            ]),
+            precompile,
+            VmRunOptions {
+                record_full_witness: true,
+                ..Default::default()
+            },
+        );
+        let witness = snapshot.full_witness.unwrap();
+
+        assert_eq!(witness.precompile_calls.len(), 1);
+        assert_eq!(witness.precompile_calls[0].call_params.address, precompile);
+        assert_eq!(witness.precompile_queries().count(), 1);
+
+        assert_eq!(witness.decommittment_queries.len(), 1);
+        let decommittment = &witness.decommittment_queries[0];
+        assert!(decommittment.query.is_fresh);
+        assert_eq!(
+            decommittment.code_words,
+            decommittment.query.decommitted_length as usize
+        );
+        witness.check_consistency(1, 0).unwrap();
+    }
+}
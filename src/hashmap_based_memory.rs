@@ -1,7 +1,8 @@
+use crate::tester_memory::ByteAddressableMemory;
 use crate::U256;
 use std::collections::HashMap;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct SimpleHashmapMemory {
     pub inner: HashMap<u32, HashMap<u32, PrimitiveValue>>,
 }
@@ -27,6 +28,11 @@ impl SimpleHashmapMemory {
         results
     }
 
+    /// Removes the page, so that the subsequent reads from it return zeroes.
+    pub fn free_page(&mut self, page_number: u32) {
+        self.inner.remove(&page_number);
+    }
+
     pub fn dump_page_content(
         &self,
         page_number: u32,
@@ -74,9 +80,11 @@ impl SimpleHashmapMemory {
 
     pub fn dump_full_page_as_u256_words(&self, page_number: u32) -> Vec<U256> {
         if let Some(page) = self.inner.get(&page_number) {
-            let max_key = *page.keys().max().unwrap_or(&0);
-            let mut result = Vec::with_capacity(max_key as usize);
-            for key in 0..max_key {
+            let Some(max_key) = page.keys().max().copied() else {
+                return vec![];
+            };
+            let mut result = Vec::with_capacity(max_key as usize + 1);
+            for key in 0..=max_key {
                 let word = page.get(&key).map(|el| el.value).unwrap_or(U256::zero());
                 result.push(word);
             }
@@ -104,6 +112,19 @@ use zk_evm::abstractions::Memory;
 use zk_evm::aux_structures::MemoryQuery;
 use zk_evm::vm_state::PrimitiveValue;
 
+impl ByteAddressableMemory for SimpleHashmapMemory {
+    fn read_slot(&self, page_number: u32, slot: u32) -> PrimitiveValue {
+        SimpleHashmapMemory::read_slot(self, page_number, slot)
+    }
+
+    fn write_slot(&mut self, page_number: u32, slot: u32, value: PrimitiveValue) {
+        self.inner
+            .entry(page_number)
+            .or_default()
+            .insert(slot, value);
+    }
+}
+
 impl Memory for SimpleHashmapMemory {
     fn read_code_query(&self, _monotonic_cycle_counter: u32, query: MemoryQuery) -> MemoryQuery {
         assert!(!query.rw_flag);
@@ -136,14 +157,18 @@ impl Memory for SimpleHashmapMemory {
         _monotonic_cycle_counter: u32,
         mut query: MemoryQuery,
     ) -> MemoryQuery {
-        let entry = self.inner.entry(query.location.page.0).or_default();
-        let value = entry
-            .entry(query.location.index.0)
-            .or_insert(PrimitiveValue::empty());
         if query.rw_flag {
-            value.value = query.value;
-            value.is_pointer = query.value_is_pointer;
+            let value = PrimitiveValue {
+                value: query.value,
+                is_pointer: query.value_is_pointer,
+            };
+            self.inner
+                .entry(query.location.page.0)
+                .or_default()
+                .insert(query.location.index.0, value);
         } else {
+            // reads must not allocate the page and slot entries
+            let value = self.read_slot(query.location.page.0, query.location.index.0);
             query.value = value.value;
             query.value_is_pointer = value.is_pointer;
         }
@@ -159,3 +184,150 @@ impl Memory for SimpleHashmapMemory {
         self.execute_partial_query(monotonic_cycle_counter, query)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zk_evm::abstractions::MemoryType;
+    use zk_evm::aux_structures::{MemoryIndex, MemoryLocation, MemoryPage, Timestamp};
+    use zk_evm::zkevm_opcode_defs::FatPointer;
+
+    const PAGE: u32 = 3;
+
+    #[test]
+    fn reads_do_not_allocate() {
+        let mut memory = SimpleHashmapMemory::default();
+        let query = MemoryQuery {
+            timestamp: Timestamp(0),
+            location: MemoryLocation {
+                memory_type: MemoryType::Heap,
+                page: MemoryPage(PAGE),
+                index: MemoryIndex(7),
+            },
+            value: U256::zero(),
+            rw_flag: false,
+            value_is_pointer: false,
+        };
+
+        let read = memory.execute_partial_query(0, query);
+        assert_eq!(read.value, U256::zero());
+        assert!(memory.inner.is_empty());
+
+        memory.execute_partial_query(
+            0,
+            MemoryQuery {
+                value: U256::from(5),
+                rw_flag: true,
+                ..query
+            },
+        );
+        assert_eq!(memory.execute_partial_query(0, query).value, U256::from(5));
+        assert_eq!(memory.inner[&PAGE].len(), 1);
+    }
+
+    #[test]
+    fn full_page_dump_ends_at_the_last_word() {
+        let mut memory = SimpleHashmapMemory::default();
+        memory.populate(vec![(PAGE, vec![U256::one(), U256::from(2)])]);
+        memory
+            .inner
+            .get_mut(&PAGE)
+            .unwrap()
+            .insert(4, PrimitiveValue::from_value(U256::from(5)));
+
+        assert_eq!(
+            memory.dump_full_page_as_u256_words(PAGE),
+            vec![1.into(), 2.into(), 0.into(), 0.into(), 5.into()]
+        );
+        assert!(memory.dump_full_page_as_u256_words(PAGE + 1).is_empty());
+    }
+
+    #[test]
+    fn unaligned_bytes_are_written_and_read_back() {
+        let mut memory = SimpleHashmapMemory::default();
+        memory.populate(vec![(PAGE, vec![U256::MAX; 3])]);
+        memory
+            .inner
+            .get_mut(&PAGE)
+            .unwrap()
+            .get_mut(&1)
+            .unwrap()
+            .is_pointer = true;
+
+        let bytes: Vec<u8> = (1..=40).collect();
+        memory.write_bytes(PAGE, 30, &bytes);
+
+        assert_eq!(memory.read_bytes(PAGE, 30, 40), bytes);
+        // the bytes around are kept
+        assert_eq!(memory.read_bytes(PAGE, 28, 2), [0xff, 0xff]);
+        assert_eq!(memory.read_bytes(PAGE, 70, 26), [0xff; 26]);
+        assert_eq!(memory.read_slot(PAGE, 0).value.low_u32(), 0xffff_0102);
+        // the overwritten pointer becomes an integer
+        assert!(!memory.read_slot(PAGE, 1).is_pointer);
+        // the missing words read as zeroes
+        assert_eq!(
+            memory.read_bytes(PAGE, 90, 10),
+            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]
+        );
+        assert_eq!(memory.read_bytes(PAGE + 1, 5, 3), [0; 3]);
+        assert!(memory.read_bytes(PAGE, 5, 0).is_empty());
+    }
+
+    #[test]
+    fn bytes_beyond_the_bound_read_as_zeroes() {
+        let mut memory = SimpleHashmapMemory::default();
+        memory.write_bytes(PAGE, 0, &[0xaa; 64]);
+
+        let mut expected = vec![0xaa; 10];
+        expected.extend([0; 20]);
+        assert_eq!(memory.read_bytes_within_bound(PAGE, 20, 30, 30), expected);
+    }
+
+    #[test]
+    fn fat_pointer_slice_is_read() {
+        let mut memory = SimpleHashmapMemory::default();
+        let bytes: Vec<u8> = (0..64).collect();
+        memory.write_bytes(PAGE, 0, &bytes);
+
+        let pointer = FatPointer {
+            offset: 4,
+            memory_page: PAGE,
+            start: 10,
+            length: 20,
+        };
+        assert_eq!(memory.read_fat_pointer(pointer), bytes[14..30]);
+    }
+
+    #[test]
+    fn hex_dump_annotates_pointers_and_the_heap_bound() {
+        let mut memory = SimpleHashmapMemory::default();
+        memory.write_bytes(PAGE, 0, &[0x11; 4]);
+        let pointer = FatPointer {
+            offset: 1,
+            memory_page: 7,
+            start: 0x20,
+            length: 0x40,
+        };
+        memory.inner.get_mut(&PAGE).unwrap().insert(
+            1,
+            PrimitiveValue {
+                value: pointer.to_u256(),
+                is_pointer: true,
+            },
+        );
+
+        let dump = memory.hex_dump(PAGE, 2..40, Some(40));
+        let lines: Vec<_> = dump.lines().collect();
+        assert_eq!(
+            lines[0],
+            "00000000: 1111111100000000 0000000000000000 0000000000000000 0000000000000000"
+        );
+        assert_eq!(
+            lines[1],
+            "00000020: 0000000000000000 0000000000000000 0000004000000020 0000000700000001  \
+             ptr(page: 7, start: 0x20, length: 0x40, offset: 0x1)  beyond heap bound 0x28"
+        );
+        assert_eq!(lines.len(), 2);
+        assert!(memory.hex_dump(PAGE, 5..5, None).is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::vm_session::TesterVmState;
+
+///
+/// The logic the runner invokes after every cycle, with full access to the VM state.
+///
+/// The hooks are cloned along with the session checkpoints, so any state they keep is restored
+/// together with the VM.
+///
+pub trait PostCycleHook: std::fmt::Debug {
+    fn after_cycle(&mut self, vm: &mut TesterVmState) -> anyhow::Result<()>;
+
+    fn box_clone(&self) -> Box<dyn PostCycleHook>;
+}
+
+impl Clone for Box<dyn PostCycleHook> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+///
+/// Makes the EVM bytecode published by the ContractDeployer through the KnownCodesStorage
+/// available to the decommitter. Always installed by the runner before the user hooks.
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EvmBytecodeRecorder;
+
+impl PostCycleHook for EvmBytecodeRecorder {
+    fn after_cycle(&mut self, vm: &mut TesterVmState) -> anyhow::Result<()> {
+        crate::evm_deploy::record_deployed_evm_bytecode(vm);
+
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn PostCycleHook> {
+        Box::new(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler_tests::VmRunOptions;
+    use crate::test_utils::{add_imm, assemble, ret_ok, run, session};
+    use crate::{Address, U256};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use zk_evm::vm_state::PrimitiveValue;
+
+    const CONTRACT: u64 = 0x10000;
+
+    ///
+    /// Records the pc after every cycle, overwrites `r4` with the number of cycles seen, and
+    /// fails once the cycle `fail_at` is reached.
+    ///
+    #[derive(Debug, Clone)]
+    struct RecordingHook {
+        pcs: Arc<Mutex<Vec<u16>>>,
+        fail_at: Option<usize>,
+    }
+
+    impl PostCycleHook for RecordingHook {
+        fn after_cycle(&mut self, vm: &mut TesterVmState) -> anyhow::Result<()> {
+            let mut pcs = self.pcs.lock().unwrap();
+            pcs.push(vm.local_state.callstack.get_current_stack().pc);
+            vm.local_state.registers[3] = PrimitiveValue::from_value(U256::from(pcs.len()));
+            if Some(pcs.len()) == self.fail_at {
+                anyhow::bail!("hook failed at cycle {}", pcs.len());
+            }
+
+            Ok(())
+        }
+
+        fn box_clone(&self) -> Box<dyn PostCycleHook> {
+            Box::new(self.clone())
+        }
+    }
+
+    fn contracts() -> HashMap<Address, Vec<u8>> {
+        HashMap::from([(
+            Address::from_low_u64_be(CONTRACT),
+            assemble(&[add_imm(1, 1), add_imm(2, 2), add_imm(3, 3), ret_ok()]),
+        )])
+    }
+
+    fn options(pcs: &Arc<Mutex<Vec<u16>>>, fail_at: Option<usize>) -> VmRunOptions {
+        VmRunOptions {
+            post_cycle_hooks: vec![Box::new(RecordingHook {
+                pcs: pcs.clone(),
+                fail_at,
+            })],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn hook_is_invoked_after_every_cycle() {
+        let pcs = Arc::new(Mutex::new(vec![]));
+        let snapshot = run(
+            contracts(),
+            Address::from_low_u64_be(CONTRACT),
+            options(&pcs, None),
+        );
+
+        // the entry frame has returned after the last cycle
+        assert_eq!(*pcs.lock().unwrap(), [1, 2, 3, 0]);
+        assert_eq!(snapshot.num_cycles_used, 4);
+        // the changes the hook makes are kept
+        assert_eq!(snapshot.registers[3].value, U256::from(4));
+    }
+
+    #[test]
+    fn hook_error_aborts_the_run() {
+        let pcs = Arc::new(Mutex::new(vec![]));
+        let mut session = session(
+            contracts(),
+            Address::from_low_u64_be(CONTRACT),
+            options(&pcs, Some(2)),
+        );
+
+        let error = session.run(1_000).unwrap_err();
+        assert_eq!(error.to_string(), "hook failed at cycle 2");
+        assert_eq!(*pcs.lock().unwrap(), [1, 2]);
+        assert!(session.result().is_none());
+    }
+}
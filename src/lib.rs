@@ -10,10 +10,32 @@ use zk_evm::vm_state::*;
 use zk_evm::witness_trace::DummyTracer;
 use zk_evm::zkevm_opcode_defs::ethereum_types::*;
 
+pub mod assembly;
+pub mod bytecode;
+pub mod call_mocks;
+pub mod call_tree;
 pub mod compiler_tests;
+pub mod debug_info;
+pub mod decommitter;
 pub mod default_environment;
+pub mod disassembler;
+pub mod environment;
 pub mod events;
 pub mod evm_deploy;
+pub mod execution_tracer;
+pub mod full_witness_tracer;
 pub mod hashmap_based_memory;
+pub mod hooks;
+pub mod page_lifecycle;
+pub mod paged_memory;
+pub mod panic_info;
+pub mod precompiles;
+pub mod revert_reason;
 pub mod simple_witness_tracer;
+pub mod system_contracts;
+#[cfg(test)]
+mod test_utils;
+pub mod tester_memory;
 pub mod utils;
+pub mod vm_session;
+pub mod watchpoints;
@@ -0,0 +1,476 @@
+use crate::debug_info::ExecutionLocation;
+use crate::Address;
+use std::collections::HashMap;
+use zk_evm::aux_structures::MemoryPage;
+use zk_evm::vm_state::{
+    aux_heap_page_from_base, heap_page_from_base, stack_page_from_base, PrimitiveValue,
+    VmLocalState,
+};
+use zk_evm::zkevm_opcode_defs::decoding::EncodingModeProduction;
+use zk_evm::zkevm_opcode_defs::definitions::ret::RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER;
+use zk_evm::zkevm_opcode_defs::{FatPointer, Opcode, UMAOpcode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PageKind {
+    Stack,
+    Heap,
+    AuxHeap,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageInfo {
+    pub page: u32,
+    pub kind: PageKind,
+    /// The address of the frame the page was created for.
+    pub owner: Address,
+    pub created_at_cycle: u32,
+    pub released_at_cycle: Option<u32>,
+}
+
+///
+/// A read through a fat pointer into a page that was already released.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FreedPageAccess {
+    pub cycle: u32,
+    pub location: ExecutionLocation,
+    pub page: PageInfo,
+}
+
+impl std::fmt::Display for FreedPageAccess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "cycle {}: {} reads {:?} page {} of {:?} created at cycle {} and released at cycle {}",
+            self.cycle,
+            self.location,
+            self.page.kind,
+            self.page.page,
+            self.page.owner,
+            self.page.created_at_cycle,
+            self.page
+                .released_at_cycle
+                .map(|cycle| cycle.to_string())
+                .unwrap_or_default(),
+        )
+    }
+}
+
+///
+/// Tracks the pages created by far calls and releases them once they cannot be legitimately
+/// accessed anymore.
+///
+/// The stack page of a frame is released when the frame returns. The heap pages are released
+/// along with it, except for the page the returndata points to, which is retained by the caller
+/// until the caller itself returns.
+///
+#[derive(Debug, Clone)]
+pub struct PageLifecycleTracer {
+    pub pages: HashMap<u32, PageInfo>,
+    pub freed_page_accesses: Vec<FreedPageAccess>,
+    /// The pages retained by every far frame, innermost last.
+    retained: Vec<Vec<u32>>,
+    /// The pages released since the last call to `take_released_pages`.
+    released: Vec<u32>,
+    pending_far_call_depth: Option<usize>,
+    pending_release: Option<Vec<u32>>,
+}
+
+impl Default for PageLifecycleTracer {
+    fn default() -> Self {
+        Self {
+            pages: HashMap::new(),
+            freed_page_accesses: vec![],
+            // the entry frame
+            retained: vec![vec![]],
+            released: vec![],
+            pending_far_call_depth: None,
+            pending_release: None,
+        }
+    }
+}
+
+impl PageLifecycleTracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_released(&self, page: u32) -> bool {
+        self.pages
+            .get(&page)
+            .map(|info| info.released_at_cycle.is_some())
+            .unwrap_or(false)
+    }
+
+    /// Returns the pages released since the previous call, so that they can be freed.
+    pub fn take_released_pages(&mut self) -> Vec<u32> {
+        std::mem::take(&mut self.released)
+    }
+
+    fn check_pointer(&mut self, pointer: PrimitiveValue, location: &ExecutionLocation, cycle: u32) {
+        if !pointer.is_pointer {
+            return;
+        }
+
+        let page = FatPointer::from_u256(pointer.value).memory_page;
+        if let Some(info) = self.pages.get(&page) {
+            if info.released_at_cycle.is_some() {
+                self.freed_page_accesses.push(FreedPageAccess {
+                    cycle,
+                    location: location.clone(),
+                    page: info.clone(),
+                });
+            }
+        }
+    }
+
+    pub(crate) fn before_execution(
+        &mut self,
+        state: &VmLocalState<8, EncodingModeProduction>,
+        opcode: &Opcode,
+        src0_value: PrimitiveValue,
+        location: &ExecutionLocation,
+    ) {
+        let cycle = state.monotonic_cycle_counter;
+        let current_frame = state.callstack.get_current_stack();
+
+        match opcode {
+            Opcode::UMA(UMAOpcode::FatPointerRead) => {
+                self.check_pointer(src0_value, location, cycle);
+            }
+            Opcode::FarCall(_) => {
+                // the calldata may be a forwarded fat pointer
+                self.check_pointer(src0_value, location, cycle);
+                self.pending_far_call_depth = Some(state.callstack.depth());
+            }
+            Opcode::Ret(_) => {
+                // the returndata may be a forwarded fat pointer
+                self.check_pointer(src0_value, location, cycle);
+                if current_frame.is_local_frame || self.retained.len() <= 1 {
+                    return;
+                }
+
+                let base_page = current_frame.base_memory_page;
+                let mut pages = self.retained.pop().unwrap_or_default();
+                pages.extend([
+                    stack_page_from_base(base_page).0,
+                    heap_page_from_base(base_page).0,
+                    aux_heap_page_from_base(base_page).0,
+                ]);
+                self.pending_release = Some(pages);
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn after_execution(&mut self, state: &VmLocalState<8, EncodingModeProduction>) {
+        let cycle = state.monotonic_cycle_counter;
+
+        if let Some(depth) = self.pending_far_call_depth.take() {
+            let new_frame = state.callstack.get_current_stack();
+            if state.callstack.depth() > depth && !new_frame.is_local_frame {
+                let base_page = new_frame.base_memory_page;
+                for (page, kind) in [
+                    (stack_page_from_base(base_page), PageKind::Stack),
+                    (heap_page_from_base(base_page), PageKind::Heap),
+                    (aux_heap_page_from_base(base_page), PageKind::AuxHeap),
+                ] {
+                    let MemoryPage(page) = page;
+                    self.pages.insert(
+                        page,
+                        PageInfo {
+                            page,
+                            kind,
+                            owner: new_frame.this_address,
+                            created_at_cycle: cycle,
+                            released_at_cycle: None,
+                        },
+                    );
+                }
+                self.retained.push(vec![]);
+            }
+        }
+
+        if let Some(pages) = self.pending_release.take() {
+            let returndata_ptr = state.registers[RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER as usize];
+            let returndata_page = if returndata_ptr.is_pointer {
+                Some(FatPointer::from_u256(returndata_ptr.value).memory_page)
+            } else {
+                None
+            };
+
+            for page in pages {
+                if Some(page) == returndata_page {
+                    if let Some(caller_pages) = self.retained.last_mut() {
+                        caller_pages.push(page);
+                    }
+                    continue;
+                }
+                if let Some(info) = self.pages.get_mut(&page) {
+                    if info.released_at_cycle.is_none() {
+                        info.released_at_cycle = Some(cycle);
+                        self.released.push(page);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler_tests::VmRunOptions;
+    use crate::test_utils::{
+        add_imm, assemble, far_call, ret_heap, ret_ok, ret_panic, session, uma,
+    };
+    use crate::vm_session::VmSession;
+    use zk_evm::vm_state::CallStackEntry;
+    use zk_evm::zkevm_opcode_defs::{FarCallOpcode, RetOpcode};
+
+    const CALLER: u64 = 0x10000;
+    const CALLEE: u64 = 0x3333;
+    const CALLEE_BASE_PAGE: u32 = 8;
+
+    fn location(address: u64, pc: u16) -> ExecutionLocation {
+        let address = Address::from_low_u64_be(address);
+        ExecutionLocation::new(address, address, pc)
+    }
+
+    // a released page can only be reached by a pointer the VM has leaked, so the VM state is
+    // driven by hand
+    #[test]
+    fn reads_from_released_pages_are_recorded() {
+        let mut tracer = PageLifecycleTracer::new();
+        let mut state = VmLocalState::<8, EncodingModeProduction>::empty_state();
+        let mut caller = CallStackEntry::empty_context();
+        caller.this_address = Address::from_low_u64_be(CALLER);
+        state.callstack.push_entry(caller);
+
+        // the far call creates the pages of the callee
+        state.monotonic_cycle_counter = 1;
+        let far_call = Opcode::FarCall(FarCallOpcode::Normal);
+        tracer.before_execution(
+            &state,
+            &far_call,
+            PrimitiveValue::empty(),
+            &location(CALLER, 0),
+        );
+        let mut callee = CallStackEntry::empty_context();
+        callee.this_address = Address::from_low_u64_be(CALLEE);
+        callee.base_memory_page = MemoryPage(CALLEE_BASE_PAGE);
+        state.callstack.push_entry(callee);
+        tracer.after_execution(&state);
+
+        // the callee returns nothing, so all its pages are released
+        state.monotonic_cycle_counter = 5;
+        let ret = Opcode::Ret(RetOpcode::Ok);
+        tracer.before_execution(&state, &ret, PrimitiveValue::empty(), &location(CALLEE, 3));
+        state.callstack.pop_entry();
+        tracer.after_execution(&state);
+
+        let heap_page = heap_page_from_base(MemoryPage(CALLEE_BASE_PAGE)).0;
+        assert!(tracer.is_released(heap_page));
+        let mut released = tracer.take_released_pages();
+        released.sort();
+        assert_eq!(
+            released,
+            [
+                stack_page_from_base(MemoryPage(CALLEE_BASE_PAGE)).0,
+                heap_page,
+                aux_heap_page_from_base(MemoryPage(CALLEE_BASE_PAGE)).0,
+            ]
+        );
+
+        state.monotonic_cycle_counter = 7;
+        let pointer = PrimitiveValue {
+            value: FatPointer {
+                offset: 0,
+                memory_page: heap_page,
+                start: 0,
+                length: 32,
+            }
+            .to_u256(),
+            is_pointer: true,
+        };
+        let read = Opcode::UMA(UMAOpcode::FatPointerRead);
+        tracer.before_execution(&state, &read, pointer, &location(CALLER, 4));
+
+        assert_eq!(
+            tracer.freed_page_accesses,
+            [FreedPageAccess {
+                cycle: 7,
+                location: location(CALLER, 4),
+                page: PageInfo {
+                    page: heap_page,
+                    kind: PageKind::Heap,
+                    owner: Address::from_low_u64_be(CALLEE),
+                    created_at_cycle: 1,
+                    released_at_cycle: Some(5),
+                },
+            }]
+        );
+    }
+
+    const MIDDLE: u64 = 0x2222;
+
+    ///
+    /// The caller far calls the middle contract, which far calls the callee and returns nothing.
+    /// The callee writes its heap and returns the written word.
+    ///
+    fn run_nested_calls(options: VmRunOptions) -> VmSession {
+        let mut caller = far_call(MIDDLE as u16, 6).to_vec();
+        caller.extend([ret_ok(), ret_panic()]);
+
+        let mut middle = far_call(CALLEE as u16, 6).to_vec();
+        middle.extend([ret_ok(), ret_panic()]);
+
+        let mut callee = vec![
+            add_imm(0, 1),
+            add_imm(0x42, 2),
+            uma(UMAOpcode::HeapWrite, (1, 2, 0, 0)),
+        ];
+        callee.extend(ret_heap(32));
+
+        let mut session = session(
+            HashMap::from([
+                (Address::from_low_u64_be(CALLER), assemble(&caller)),
+                (Address::from_low_u64_be(MIDDLE), assemble(&middle)),
+                (Address::from_low_u64_be(CALLEE), assemble(&callee)),
+            ]),
+            Address::from_low_u64_be(CALLER),
+            options,
+        );
+        assert!(session.run(1_000).unwrap());
+
+        session
+    }
+
+    fn page_of(tracer: &PageLifecycleTracer, owner: u64, kind: PageKind) -> &PageInfo {
+        tracer
+            .pages
+            .values()
+            .find(|info| info.owner == Address::from_low_u64_be(owner) && info.kind == kind)
+            .unwrap()
+    }
+
+    #[test]
+    fn pages_are_released_when_the_frame_returns() {
+        let session = run_nested_calls(VmRunOptions {
+            track_page_lifecycle: true,
+            ..Default::default()
+        });
+        let tracer = session.tracer.page_lifecycle_tracer.as_ref().unwrap();
+        // the pages of the entry frame are not tracked
+        assert_eq!(tracer.pages.len(), 6);
+
+        let callee_stack = page_of(tracer, CALLEE, PageKind::Stack);
+        let callee_return_cycle = callee_stack.released_at_cycle.unwrap();
+        assert!(callee_return_cycle > callee_stack.created_at_cycle);
+        assert_eq!(
+            page_of(tracer, CALLEE, PageKind::AuxHeap).released_at_cycle,
+            Some(callee_return_cycle)
+        );
+
+        // the returndata of the callee is released along with the middle frame
+        let middle_stack = page_of(tracer, MIDDLE, PageKind::Stack);
+        let middle_return_cycle = middle_stack.released_at_cycle.unwrap();
+        assert!(middle_return_cycle > callee_return_cycle);
+        assert_eq!(
+            page_of(tracer, CALLEE, PageKind::Heap).released_at_cycle,
+            Some(middle_return_cycle)
+        );
+
+        // the returndata of the middle frame is retained by the entry frame, which never returns
+        let middle_heap = page_of(tracer, MIDDLE, PageKind::Heap).page;
+        assert!(!tracer.is_released(middle_heap));
+        assert!(tracer.freed_page_accesses.is_empty());
+
+        // the released pages stay in the memory unless they are freed
+        let callee_heap = page_of(tracer, CALLEE, PageKind::Heap).page;
+        assert_eq!(
+            session.vm.memory.read_slot(callee_heap, 0).value,
+            0x42.into()
+        );
+    }
+
+    #[test]
+    fn released_pages_are_freed_on_request() {
+        let session = run_nested_calls(VmRunOptions {
+            free_released_pages: true,
+            ..Default::default()
+        });
+        let tracer = session.tracer.page_lifecycle_tracer.as_ref().unwrap();
+
+        let callee_heap = page_of(tracer, CALLEE, PageKind::Heap).page;
+        assert!(tracer.is_released(callee_heap));
+        assert!(session.vm.memory.read_slot(callee_heap, 0).value.is_zero());
+    }
+
+    #[test]
+    fn reads_from_released_pages_fail_the_run() {
+        // the callee writes its aux heap and returns its heap, the caller reads through r1
+        let mut caller = far_call(CALLEE as u16, 7).to_vec();
+        caller.extend([
+            uma(UMAOpcode::FatPointerRead, (1, 0, 2, 0)),
+            ret_ok(),
+            ret_panic(),
+        ]);
+        let mut callee = vec![
+            add_imm(0, 1),
+            add_imm(0x42, 2),
+            uma(UMAOpcode::AuxHeapWrite, (1, 2, 0, 0)),
+        ];
+        callee.extend(ret_heap(32));
+        let mut session = session(
+            HashMap::from([
+                (Address::from_low_u64_be(CALLER), assemble(&caller)),
+                (Address::from_low_u64_be(CALLEE), assemble(&callee)),
+            ]),
+            Address::from_low_u64_be(CALLER),
+            VmRunOptions {
+                track_page_lifecycle: true,
+                ..Default::default()
+            },
+        );
+        while session.vm.local_state.callstack.depth() == 1 {
+            session.step().unwrap();
+        }
+        while session.vm.local_state.callstack.depth() > 1 {
+            session.step().unwrap();
+        }
+
+        // a well-formed contract can only leak such a pointer through a VM bug, so it is injected
+        let tracer = session.tracer.page_lifecycle_tracer.as_ref().unwrap();
+        let aux_heap = page_of(tracer, CALLEE, PageKind::AuxHeap).clone();
+        assert!(aux_heap.released_at_cycle.is_some());
+        let pointer = FatPointer {
+            offset: 0,
+            memory_page: aux_heap.page,
+            start: 0,
+            length: 32,
+        };
+        session.vm.local_state.registers[0] = PrimitiveValue {
+            value: pointer.to_u256(),
+            is_pointer: true,
+        };
+        let read_cycle = session.vm.local_state.monotonic_cycle_counter;
+        assert!(session.run(1_000).unwrap());
+
+        let tracer = session.tracer.page_lifecycle_tracer.as_ref().unwrap();
+        assert_eq!(
+            tracer.freed_page_accesses,
+            vec![FreedPageAccess {
+                cycle: read_cycle,
+                location: location(CALLER, 5),
+                page: aux_heap,
+            }]
+        );
+        let error = session.finish().unwrap_err().to_string();
+        assert!(
+            error.starts_with("Reads from the released memory pages"),
+            "{}",
+            error
+        );
+    }
+}
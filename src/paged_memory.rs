@@ -0,0 +1,265 @@
+use crate::tester_memory::ByteAddressableMemory;
+use crate::U256;
+use std::collections::HashMap;
+
+use zk_evm::abstractions::Memory;
+use zk_evm::aux_structures::MemoryQuery;
+use zk_evm::vm_state::PrimitiveValue;
+
+///
+/// The memory that keeps every page as a growable vector of words.
+///
+/// Reads never allocate, and writes only grow the page up to the written word, so heap-heavy
+/// workloads avoid hashing every access as in `SimpleHashmapMemory`.
+///
+/// The runner uses it with `MemoryBackend::Paged`.
+///
+#[derive(Debug, Default, Clone)]
+pub struct PagedMemory {
+    pub pages: HashMap<u32, Vec<PrimitiveValue>>,
+}
+
+impl PagedMemory {
+    pub fn populate(&mut self, elements: Vec<(u32, Vec<U256>)>) -> Vec<(u32, usize)> {
+        let mut results = vec![];
+        for (page, values) in elements.into_iter() {
+            assert!(!self.pages.contains_key(&page));
+            let len = values.len();
+            let words = values.into_iter().map(PrimitiveValue::from_value).collect();
+            self.pages.insert(page, words);
+            results.push((page, len));
+        }
+
+        results
+    }
+
+    /// Removes the page, so that the subsequent reads from it return zeroes.
+    pub fn free_page(&mut self, page_number: u32) {
+        self.pages.remove(&page_number);
+    }
+
+    pub fn read_slot(&self, page_number: u32, slot: u32) -> PrimitiveValue {
+        self.pages
+            .get(&page_number)
+            .and_then(|page| page.get(slot as usize))
+            .copied()
+            .unwrap_or(PrimitiveValue::empty())
+    }
+
+    fn write_slot(&mut self, page_number: u32, slot: u32, value: PrimitiveValue) {
+        let slot = slot as usize;
+        let is_empty = value.value.is_zero() && !value.is_pointer;
+        let page_len = self.pages.get(&page_number).map_or(0, |page| page.len());
+        // writing zeroes beyond the end of the page does not need the page to be allocated or grow
+        if is_empty && page_len <= slot {
+            return;
+        }
+
+        let page = self.pages.entry(page_number).or_default();
+        if page.len() <= slot {
+            page.resize(slot + 1, PrimitiveValue::empty());
+        }
+        page[slot] = value;
+    }
+
+    pub fn dump_page_content(
+        &self,
+        page_number: u32,
+        range: std::ops::Range<u32>,
+    ) -> Vec<[u8; 32]> {
+        let as_u256 = self.dump_page_content_as_u256_words(page_number, range);
+        let mut result = Vec::with_capacity(as_u256.len());
+        let mut buffer = [0u8; 32];
+        for word in as_u256.into_iter() {
+            word.to_big_endian(&mut buffer);
+            result.push(buffer);
+        }
+
+        result
+    }
+
+    pub fn dump_page_content_as_u256_words(
+        &self,
+        page_number: u32,
+        range: std::ops::Range<u32>,
+    ) -> Vec<U256> {
+        let mut result = vec![U256::zero(); range.len()];
+        if let Some(page) = self.pages.get(&page_number) {
+            let start = (range.start as usize).min(page.len());
+            let end = (range.end as usize).min(page.len());
+            for (dst, src) in result.iter_mut().zip(page[start..end].iter()) {
+                *dst = src.value;
+            }
+        }
+
+        result
+    }
+
+    pub fn dump_full_page_as_u256_words(&self, page_number: u32) -> Vec<U256> {
+        self.pages
+            .get(&page_number)
+            .map(|page| page.iter().map(|el| el.value).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn dump_full_page(&self, page_number: u32) -> Vec<[u8; 32]> {
+        let as_u256 = self.dump_full_page_as_u256_words(page_number);
+        let mut result = Vec::with_capacity(as_u256.len());
+        let mut buffer = [0u8; 32];
+        for word in as_u256.into_iter() {
+            word.to_big_endian(&mut buffer);
+            result.push(buffer);
+        }
+
+        result
+    }
+}
+
+impl ByteAddressableMemory for PagedMemory {
+    fn read_slot(&self, page_number: u32, slot: u32) -> PrimitiveValue {
+        PagedMemory::read_slot(self, page_number, slot)
+    }
+
+    fn write_slot(&mut self, page_number: u32, slot: u32, value: PrimitiveValue) {
+        PagedMemory::write_slot(self, page_number, slot, value)
+    }
+}
+
+impl Memory for PagedMemory {
+    fn read_code_query(&self, _monotonic_cycle_counter: u32, query: MemoryQuery) -> MemoryQuery {
+        assert!(!query.rw_flag);
+
+        let value = self.read_slot(query.location.page.0, query.location.index.0);
+        let mut query = query;
+        query.value_is_pointer = value.is_pointer;
+        query.value = value.value;
+
+        query
+    }
+
+    fn execute_partial_query(
+        &mut self,
+        _monotonic_cycle_counter: u32,
+        mut query: MemoryQuery,
+    ) -> MemoryQuery {
+        if query.rw_flag {
+            self.write_slot(
+                query.location.page.0,
+                query.location.index.0,
+                PrimitiveValue {
+                    value: query.value,
+                    is_pointer: query.value_is_pointer,
+                },
+            );
+        } else {
+            let value = self.read_slot(query.location.page.0, query.location.index.0);
+            query.value = value.value;
+            query.value_is_pointer = value.is_pointer;
+        }
+
+        query
+    }
+
+    fn specialized_code_query(
+        &mut self,
+        monotonic_cycle_counter: u32,
+        query: MemoryQuery,
+    ) -> MemoryQuery {
+        self.execute_partial_query(monotonic_cycle_counter, query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashmap_based_memory::SimpleHashmapMemory;
+    use zk_evm::abstractions::MemoryType;
+    use zk_evm::aux_structures::{MemoryIndex, MemoryLocation, MemoryPage, Timestamp};
+
+    fn query(page: u32, index: u32, value: Option<U256>) -> MemoryQuery {
+        MemoryQuery {
+            timestamp: Timestamp(0),
+            location: MemoryLocation {
+                memory_type: MemoryType::Heap,
+                page: MemoryPage(page),
+                index: MemoryIndex(index),
+            },
+            value: value.unwrap_or_default(),
+            rw_flag: value.is_some(),
+            value_is_pointer: false,
+        }
+    }
+
+    #[test]
+    fn reads_and_zero_writes_do_not_allocate() {
+        let mut memory = PagedMemory::default();
+        let read = memory.execute_partial_query(0, query(5, 100, None));
+        memory.execute_partial_query(0, query(6, 100, Some(U256::zero())));
+
+        assert_eq!(read.value, U256::zero());
+        assert!(memory.pages.is_empty());
+    }
+
+    #[test]
+    fn writes_grow_the_page_up_to_the_written_word() {
+        let mut memory = PagedMemory::default();
+        memory.execute_partial_query(0, query(5, 3, Some(U256::from(7))));
+        // a zero write within the page overwrites the word
+        memory.execute_partial_query(0, query(5, 3, Some(U256::zero())));
+        memory.execute_partial_query(0, query(5, 1, Some(U256::from(9))));
+        // a zero write beyond the end does not grow the page
+        memory.execute_partial_query(0, query(5, 10, Some(U256::zero())));
+
+        assert_eq!(memory.pages[&5].len(), 4);
+        assert_eq!(
+            memory.dump_full_page_as_u256_words(5),
+            vec![0.into(), 9.into(), 0.into(), 0.into()]
+        );
+        assert_eq!(
+            memory.dump_page_content_as_u256_words(5, 1..6),
+            vec![9.into(), 0.into(), 0.into(), 0.into(), 0.into()]
+        );
+    }
+
+    #[test]
+    fn behaves_like_the_hashmap_memory() {
+        let mut paged = PagedMemory::default();
+        let mut hashmap = SimpleHashmapMemory::default();
+        let calldata = vec![(3, (1..=8).map(U256::from).collect::<Vec<_>>())];
+        paged.populate(calldata.clone());
+        hashmap.populate(calldata);
+
+        let queries = [
+            query(3, 2, None),
+            query(3, 20, None),
+            query(7, 4, Some(U256::from(42))),
+            query(7, 0, Some(U256::zero())),
+            query(7, 4, None),
+            query(3, 2, Some(U256::MAX)),
+            query(3, 2, None),
+            query(9, 1, None),
+        ];
+        for query in queries {
+            assert_eq!(
+                paged.execute_partial_query(0, query),
+                hashmap.execute_partial_query(0, query)
+            );
+        }
+        for page in [3, 7, 9] {
+            assert_eq!(
+                paged.dump_page_content(page, 0..10),
+                hashmap.dump_page_content(page, 0..10)
+            );
+            assert_eq!(paged.dump_full_page(page), hashmap.dump_full_page(page));
+        }
+
+        let bytes: Vec<u8> = (1..=40).collect();
+        paged.write_bytes(7, 30, &bytes);
+        hashmap.write_bytes(7, 30, &bytes);
+        assert_eq!(paged.read_bytes(7, 0, 96), hashmap.read_bytes(7, 0, 96));
+        assert_eq!(
+            paged.hex_dump(7, 0..96, None),
+            hashmap.hex_dump(7, 0..96, None)
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use crate::debug_info::ExecutionLocation;
+
+///
+/// The cause of a VM panic.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PanicReason {
+    /// Not enough ergs to execute the instruction.
+    OutOfErgs,
+    /// The instruction cannot be decoded.
+    InvalidOpcode,
+    /// A state modification in the static context.
+    StaticViolation,
+    /// A kernel-only instruction executed outside of the kernel mode.
+    KernelModeViolation,
+    /// A near call exceeding the callstack limit.
+    StackOverflow,
+    /// A heap access at an offset beyond the addressable range.
+    HeapBound,
+    /// A fat pointer expected, but an integer given.
+    NotAPointer,
+    /// A fat pointer given where an integer is expected.
+    UnexpectedPointer,
+    /// An overflow of the fat pointer offset or length.
+    PointerOverflow,
+    /// A fat pointer or a far call memory slice that is not well-formed.
+    MalformedPointer,
+    /// A far call to an address whose code cannot be called.
+    InvalidCallTarget,
+    /// A far call exceeding the callstack limit.
+    CallDepth,
+    /// `ret.panic` executed by the contract itself.
+    ExplicitPanic,
+    /// The cause was not recorded.
+    Unknown,
+}
+
+impl std::fmt::Display for PanicReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            Self::OutOfErgs => "out of ergs",
+            Self::InvalidOpcode => "invalid opcode",
+            Self::StaticViolation => "state modification in a static context",
+            Self::KernelModeViolation => "kernel mode instruction outside of the kernel",
+            Self::StackOverflow => "near call stack overflow",
+            Self::HeapBound => "heap bound exceeded",
+            Self::NotAPointer => "fat pointer expected",
+            Self::UnexpectedPointer => "unexpected fat pointer",
+            Self::PointerOverflow => "fat pointer overflow",
+            Self::MalformedPointer => "malformed fat pointer",
+            Self::InvalidCallTarget => "invalid far call target",
+            Self::CallDepth => "far call depth exceeded",
+            Self::ExplicitPanic => "explicit panic",
+            Self::Unknown => "unknown",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+///
+/// The cause of a panic and the place it originated from.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanicInfo {
+    pub reason: PanicReason,
+    pub location: ExecutionLocation,
+    /// The callstack depth of the frame that panicked.
+    pub frame_depth: usize,
+}
+
+impl std::fmt::Display for PanicInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at {} (depth {})",
+            self.reason, self.location, self.frame_depth
+        )
+    }
+}
@@ -0,0 +1,43 @@
+use zk_evm::abstractions::{Memory, PrecompileCyclesWitness, PrecompilesProcessor};
+use zk_evm::aux_structures::{LogQuery, MemoryQuery};
+use zk_evm::zk_evm_abstractions::precompiles::DefaultPrecompilesProcessor;
+
+///
+/// The precompiles processor that returns the memory queries and the round witness of the
+/// precompiles only if `record_witness` is set.
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TesterPrecompilesProcessor {
+    pub record_witness: bool,
+}
+
+impl PrecompilesProcessor for TesterPrecompilesProcessor {
+    fn execute_precompile<M: Memory>(
+        &mut self,
+        monotonic_cycle_counter: u32,
+        query: LogQuery,
+        memory: &mut M,
+    ) -> Option<(Vec<MemoryQuery>, Vec<MemoryQuery>, PrecompileCyclesWitness)> {
+        if self.record_witness {
+            DefaultPrecompilesProcessor::<true>.execute_precompile(
+                monotonic_cycle_counter,
+                query,
+                memory,
+            )
+        } else {
+            DefaultPrecompilesProcessor::<false>.execute_precompile(
+                monotonic_cycle_counter,
+                query,
+                memory,
+            )
+        }
+    }
+
+    fn start_frame(&mut self) {
+        DefaultPrecompilesProcessor::<false>.start_frame();
+    }
+
+    fn finish_frame(&mut self, panicked: bool) {
+        DefaultPrecompilesProcessor::<false>.finish_frame(panicked);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::U256;
+
+/// The selector of `Error(string)`.
+pub const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// The selector of `Panic(uint256)`.
+pub const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+///
+/// The decoded revert data.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum RevertReason {
+    /// The revert without any data.
+    Empty,
+    /// `Error(string)`, e.g. `require(condition, "message")`.
+    Error(String),
+    /// `Panic(uint256)` with the Solidity panic code.
+    Panic(U256),
+    /// A custom error from the provided contract ABI.
+    Custom {
+        name: String,
+        params: Vec<ethabi::Token>,
+    },
+    /// The data that cannot be decoded.
+    Unknown(Vec<u8>),
+}
+
+impl RevertReason {
+    pub fn decode(data: &[u8]) -> Self {
+        Self::decode_with_abi(data, None)
+    }
+
+    /// Decodes the revert data, looking up custom errors in `contract` if provided.
+    pub fn decode_with_abi(data: &[u8], contract: Option<&ethabi::Contract>) -> Self {
+        if data.is_empty() {
+            return Self::Empty;
+        }
+        if data.len() < 4 {
+            return Self::Unknown(data.to_vec());
+        }
+
+        let (selector, params) = data.split_at(4);
+        if selector == ERROR_SELECTOR {
+            if let Ok(mut tokens) = ethabi::decode(&[ethabi::ParamType::String], params) {
+                if let Some(message) = tokens.pop().and_then(|token| token.into_string()) {
+                    return Self::Error(message);
+                }
+            }
+        } else if selector == PANIC_SELECTOR && params.len() == 32 {
+            return Self::Panic(U256::from_big_endian(params));
+        } else if let Some(contract) = contract {
+            for error in contract.errors() {
+                if error.signature().as_bytes()[..4] != *selector {
+                    continue;
+                }
+                if let Ok(params) = error.decode(params) {
+                    return Self::Custom {
+                        name: error.name.clone(),
+                        params,
+                    };
+                }
+            }
+        }
+
+        Self::Unknown(data.to_vec())
+    }
+}
+
+impl std::fmt::Display for RevertReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "<empty>"),
+            Self::Error(message) => write!(f, "Error({:?})", message),
+            Self::Panic(code) => match panic_code_description(*code) {
+                Some(description) => write!(f, "Panic(0x{:02x}: {})", code, description),
+                None => write!(f, "Panic(0x{:x})", code),
+            },
+            Self::Custom { name, params } => write!(
+                f,
+                "{}({})",
+                name,
+                params
+                    .iter()
+                    .map(|param| param.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Self::Unknown(data) => write!(f, "0x{}", hex::encode(data)),
+        }
+    }
+}
+
+/// Returns the meaning of the Solidity panic code.
+pub fn panic_code_description(code: U256) -> Option<&'static str> {
+    if code > U256::from(u8::MAX) {
+        return None;
+    }
+
+    let description = match code.low_u32() {
+        0x00 => "generic compiler inserted panic",
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "conversion into an invalid enum value",
+        0x22 => "access to an incorrectly encoded storage byte array",
+        0x31 => "pop on an empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "too much memory allocated",
+        0x51 => "call to a zero-initialized internal function",
+        _ => return None,
+    };
+
+    Some(description)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler_tests::VmExecutionResult;
+
+    #[test]
+    fn selectors_match_the_signatures() {
+        assert_eq!(
+            ethabi::short_signature("Error", &[ethabi::ParamType::String]),
+            ERROR_SELECTOR
+        );
+        assert_eq!(
+            ethabi::short_signature("Panic", &[ethabi::ParamType::Uint(256)]),
+            PANIC_SELECTOR
+        );
+    }
+
+    #[test]
+    fn error_string_is_decoded() {
+        // `require(false, "not owner")`
+        let data = hex::decode(concat!(
+            "08c379a0",
+            "0000000000000000000000000000000000000000000000000000000000000020",
+            "0000000000000000000000000000000000000000000000000000000000000009",
+            "6e6f74206f776e65720000000000000000000000000000000000000000000000",
+        ))
+        .unwrap();
+
+        let reason = VmExecutionResult::Revert(data).revert_reason().unwrap();
+        assert_eq!(reason, RevertReason::Error("not owner".to_owned()));
+        assert_eq!(reason.to_string(), "Error(\"not owner\")");
+    }
+
+    #[test]
+    fn panic_code_is_decoded() {
+        // arithmetic overflow
+        let data = hex::decode(concat!(
+            "4e487b71",
+            "0000000000000000000000000000000000000000000000000000000000000011",
+        ))
+        .unwrap();
+
+        let reason = RevertReason::decode(&data);
+        assert_eq!(reason, RevertReason::Panic(U256::from(0x11)));
+        assert_eq!(
+            reason.to_string(),
+            "Panic(0x11: arithmetic overflow or underflow)"
+        );
+        assert_eq!(
+            RevertReason::Panic(U256::from(0x99)).to_string(),
+            "Panic(0x99)"
+        );
+        // the code must be a single word
+        assert!(matches!(
+            RevertReason::decode(&data[..20]),
+            RevertReason::Unknown(_)
+        ));
+    }
+
+    #[test]
+    fn custom_errors_are_decoded_with_the_abi() {
+        let contract = ethabi::Contract::load(
+            br#"[{
+                "type": "error",
+                "name": "InsufficientBalance",
+                "inputs": [
+                    { "name": "available", "type": "uint256" },
+                    { "name": "required", "type": "uint256" }
+                ]
+            }]"#
+            .as_slice(),
+        )
+        .unwrap();
+        let mut data = ethabi::short_signature(
+            "InsufficientBalance",
+            &[ethabi::ParamType::Uint(256), ethabi::ParamType::Uint(256)],
+        )
+        .to_vec();
+        data.extend(ethabi::encode(&[
+            ethabi::Token::Uint(U256::from(1)),
+            ethabi::Token::Uint(U256::from(2)),
+        ]));
+
+        let reason = RevertReason::decode_with_abi(&data, Some(&contract));
+        assert_eq!(reason.to_string(), "InsufficientBalance(1, 2)");
+        assert_eq!(RevertReason::decode(&data), RevertReason::Unknown(data));
+    }
+
+    #[test]
+    fn undecodable_data_is_kept() {
+        assert_eq!(RevertReason::decode(&[]), RevertReason::Empty);
+        assert_eq!(RevertReason::decode(&[0x08, 0xc3]).to_string(), "0x08c3");
+        assert!(VmExecutionResult::Ok(vec![]).revert_reason().is_none());
+    }
+}
@@ -1,19 +1,243 @@
-use zk_evm::aux_structures::MemoryQuery;
+use crate::full_witness_tracer::FullWitnessTracer;
+use crate::watchpoints::Watchpoints;
+use crate::{Address, U256};
+use std::collections::HashSet;
+use zk_evm::aux_structures::{DecommittmentQuery, LogQuery, MemoryQuery};
+use zk_evm::vm_state::CallStackEntry;
+use zk_evm::zk_evm_abstractions::vm::PrecompileCyclesWitness;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct MemoryLogWitnessTracer {
     pub is_dummy: bool,
     pub queries: Vec<MemoryQuery>,
+    /// If set, only the queries to these pages are recorded.
+    pub pages: Option<HashSet<u32>>,
+    /// If set, all the witness queries are additionally recorded into it.
+    pub full_witness: Option<FullWitnessTracer>,
+    /// If set, the writes to the watched locations are recorded into it.
+    pub watchpoints: Option<Watchpoints>,
 }
 
 use zk_evm::witness_trace::VmWitnessTracer;
 use zk_evm::zkevm_opcode_defs::decoding::VmEncodingMode;
 
 impl<const N: usize, E: VmEncodingMode<N>> VmWitnessTracer<N, E> for MemoryLogWitnessTracer {
-    fn add_memory_query(&mut self, _monotonic_cycle_counter: u32, memory_query: MemoryQuery) {
+    fn add_memory_query(&mut self, monotonic_cycle_counter: u32, memory_query: MemoryQuery) {
+        if let Some(full_witness) = self.full_witness.as_mut() {
+            VmWitnessTracer::<N, E>::add_memory_query(
+                full_witness,
+                monotonic_cycle_counter,
+                memory_query,
+            );
+        }
+        if let Some(watchpoints) = self.watchpoints.as_mut() {
+            watchpoints.on_memory_query(monotonic_cycle_counter, &memory_query);
+        }
         if self.is_dummy {
             return;
         }
+        if let Some(pages) = self.pages.as_ref() {
+            if !pages.contains(&memory_query.location.page.0) {
+                return;
+            }
+        }
         self.queries.push(memory_query);
     }
+
+    fn add_log_query(&mut self, monotonic_cycle_counter: u32, log_query: LogQuery) {
+        if let Some(full_witness) = self.full_witness.as_mut() {
+            VmWitnessTracer::<N, E>::add_log_query(
+                full_witness,
+                monotonic_cycle_counter,
+                log_query,
+            );
+        }
+        if let Some(watchpoints) = self.watchpoints.as_mut() {
+            watchpoints.on_log_query(monotonic_cycle_counter, &log_query);
+        }
+    }
+
+    fn execute_decommittment(
+        &mut self,
+        monotonic_cycle_counter: u32,
+        decommittment_query: DecommittmentQuery,
+        mem_witness: Vec<U256>,
+    ) {
+        if let Some(full_witness) = self.full_witness.as_mut() {
+            VmWitnessTracer::<N, E>::execute_decommittment(
+                full_witness,
+                monotonic_cycle_counter,
+                decommittment_query,
+                mem_witness,
+            );
+        }
+    }
+
+    fn add_precompile_call_result(
+        &mut self,
+        monotonic_cycle_counter: u32,
+        call_params: LogQuery,
+        mem_witness_in: Vec<MemoryQuery>,
+        memory_witness_out: Vec<MemoryQuery>,
+        round_witness: PrecompileCyclesWitness,
+    ) {
+        if let Some(full_witness) = self.full_witness.as_mut() {
+            VmWitnessTracer::<N, E>::add_precompile_call_result(
+                full_witness,
+                monotonic_cycle_counter,
+                call_params,
+                mem_witness_in,
+                memory_witness_out,
+                round_witness,
+            );
+        }
+    }
+
+    fn start_new_execution_context(
+        &mut self,
+        monotonic_cycle_counter: u32,
+        previous_context: &CallStackEntry<N, E>,
+        new_context: &CallStackEntry<N, E>,
+    ) {
+        if let Some(full_witness) = self.full_witness.as_mut() {
+            VmWitnessTracer::<N, E>::start_new_execution_context(
+                full_witness,
+                monotonic_cycle_counter,
+                previous_context,
+                new_context,
+            );
+        }
+    }
+
+    fn finish_execution_context(&mut self, monotonic_cycle_counter: u32, panicked: bool) {
+        if let Some(full_witness) = self.full_witness.as_mut() {
+            VmWitnessTracer::<N, E>::finish_execution_context(
+                full_witness,
+                monotonic_cycle_counter,
+                panicked,
+            );
+        }
+    }
+}
+
+///
+/// Selects the memory queries collected during a run.
+///
+#[derive(Clone, Debug, Default)]
+pub struct MemoryQueryFilter {
+    /// If set, only the queries to these pages are collected.
+    pub pages: Option<HashSet<u32>>,
+    /// If set, only the queries to the pages of the frames of these addresses are collected.
+    pub frames: Option<HashSet<Address>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler_tests::{VmRunOptions, VmSnapshot};
+    use crate::default_environment::INITIAL_BASE_PAGE;
+    use crate::test_utils::{add_imm, assemble, far_call, ret_ok, run, uma};
+    use crate::U256;
+    use std::collections::HashMap;
+    use zk_evm::aux_structures::MemoryPage;
+    use zk_evm::vm_state::{
+        aux_heap_page_from_base, code_page_candidate_from_base, heap_page_from_base,
+        stack_page_from_base,
+    };
+    use zk_evm::zkevm_opcode_defs::UMAOpcode;
+
+    const CALLEE: u64 = 0x1234;
+    const CALLER: u64 = 0x10000;
+
+    /// `heap[0] = value`
+    fn write_heap(value: u16) -> [u64; 2] {
+        [add_imm(value, 3), uma(UMAOpcode::HeapWrite, (0, 3, 0, 0))]
+    }
+
+    /// Both the caller and the callee write their heap.
+    fn run_with_filter(filter: MemoryQueryFilter) -> VmSnapshot {
+        let mut caller = write_heap(0x42).to_vec();
+        // the exception handler is at pc 8
+        caller.extend(far_call(CALLEE as u16, 8));
+        caller.extend([ret_ok(), ret_ok()]);
+        let mut callee = write_heap(0x43).to_vec();
+        callee.push(ret_ok());
+
+        run(
+            HashMap::from([
+                (Address::from_low_u64_be(CALLER), assemble(&caller)),
+                (Address::from_low_u64_be(CALLEE), assemble(&callee)),
+            ]),
+            Address::from_low_u64_be(CALLER),
+            VmRunOptions {
+                memory_queries: Some(filter),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn heap_writes(queries: &[MemoryQuery]) -> Vec<(u32, U256)> {
+        queries
+            .iter()
+            .filter(|query| query.rw_flag && query.location.index.0 == 0)
+            .map(|query| (query.location.page.0, query.value))
+            .collect()
+    }
+
+    #[test]
+    fn queries_are_filtered_by_page() {
+        let caller_heap_page = heap_page_from_base(MemoryPage(INITIAL_BASE_PAGE)).0;
+        let snapshot = run_with_filter(MemoryQueryFilter {
+            pages: Some(HashSet::from([caller_heap_page])),
+            frames: None,
+        });
+
+        assert!(snapshot
+            .memory_queries
+            .iter()
+            .all(|query| query.location.page.0 == caller_heap_page));
+        assert_eq!(
+            heap_writes(&snapshot.memory_queries),
+            vec![(caller_heap_page, U256::from(0x42))]
+        );
+    }
+
+    #[test]
+    fn queries_are_filtered_by_frame() {
+        let snapshot = run_with_filter(MemoryQueryFilter {
+            pages: None,
+            frames: Some(HashSet::from([Address::from_low_u64_be(CALLEE)])),
+        });
+        let calls: Vec<_> = snapshot.call_tree.far_calls().collect();
+        assert_eq!(calls.len(), 1);
+        let callee_base_page = MemoryPage(calls[0].base_memory_page);
+        let callee_heap_page = heap_page_from_base(callee_base_page).0;
+
+        assert!(!snapshot.memory_queries.is_empty());
+        let callee_pages = [
+            code_page_candidate_from_base(callee_base_page).0,
+            stack_page_from_base(callee_base_page).0,
+            callee_heap_page,
+            aux_heap_page_from_base(callee_base_page).0,
+        ];
+        assert!(snapshot
+            .memory_queries
+            .iter()
+            .all(|query| callee_pages.contains(&query.location.page.0)));
+        assert_eq!(
+            heap_writes(&snapshot.memory_queries),
+            vec![(callee_heap_page, U256::from(0x43))]
+        );
+    }
+
+    #[test]
+    fn all_queries_are_kept_without_a_filter() {
+        let snapshot = run_with_filter(MemoryQueryFilter::default());
+        let caller_heap_page = heap_page_from_base(MemoryPage(INITIAL_BASE_PAGE)).0;
+
+        let writes = heap_writes(&snapshot.memory_queries);
+        assert_eq!(writes.len(), 2);
+        assert_eq!(writes[0], (caller_heap_page, U256::from(0x42)));
+        assert_eq!(writes[1].1, U256::from(0x43));
+    }
 }
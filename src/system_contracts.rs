@@ -0,0 +1,736 @@
+use crate::bytecode::bytecode_hash;
+use crate::compiler_tests::StorageKey;
+use crate::{Address, H160, H256, U256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use zk_evm::zkevm_opcode_defs::sha3::{Digest, Keccak256};
+use zk_evm::zkevm_opcode_defs::system_params::DEPLOYER_SYSTEM_CONTRACT_ADDRESS;
+
+// In zk_evm@1.5.0 the "deployer address" constant is incorrect and it points to the account code storage.
+// so we duplicate those here.
+pub const CONTRACT_DEPLOYER_ADDRESS: Address = H160([
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x80, 0x06,
+]);
+
+pub const KNOWN_CODES_STORAGE_ADDRESS: Address = H160([
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x80, 0x04,
+]);
+
+pub const IMMUTABLE_SIMULATOR_ADDRESS: Address = H160([
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x80, 0x05,
+]);
+
+pub const SYSTEM_CONTEXT_ADDRESS: Address = H160([
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x80, 0x0b,
+]);
+
+pub const L2_BASE_TOKEN_ADDRESS: Address = H160([
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x80, 0x0a,
+]);
+
+pub const NONCE_HOLDER_ADDRESS: Address = H160([
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x80, 0x03,
+]);
+
+const BOOTLOADER_ADDRESS: Address = H160([
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x80, 0x01,
+]);
+
+// The slots of the SystemContext contract variables.
+const SYSTEM_CONTEXT_CHAIN_ID_POSITION: u64 = 0;
+const SYSTEM_CONTEXT_COINBASE_POSITION: u64 = 4;
+const SYSTEM_CONTEXT_BASE_FEE_POSITION: u64 = 6;
+const SYSTEM_CONTEXT_BATCH_INFO_POSITION: u64 = 8;
+const SYSTEM_CONTEXT_CURRENT_L2_BLOCK_INFO_POSITION: u64 = 9;
+// The `l2BlockHash[257]` array takes the slots 11..=267.
+// `block.number` and `block.timestamp` are read from the virtual L2 block.
+const SYSTEM_CONTEXT_CURRENT_VIRTUAL_L2_BLOCK_INFO_POSITION: u64 = 268;
+// Follows the virtual block upgrade info at slot 269 and the tx number in block at slot 270.
+const SYSTEM_CONTEXT_GAS_PER_PUBDATA_POSITION: u64 = 271;
+
+// The slots of the L2BaseToken contract variables.
+const L2_BASE_TOKEN_BALANCE_POSITION: u64 = 0;
+const L2_BASE_TOKEN_TOTAL_SUPPLY_POSITION: u64 = 1;
+
+// The slot of the ImmutableSimulator immutables mapping.
+const IMMUTABLE_SIMULATOR_STORAGE_POSITION: u64 = 0;
+
+// The slot of the NonceHolder raw nonces mapping.
+const NONCE_HOLDER_RAW_NONCES_POSITION: u64 = 0;
+
+fn u256_to_h256(value: U256) -> H256 {
+    let mut buffer = [0u8; 32];
+    value.to_big_endian(&mut buffer);
+    H256::from(buffer)
+}
+
+fn address_to_h256(address: Address) -> H256 {
+    H256::from(address)
+}
+
+/// The slot of `mapping[key]` for the mapping declared at `position`.
+fn mapping_slot(key: H256, position: U256) -> U256 {
+    let mut hasher = Keccak256::new();
+    hasher.update(key.as_bytes());
+    hasher.update(u256_to_h256(position).as_bytes());
+
+    U256::from_big_endian(&hasher.finalize()[..])
+}
+
+/// The storage key of the code hash of the address in the AccountCodeStorage.
+pub fn account_code_key(address: Address) -> StorageKey {
+    StorageKey {
+        address: *DEPLOYER_SYSTEM_CONTRACT_ADDRESS,
+        key: U256::from_big_endian(address.as_bytes()),
+    }
+}
+
+///
+/// Registers the deployed code of the address: sets its code hash in the AccountCodeStorage and
+/// marks the hash as known in the KnownCodesStorage.
+///
+pub fn register_code_hash(
+    storage: &mut HashMap<StorageKey, H256>,
+    address: Address,
+    bytecode_hash: H256,
+) {
+    storage.insert(account_code_key(address), bytecode_hash);
+    storage.insert(known_code_key(bytecode_hash), H256::from_low_u64_be(1));
+}
+
+/// The storage key of the marker of the bytecode hash in the KnownCodesStorage.
+pub fn known_code_key(bytecode_hash: H256) -> StorageKey {
+    StorageKey {
+        address: KNOWN_CODES_STORAGE_ADDRESS,
+        key: U256::from_big_endian(bytecode_hash.as_bytes()),
+    }
+}
+
+/// The storage key of the immutable with the index of the contract.
+pub fn immutable_key(address: Address, index: U256) -> StorageKey {
+    let contract_slot = mapping_slot(
+        address_to_h256(address),
+        U256::from(IMMUTABLE_SIMULATOR_STORAGE_POSITION),
+    );
+
+    StorageKey {
+        address: IMMUTABLE_SIMULATOR_ADDRESS,
+        key: mapping_slot(u256_to_h256(index), contract_slot),
+    }
+}
+
+/// The address assigned by the ContractDeployer `create2`.
+pub fn create2_address(
+    deployer: Address,
+    salt: H256,
+    bytecode_hash: H256,
+    constructor_calldata: &[u8],
+) -> Address {
+    let prefix = Keccak256::digest(b"zksyncCreate2");
+    let input_hash = Keccak256::digest(constructor_calldata);
+
+    let mut hasher = Keccak256::new();
+    hasher.update(prefix);
+    hasher.update(address_to_h256(deployer).as_bytes());
+    hasher.update(salt.as_bytes());
+    hasher.update(bytecode_hash.as_bytes());
+    hasher.update(input_hash);
+
+    Address::from_slice(&hasher.finalize()[12..])
+}
+
+/// The storage key of the base token balance of the address.
+pub fn balance_key(address: Address) -> StorageKey {
+    StorageKey {
+        address: L2_BASE_TOKEN_ADDRESS,
+        key: mapping_slot(
+            address_to_h256(address),
+            U256::from(L2_BASE_TOKEN_BALANCE_POSITION),
+        ),
+    }
+}
+
+///
+/// Writes the base token balances into the storage. The total supply is increased by the sum
+/// of the balances, so that the token invariants hold for the transfers. Without balances,
+/// the storage is left untouched.
+///
+pub fn write_balances(balances: &HashMap<Address, U256>, storage: &mut HashMap<StorageKey, H256>) {
+    if balances.is_empty() {
+        return;
+    }
+
+    let total_supply_key = StorageKey {
+        address: L2_BASE_TOKEN_ADDRESS,
+        key: U256::from(L2_BASE_TOKEN_TOTAL_SUPPLY_POSITION),
+    };
+    let mut total_supply = storage
+        .get(&total_supply_key)
+        .map(|value| U256::from_big_endian(value.as_bytes()))
+        .unwrap_or_default();
+
+    for (address, balance) in balances.iter() {
+        let key = balance_key(*address);
+        if let Some(previous) = storage.get(&key) {
+            total_supply = total_supply.saturating_sub(U256::from_big_endian(previous.as_bytes()));
+        }
+        total_supply += *balance;
+        storage.insert(key, u256_to_h256(*balance));
+    }
+
+    storage.insert(total_supply_key, u256_to_h256(total_supply));
+}
+
+/// The base token balance of the address in the storage.
+pub fn read_balance(storage: &HashMap<StorageKey, H256>, address: Address) -> U256 {
+    storage
+        .get(&balance_key(address))
+        .map(|value| U256::from_big_endian(value.as_bytes()))
+        .unwrap_or_default()
+}
+
+///
+/// The nonces of an account. The NonceHolder packs them into a single slot, with the deployment
+/// nonce in the upper 128 bits.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct AccountNonces {
+    pub tx_nonce: u128,
+    pub deployment_nonce: u128,
+}
+
+impl AccountNonces {
+    pub fn new(tx_nonce: u128, deployment_nonce: u128) -> Self {
+        Self {
+            tx_nonce,
+            deployment_nonce,
+        }
+    }
+
+    ///
+    /// The nonces from 256-bit values, as the contracts see them. The NonceHolder keeps each
+    /// nonce in 128 bits, so larger values are rejected.
+    ///
+    pub fn from_u256(tx_nonce: U256, deployment_nonce: U256) -> anyhow::Result<Self> {
+        let to_u128 = |value: U256, name: &str| {
+            if value > U256::from(u128::MAX) {
+                anyhow::bail!("The {} nonce {} does not fit into 128 bits", name, value);
+            }
+            Ok(value.low_u128())
+        };
+
+        Ok(Self {
+            tx_nonce: to_u128(tx_nonce, "tx")?,
+            deployment_nonce: to_u128(deployment_nonce, "deployment")?,
+        })
+    }
+
+    fn pack(self) -> U256 {
+        (U256::from(self.deployment_nonce) << 128) + U256::from(self.tx_nonce)
+    }
+
+    fn unpack(value: U256) -> Self {
+        Self {
+            tx_nonce: value.low_u128(),
+            deployment_nonce: (value >> 128).low_u128(),
+        }
+    }
+}
+
+/// The storage key of the packed nonces of the address.
+pub fn nonces_key(address: Address) -> StorageKey {
+    StorageKey {
+        address: NONCE_HOLDER_ADDRESS,
+        key: mapping_slot(
+            address_to_h256(address),
+            U256::from(NONCE_HOLDER_RAW_NONCES_POSITION),
+        ),
+    }
+}
+
+/// Writes the nonces into the NonceHolder storage.
+pub fn write_nonces(
+    nonces: &HashMap<Address, AccountNonces>,
+    storage: &mut HashMap<StorageKey, H256>,
+) {
+    for (address, nonces) in nonces.iter() {
+        storage.insert(nonces_key(*address), u256_to_h256(nonces.pack()));
+    }
+}
+
+/// The nonces of the address in the storage.
+pub fn read_nonces(storage: &HashMap<StorageKey, H256>, address: Address) -> AccountNonces {
+    storage
+        .get(&nonces_key(address))
+        .map(|value| AccountNonces::unpack(U256::from_big_endian(value.as_bytes())))
+        .unwrap_or_default()
+}
+
+///
+/// The block the run happens in, as seen by the contracts through the SystemContext contract.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockContext {
+    pub block_number: u64,
+    pub timestamp: u64,
+    pub base_fee: U256,
+    pub gas_per_pubdata: U256,
+    pub coinbase: Address,
+    pub chain_id: U256,
+}
+
+impl Default for BlockContext {
+    fn default() -> Self {
+        Self {
+            block_number: 1,
+            timestamp: 1,
+            base_fee: U256::from(250_000_000u64),
+            gas_per_pubdata: U256::from(800u64),
+            coinbase: BOOTLOADER_ADDRESS,
+            chain_id: U256::from(270u64),
+        }
+    }
+}
+
+impl BlockContext {
+    fn slot(position: u64) -> StorageKey {
+        StorageKey {
+            address: SYSTEM_CONTEXT_ADDRESS,
+            key: U256::from(position),
+        }
+    }
+
+    ///
+    /// The SystemContext storage entries. The batch, the L2 block and the virtual L2 block
+    /// are all set to this block.
+    ///
+    pub fn storage_entries(&self) -> HashMap<StorageKey, H256> {
+        // `BlockInfo { uint128 timestamp; uint128 number; }`
+        let block_info = (U256::from(self.block_number) << 128) + U256::from(self.timestamp);
+
+        HashMap::from([
+            (
+                Self::slot(SYSTEM_CONTEXT_CHAIN_ID_POSITION),
+                u256_to_h256(self.chain_id),
+            ),
+            (
+                Self::slot(SYSTEM_CONTEXT_COINBASE_POSITION),
+                address_to_h256(self.coinbase),
+            ),
+            (
+                Self::slot(SYSTEM_CONTEXT_BASE_FEE_POSITION),
+                u256_to_h256(self.base_fee),
+            ),
+            (
+                Self::slot(SYSTEM_CONTEXT_BATCH_INFO_POSITION),
+                u256_to_h256(block_info),
+            ),
+            (
+                Self::slot(SYSTEM_CONTEXT_CURRENT_L2_BLOCK_INFO_POSITION),
+                u256_to_h256(block_info),
+            ),
+            (
+                Self::slot(SYSTEM_CONTEXT_CURRENT_VIRTUAL_L2_BLOCK_INFO_POSITION),
+                u256_to_h256(block_info),
+            ),
+            (
+                Self::slot(SYSTEM_CONTEXT_GAS_PER_PUBDATA_POSITION),
+                u256_to_h256(self.gas_per_pubdata),
+            ),
+        ])
+    }
+
+    /// Writes the block into the storage, overwriting the existing SystemContext entries.
+    pub fn write_into(&self, storage: &mut HashMap<StorageKey, H256>) {
+        storage.extend(self.storage_entries());
+    }
+}
+
+/// The system contracts by the artifact name, along with their addresses.
+pub const SYSTEM_CONTRACT_ARTIFACTS: [(&str, u32); 25] = [
+    ("Ecrecover", 0x0001),
+    ("SHA256", 0x0002),
+    ("Identity", 0x0004),
+    ("EcAdd", 0x0006),
+    ("EcMul", 0x0007),
+    ("EcPairing", 0x0008),
+    ("P256Verify", 0x0100),
+    ("AccountCodeStorage", 0x8002),
+    ("NonceHolder", 0x8003),
+    ("KnownCodesStorage", 0x8004),
+    ("ImmutableSimulator", 0x8005),
+    ("ContractDeployer", 0x8006),
+    ("L1Messenger", 0x8008),
+    ("MsgValueSimulator", 0x8009),
+    ("L2BaseToken", 0x800a),
+    ("SystemContext", 0x800b),
+    ("BootloaderUtilities", 0x800c),
+    ("EventWriter", 0x800d),
+    ("Compressor", 0x800e),
+    ("ComplexUpgrader", 0x800f),
+    ("Keccak256", 0x8010),
+    ("PubdataChunkPublisher", 0x8011),
+    ("CodeOracle", 0x8012),
+    ("EvmGasManager", 0x8013),
+    ("Create2Factory", 0x10000),
+];
+
+///
+/// The system contracts loaded from the compiled artifacts.
+///
+#[derive(Debug, Clone, Default)]
+pub struct SystemContracts {
+    pub contracts: HashMap<Address, Vec<u8>>,
+    /// The code hashes of the contracts in the AccountCodeStorage and KnownCodesStorage.
+    pub storage: HashMap<StorageKey, H256>,
+}
+
+impl SystemContracts {
+    ///
+    /// Loads the system contracts from the directory of the compiled artifacts.
+    ///
+    /// The directory is searched recursively for `<Name>.json` artifacts with the hex `bytecode`
+    /// field and for `<Name>.zbin` or `<Name>.yul.zbin` raw bytecode files, where `<Name>` is
+    /// one of `SYSTEM_CONTRACT_ARTIFACTS`. The contracts without an artifact are skipped.
+    /// Two artifacts of the same kind with the same name are rejected as ambiguous, and the
+    /// symlinked directories are not followed.
+    ///
+    pub fn load(directory: &Path) -> anyhow::Result<Self> {
+        let mut files = HashMap::new();
+        collect_artifact_files(directory, &mut files)?;
+
+        let mut result = Self::default();
+        for (name, address) in SYSTEM_CONTRACT_ARTIFACTS.iter() {
+            let Some(path) = files.get(*name).and_then(ArtifactFiles::preferred) else {
+                continue;
+            };
+            let bytecode = read_artifact(path)
+                .map_err(|error| anyhow::anyhow!("Artifact {}: {}", path.display(), error))?;
+            let hash = bytecode_hash(&bytecode)
+                .map_err(|error| anyhow::anyhow!("Artifact {}: {}", path.display(), error))?;
+
+            let address = Address::from_low_u64_be(u64::from(*address));
+            register_code_hash(&mut result.storage, address, hash);
+            result.contracts.insert(address, bytecode);
+        }
+
+        Ok(result)
+    }
+}
+
+/// The artifact files of a system contract: the JSON one and the raw bytecode one.
+#[derive(Debug, Default)]
+struct ArtifactFiles {
+    json: Option<PathBuf>,
+    raw: Option<PathBuf>,
+}
+
+impl ArtifactFiles {
+    /// The JSON artifacts are preferred over the raw ones.
+    fn preferred(&self) -> Option<&PathBuf> {
+        self.json.as_ref().or(self.raw.as_ref())
+    }
+}
+
+fn collect_artifact_files(
+    directory: &Path,
+    files: &mut HashMap<String, ArtifactFiles>,
+) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_artifact_files(&path, files)?;
+            continue;
+        }
+        // symlinked directories may form loops
+        if file_type.is_symlink() && path.is_dir() {
+            continue;
+        }
+
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Some((name, is_json)) = [(".yul.zbin", false), (".zbin", false), (".json", true)]
+            .iter()
+            .find_map(|(extension, is_json)| {
+                file_name
+                    .strip_suffix(extension)
+                    .map(|name| (name, *is_json))
+            })
+        else {
+            continue;
+        };
+        if !SYSTEM_CONTRACT_ARTIFACTS
+            .iter()
+            .any(|(artifact_name, _)| *artifact_name == name)
+        {
+            continue;
+        }
+
+        let artifact_files = files.entry(name.to_owned()).or_default();
+        let slot = if is_json {
+            &mut artifact_files.json
+        } else {
+            &mut artifact_files.raw
+        };
+        if let Some(existing) = slot {
+            anyhow::bail!(
+                "Ambiguous artifacts of {}: {} and {}",
+                name,
+                existing.display(),
+                path.display()
+            );
+        }
+        *slot = Some(path);
+    }
+
+    Ok(())
+}
+
+fn read_artifact(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let content = std::fs::read(path)?;
+    if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+        return Ok(content);
+    }
+
+    let artifact: serde_json::Value = serde_json::from_slice(&content)?;
+    let bytecode = artifact
+        .get("bytecode")
+        .and_then(|bytecode| bytecode.as_str())
+        .ok_or_else(|| anyhow::anyhow!("No `bytecode` field"))?;
+
+    Ok(hex::decode(bytecode.trim_start_matches("0x"))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{assemble, ret_ok, ret_panic};
+
+    /// A directory removed when dropped, unique per test.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("zkevm_tester_{}_{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn write(&self, relative_path: &str, content: &[u8]) -> PathBuf {
+            let path = self.0.join(relative_path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, content).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn json_artifact(bytecode: &[u8]) -> Vec<u8> {
+        serde_json::json!({ "bytecode": format!("0x{}", hex::encode(bytecode)) })
+            .to_string()
+            .into_bytes()
+    }
+
+    #[test]
+    fn block_context_writes_the_system_context_slots() {
+        let context = BlockContext {
+            block_number: 7,
+            timestamp: 1_000,
+            base_fee: U256::from(3u64),
+            gas_per_pubdata: U256::from(50u64),
+            coinbase: Address::from_low_u64_be(0xc0ffee),
+            chain_id: U256::from(270u64),
+        };
+        let block_info = (U256::from(7u64) << 128) + U256::from(1_000u64);
+
+        let mut entries: Vec<(u64, H256)> = context
+            .storage_entries()
+            .into_iter()
+            .map(|(key, value)| {
+                assert_eq!(key.address, SYSTEM_CONTEXT_ADDRESS);
+                (key.key.as_u64(), value)
+            })
+            .collect();
+        entries.sort();
+
+        assert_eq!(
+            entries,
+            vec![
+                (0, u256_to_h256(U256::from(270u64))),
+                (4, address_to_h256(Address::from_low_u64_be(0xc0ffee))),
+                (6, u256_to_h256(U256::from(3u64))),
+                (8, u256_to_h256(block_info)),
+                (9, u256_to_h256(block_info)),
+                (268, u256_to_h256(block_info)),
+                (271, u256_to_h256(U256::from(50u64))),
+            ]
+        );
+    }
+
+    #[test]
+    fn balances_are_written_along_with_the_total_supply() {
+        let total_supply_key = StorageKey {
+            address: L2_BASE_TOKEN_ADDRESS,
+            key: U256::from(1u64),
+        };
+        let alice = Address::from_low_u64_be(0xa11ce);
+        let bob = Address::from_low_u64_be(0xb0b);
+
+        let mut storage = HashMap::new();
+        write_balances(&HashMap::new(), &mut storage);
+        assert!(storage.is_empty());
+
+        // an existing balance is replaced, and the total supply of the others is kept
+        storage.insert(balance_key(alice), u256_to_h256(U256::from(10u64)));
+        storage.insert(total_supply_key, u256_to_h256(U256::from(1_010u64)));
+        write_balances(
+            &HashMap::from([(alice, U256::from(300u64)), (bob, U256::from(25u64))]),
+            &mut storage,
+        );
+
+        assert_eq!(
+            storage[&total_supply_key],
+            u256_to_h256(U256::from(1_325u64))
+        );
+        let expected_slot = |address: Address| {
+            let mut preimage = [0u8; 64];
+            preimage[12..32].copy_from_slice(address.as_bytes());
+            U256::from_big_endian(&Keccak256::digest(preimage)[..])
+        };
+        for (address, balance) in [(alice, 300u64), (bob, 25u64)] {
+            let key = balance_key(address);
+            assert_eq!(key.address, L2_BASE_TOKEN_ADDRESS);
+            assert_eq!(key.key, expected_slot(address));
+            assert_eq!(storage[&key], u256_to_h256(U256::from(balance)));
+            assert_eq!(read_balance(&storage, address), U256::from(balance));
+        }
+    }
+
+    #[test]
+    fn nonces_are_packed_with_the_deployment_nonce_in_the_upper_half() {
+        let nonces = AccountNonces::new(0x1122, u128::MAX - 1);
+        let packed = nonces.pack();
+
+        assert_eq!(packed.low_u128(), 0x1122);
+        assert_eq!((packed >> 128).low_u128(), u128::MAX - 1);
+        assert_eq!(AccountNonces::unpack(packed), nonces);
+
+        let address = Address::from_low_u64_be(0xabcd);
+        let mut storage = HashMap::new();
+        write_nonces(&HashMap::from([(address, nonces)]), &mut storage);
+        assert_eq!(storage[&nonces_key(address)], u256_to_h256(packed));
+        assert_eq!(read_nonces(&storage, address), nonces);
+        assert_eq!(
+            read_nonces(&storage, Address::from_low_u64_be(0xabce)),
+            AccountNonces::default()
+        );
+    }
+
+    #[test]
+    fn nonces_above_128_bits_are_rejected() {
+        let max = U256::from(u128::MAX);
+        assert_eq!(
+            AccountNonces::from_u256(max, U256::from(3u64)).unwrap(),
+            AccountNonces::new(u128::MAX, 3)
+        );
+        assert!(AccountNonces::from_u256(max + 1, U256::zero()).is_err());
+        assert!(AccountNonces::from_u256(U256::zero(), max + 1).is_err());
+    }
+
+    #[test]
+    fn artifacts_are_loaded_at_their_addresses() {
+        let directory = TempDir::new("artifacts_are_loaded");
+        let json_bytecode = assemble(&[ret_ok()]);
+        let raw_bytecode = assemble(&[ret_panic()]);
+        directory.write("contracts/NonceHolder.json", &json_artifact(&json_bytecode));
+        // the JSON artifact is preferred over the raw one
+        directory.write("yul/NonceHolder.yul.zbin", &raw_bytecode);
+        directory.write("yul/Keccak256.yul.zbin", &raw_bytecode);
+        // above the 16-bit addresses
+        directory.write("Create2Factory.zbin", &raw_bytecode);
+        directory.write("Unknown.json", b"not an artifact");
+
+        let loaded = SystemContracts::load(&directory.0).unwrap();
+
+        let expected = [
+            (0x8003, &json_bytecode),
+            (0x8010, &raw_bytecode),
+            (0x10000, &raw_bytecode),
+        ];
+        assert_eq!(loaded.contracts.len(), expected.len());
+        for (address, bytecode) in expected {
+            let address = Address::from_low_u64_be(address);
+            assert_eq!(&loaded.contracts[&address], bytecode);
+
+            let hash = bytecode_hash(bytecode).unwrap();
+            assert_eq!(loaded.storage[&account_code_key(address)], hash);
+            assert_eq!(
+                loaded.storage[&known_code_key(hash)],
+                H256::from_low_u64_be(1)
+            );
+        }
+    }
+
+    #[test]
+    fn ambiguous_artifacts_are_rejected() {
+        let directory = TempDir::new("ambiguous_artifacts");
+        let bytecode = assemble(&[ret_ok()]);
+        directory.write("a/NonceHolder.json", &json_artifact(&bytecode));
+        directory.write("b/NonceHolder.json", &json_artifact(&bytecode));
+
+        let error = SystemContracts::load(&directory.0).unwrap_err().to_string();
+        assert!(
+            error.contains("Ambiguous artifacts of NonceHolder"),
+            "unexpected error: {}",
+            error
+        );
+    }
+
+    #[test]
+    fn invalid_artifacts_are_reported_with_the_path() {
+        let directory = TempDir::new("invalid_artifacts");
+        let path = directory.write("NonceHolder.zbin", &[0u8; 64]);
+
+        let error = SystemContracts::load(&directory.0).unwrap_err().to_string();
+        assert!(
+            error.contains(&path.display().to_string()),
+            "unexpected error: {}",
+            error
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_directories_are_skipped() {
+        let directory = TempDir::new("symlinked_directories");
+        let bytecode = assemble(&[ret_ok()]);
+        directory.write("contracts/NonceHolder.json", &json_artifact(&bytecode));
+        // an alias of the artifacts, and a loop back to the root
+        std::os::unix::fs::symlink(directory.0.join("contracts"), directory.0.join("alias"))
+            .unwrap();
+        std::os::unix::fs::symlink(&directory.0, directory.0.join("contracts/root")).unwrap();
+
+        let loaded = SystemContracts::load(&directory.0).unwrap();
+        assert_eq!(
+            loaded.contracts.keys().collect::<Vec<_>>(),
+            vec![&Address::from_low_u64_be(0x8003)]
+        );
+    }
+}
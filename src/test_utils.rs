@@ -0,0 +1,86 @@
+use crate::bytecode::bytecode_to_words;
+use crate::compiler_tests::{
+    run_vm_multi_contracts_with_options, VmLaunchOption, VmRunOptions, VmSnapshot,
+};
+use crate::vm_session::VmSession;
+use crate::{Address, U256};
+use std::collections::HashMap;
+
+pub(crate) use crate::assembly::{
+    add_imm, assemble, code_hash, far_call, instruction, near_call, ret_heap, ret_ok, ret_panic,
+    static_far_call, uma,
+};
+
+///
+/// The contract that only returns, used as the default account and the EVM simulator, whose
+/// code hashes the VM requires to be well-formed.
+///
+pub(crate) fn default_code() -> (U256, Vec<u8>) {
+    let bytecode = assemble(&[ret_ok()]);
+
+    (code_hash(&bytecode), bytecode)
+}
+
+/// Runs `contracts` from `entry_address` to the end, with the contracts registered.
+pub(crate) fn run(
+    contracts: HashMap<Address, Vec<u8>>,
+    entry_address: Address,
+    options: VmRunOptions,
+) -> VmSnapshot {
+    let (default_code_hash, default_code) = default_code();
+
+    run_vm_multi_contracts_with_options(
+        String::new(),
+        contracts,
+        &[],
+        HashMap::new(),
+        HashMap::new(),
+        entry_address,
+        None,
+        VmLaunchOption::Default,
+        10_000,
+        HashMap::from([(default_code_hash, default_code)]),
+        HashMap::new(),
+        default_code_hash,
+        default_code_hash,
+        VmRunOptions {
+            register_contracts: true,
+            ..options
+        },
+    )
+    .unwrap()
+}
+
+/// The session running `contracts` from `entry_address`, with the contracts registered.
+pub(crate) fn session(
+    contracts: HashMap<Address, Vec<u8>>,
+    entry_address: Address,
+    options: VmRunOptions,
+) -> VmSession {
+    let contracts = contracts
+        .into_iter()
+        .map(|(address, bytecode)| (address, bytecode_to_words(&bytecode).unwrap()))
+        .collect();
+    let (default_code_hash, default_code) = default_code();
+    let known_contracts =
+        HashMap::from([(default_code_hash, bytecode_to_words(&default_code).unwrap())]);
+
+    VmSession::new(
+        contracts,
+        &[],
+        HashMap::new(),
+        HashMap::new(),
+        entry_address,
+        None,
+        VmLaunchOption::Default,
+        known_contracts,
+        HashMap::new(),
+        default_code_hash,
+        default_code_hash,
+        VmRunOptions {
+            register_contracts: true,
+            ..options
+        },
+    )
+    .unwrap()
+}
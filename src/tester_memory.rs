@@ -0,0 +1,338 @@
+use crate::hashmap_based_memory::SimpleHashmapMemory;
+use crate::paged_memory::PagedMemory;
+use crate::U256;
+
+use zk_evm::abstractions::Memory;
+use zk_evm::aux_structures::MemoryQuery;
+use zk_evm::vm_state::PrimitiveValue;
+use zk_evm::zkevm_opcode_defs::FatPointer;
+
+///
+/// Byte-granular access on top of the word slots of a memory, for the tester to read and write
+/// the heap and the calldata the way the contracts see them.
+///
+pub trait ByteAddressableMemory {
+    fn read_slot(&self, page_number: u32, slot: u32) -> PrimitiveValue;
+
+    /// Writes the word as is, without going through the VM.
+    fn write_slot(&mut self, page_number: u32, slot: u32, value: PrimitiveValue);
+
+    /// Reads `length` bytes starting at the byte `offset`, which does not have to be word-aligned.
+    fn read_bytes(&self, page_number: u32, offset: u32, length: u32) -> Vec<u8> {
+        self.read_bytes_within_bound(page_number, offset, length, u32::MAX)
+    }
+
+    /// Same as `read_bytes`, but the bytes at or beyond `bound` read as zeroes,
+    /// which is how the heap looks like to a frame with the given heap bound.
+    fn read_bytes_within_bound(
+        &self,
+        page_number: u32,
+        offset: u32,
+        length: u32,
+        bound: u32,
+    ) -> Vec<u8> {
+        let mut result = Vec::with_capacity(length as usize);
+        let end = offset as u64 + length as u64;
+        let mut position = offset as u64;
+        let mut buffer = [0u8; 32];
+        while position < end {
+            let in_word = (position % 32) as usize;
+            let to_take = std::cmp::min(32 - in_word, (end - position) as usize);
+            self.read_slot(page_number, (position / 32) as u32)
+                .value
+                .to_big_endian(&mut buffer);
+            for (index, byte) in buffer[in_word..(in_word + to_take)].iter().enumerate() {
+                if position + (index as u64) < bound as u64 {
+                    result.push(*byte);
+                } else {
+                    result.push(0);
+                }
+            }
+            position += to_take as u64;
+        }
+
+        result
+    }
+
+    /// Writes the bytes starting at the byte `offset`. The touched words lose the pointer flag.
+    fn write_bytes(&mut self, page_number: u32, offset: u32, bytes: &[u8]) {
+        let end = offset as u64 + bytes.len() as u64;
+        let mut position = offset as u64;
+        let mut bytes = bytes;
+        let mut buffer = [0u8; 32];
+        while position < end {
+            let in_word = (position % 32) as usize;
+            let to_take = std::cmp::min(32 - in_word, (end - position) as usize);
+            let slot = (position / 32) as u32;
+            self.read_slot(page_number, slot)
+                .value
+                .to_big_endian(&mut buffer);
+            buffer[in_word..(in_word + to_take)].copy_from_slice(&bytes[..to_take]);
+            self.write_slot(
+                page_number,
+                slot,
+                PrimitiveValue::from_value(U256::from_big_endian(&buffer)),
+            );
+            bytes = &bytes[to_take..];
+            position += to_take as u64;
+        }
+    }
+
+    /// Reads the slice the fat pointer points to, i.e. `[start + offset, start + length)`.
+    fn read_fat_pointer(&self, pointer: FatPointer) -> Vec<u8> {
+        let FatPointer {
+            offset,
+            memory_page,
+            start,
+            length,
+        } = pointer;
+
+        self.read_bytes(
+            memory_page,
+            start.wrapping_add(offset),
+            length.saturating_sub(offset),
+        )
+    }
+
+    ///
+    /// Formats the words covering the byte range as a hex dump. The words holding pointers
+    /// are annotated with the decoded fat pointer, and the words at or beyond `heap_bound`
+    /// are marked as such.
+    ///
+    fn hex_dump(
+        &self,
+        page_number: u32,
+        range: std::ops::Range<u32>,
+        heap_bound: Option<u32>,
+    ) -> String {
+        use std::fmt::Write;
+
+        let mut result = String::new();
+        if range.is_empty() {
+            return result;
+        }
+
+        let first_word = range.start / 32;
+        let last_word = (range.end - 1) / 32;
+        let mut buffer = [0u8; 32];
+        for slot in first_word..=last_word {
+            let value = self.read_slot(page_number, slot);
+            value.value.to_big_endian(&mut buffer);
+            let byte_offset = slot as u64 * 32;
+            write!(result, "{:08x}:", byte_offset).unwrap();
+            for chunk in buffer.chunks(8) {
+                write!(result, " {}", hex::encode(chunk)).unwrap();
+            }
+            if value.is_pointer {
+                let FatPointer {
+                    offset,
+                    memory_page,
+                    start,
+                    length,
+                } = FatPointer::from_u256(value.value);
+                write!(
+                    result,
+                    "  ptr(page: {}, start: 0x{:x}, length: 0x{:x}, offset: 0x{:x})",
+                    memory_page, start, length, offset
+                )
+                .unwrap();
+            }
+            if let Some(bound) = heap_bound {
+                if byte_offset + 32 > bound as u64 {
+                    write!(result, "  beyond heap bound 0x{:x}", bound).unwrap();
+                }
+            }
+            result.push('\n');
+        }
+
+        result
+    }
+}
+
+///
+/// The memory implementation the runner executes the VM with.
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryBackend {
+    /// `SimpleHashmapMemory`.
+    #[default]
+    Hashmap,
+    /// `PagedMemory`, for heap-heavy workloads.
+    Paged,
+}
+
+///
+/// The memory of the runner, backed by the implementation chosen with `MemoryBackend`.
+///
+/// The page dumps of the backends only differ in their length: the full page of
+/// `SimpleHashmapMemory` ends at the last word ever written, while `PagedMemory` does not grow
+/// the page to write zeroes beyond its end.
+///
+#[derive(Debug, Clone)]
+pub enum TesterMemory {
+    Hashmap(SimpleHashmapMemory),
+    Paged(PagedMemory),
+}
+
+impl Default for TesterMemory {
+    fn default() -> Self {
+        Self::new(MemoryBackend::default())
+    }
+}
+
+impl TesterMemory {
+    pub fn new(backend: MemoryBackend) -> Self {
+        match backend {
+            MemoryBackend::Hashmap => Self::Hashmap(SimpleHashmapMemory::default()),
+            MemoryBackend::Paged => Self::Paged(PagedMemory::default()),
+        }
+    }
+
+    pub fn populate(&mut self, elements: Vec<(u32, Vec<U256>)>) -> Vec<(u32, usize)> {
+        match self {
+            Self::Hashmap(memory) => memory.populate(elements),
+            Self::Paged(memory) => memory.populate(elements),
+        }
+    }
+
+    pub fn free_page(&mut self, page_number: u32) {
+        match self {
+            Self::Hashmap(memory) => memory.free_page(page_number),
+            Self::Paged(memory) => memory.free_page(page_number),
+        }
+    }
+
+    pub fn read_slot(&self, page_number: u32, slot: u32) -> PrimitiveValue {
+        match self {
+            Self::Hashmap(memory) => memory.read_slot(page_number, slot),
+            Self::Paged(memory) => memory.read_slot(page_number, slot),
+        }
+    }
+
+    pub fn dump_page_content(
+        &self,
+        page_number: u32,
+        range: std::ops::Range<u32>,
+    ) -> Vec<[u8; 32]> {
+        match self {
+            Self::Hashmap(memory) => memory.dump_page_content(page_number, range),
+            Self::Paged(memory) => memory.dump_page_content(page_number, range),
+        }
+    }
+
+    pub fn dump_page_content_as_u256_words(
+        &self,
+        page_number: u32,
+        range: std::ops::Range<u32>,
+    ) -> Vec<U256> {
+        match self {
+            Self::Hashmap(memory) => memory.dump_page_content_as_u256_words(page_number, range),
+            Self::Paged(memory) => memory.dump_page_content_as_u256_words(page_number, range),
+        }
+    }
+
+    pub fn dump_full_page_as_u256_words(&self, page_number: u32) -> Vec<U256> {
+        match self {
+            Self::Hashmap(memory) => memory.dump_full_page_as_u256_words(page_number),
+            Self::Paged(memory) => memory.dump_full_page_as_u256_words(page_number),
+        }
+    }
+
+    pub fn dump_full_page(&self, page_number: u32) -> Vec<[u8; 32]> {
+        match self {
+            Self::Hashmap(memory) => memory.dump_full_page(page_number),
+            Self::Paged(memory) => memory.dump_full_page(page_number),
+        }
+    }
+}
+
+impl ByteAddressableMemory for TesterMemory {
+    fn read_slot(&self, page_number: u32, slot: u32) -> PrimitiveValue {
+        TesterMemory::read_slot(self, page_number, slot)
+    }
+
+    fn write_slot(&mut self, page_number: u32, slot: u32, value: PrimitiveValue) {
+        match self {
+            Self::Hashmap(memory) => {
+                ByteAddressableMemory::write_slot(memory, page_number, slot, value)
+            }
+            Self::Paged(memory) => {
+                ByteAddressableMemory::write_slot(memory, page_number, slot, value)
+            }
+        }
+    }
+}
+
+impl Memory for TesterMemory {
+    fn read_code_query(&self, monotonic_cycle_counter: u32, query: MemoryQuery) -> MemoryQuery {
+        match self {
+            Self::Hashmap(memory) => memory.read_code_query(monotonic_cycle_counter, query),
+            Self::Paged(memory) => memory.read_code_query(monotonic_cycle_counter, query),
+        }
+    }
+
+    fn execute_partial_query(
+        &mut self,
+        monotonic_cycle_counter: u32,
+        query: MemoryQuery,
+    ) -> MemoryQuery {
+        match self {
+            Self::Hashmap(memory) => memory.execute_partial_query(monotonic_cycle_counter, query),
+            Self::Paged(memory) => memory.execute_partial_query(monotonic_cycle_counter, query),
+        }
+    }
+
+    fn specialized_code_query(
+        &mut self,
+        monotonic_cycle_counter: u32,
+        query: MemoryQuery,
+    ) -> MemoryQuery {
+        match self {
+            Self::Hashmap(memory) => memory.specialized_code_query(monotonic_cycle_counter, query),
+            Self::Paged(memory) => memory.specialized_code_query(monotonic_cycle_counter, query),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler_tests::{VmExecutionResult, VmRunOptions};
+    use crate::test_utils::{add_imm, assemble, ret_heap, run, uma};
+    use crate::Address;
+    use std::collections::HashMap;
+    use zk_evm::zkevm_opcode_defs::UMAOpcode;
+
+    #[test]
+    fn runs_on_both_backends() {
+        // heap[0] = 0x42; heap[32] = heap[0]
+        let mut program = vec![
+            add_imm(0x42, 3),
+            uma(UMAOpcode::HeapWrite, (0, 3, 0, 0)),
+            uma(UMAOpcode::HeapRead, (0, 0, 5, 0)),
+            add_imm(32, 4),
+            uma(UMAOpcode::HeapWrite, (4, 5, 0, 0)),
+        ];
+        program.extend(ret_heap(64));
+        let address = Address::from_low_u64_be(0x10000);
+        let mut expected = vec![0u8; 64];
+        expected[31] = 0x42;
+        expected[63] = 0x42;
+
+        for memory_backend in [MemoryBackend::Hashmap, MemoryBackend::Paged] {
+            let snapshot = run(
+                HashMap::from([(address, assemble(&program))]),
+                address,
+                VmRunOptions {
+                    memory_backend,
+                    ..Default::default()
+                },
+            );
+
+            match snapshot.execution_result {
+                VmExecutionResult::Ok(returndata) => assert_eq!(returndata, expected),
+                result => panic!("unexpected result with {:?}: {:?}", memory_backend, result),
+            }
+        }
+    }
+}
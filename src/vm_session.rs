@@ -0,0 +1,1079 @@
+use crate::bytecode::words_hash;
+use crate::call_mocks::CallMocker;
+use crate::compiler_tests::{
+    calldata_to_aligned_data, create_default_testing_tools, create_vm, current_instruction,
+    vm_may_have_ended, MemoryArea, StorageKey, VmExecutionContext, VmExecutionResult,
+    VmLaunchOption, VmRunOptions, VmSnapshot,
+};
+use crate::debug_info::ExecutionLocation;
+use crate::decommitter::TesterDecommitter;
+use crate::default_environment::*;
+use crate::execution_tracer::ExecutionTracer;
+use crate::full_witness_tracer::FullWitnessTracer;
+use crate::hooks::{EvmBytecodeRecorder, PostCycleHook};
+use crate::precompiles::TesterPrecompilesProcessor;
+use crate::simple_witness_tracer::MemoryLogWitnessTracer;
+use crate::system_contracts::{account_code_key, register_code_hash};
+use crate::tester_memory::TesterMemory;
+use crate::watchpoints::{WatchpointHit, Watchpoints};
+use crate::{Address, H256, U256};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use zk_evm::aux_structures::MemoryPage;
+use zk_evm::block_properties::BlockProperties;
+use zk_evm::reference_impls::event_sink::InMemoryEventSink;
+use zk_evm::testing::storage::InMemoryStorage;
+use zk_evm::vm_state::*;
+use zk_evm::zkevm_opcode_defs::decoding::{AllowedPcOrImm, EncodingModeProduction};
+use zk_evm::zkevm_opcode_defs::system_params::{
+    DEPLOYER_SYSTEM_CONTRACT_ADDRESS, DEPLOYER_SYSTEM_CONTRACT_ADDRESS_LOW,
+    KNOWN_CODE_FACTORY_SYSTEM_CONTRACT_ADDRESS,
+};
+use zk_evm::zkevm_opcode_defs::{BlobSha256Format, ContractCodeSha256Format, VersionedHashLen32};
+
+pub type TesterVmState = VmState<
+    InMemoryStorage,
+    TesterMemory,
+    InMemoryEventSink,
+    TesterPrecompilesProcessor,
+    TesterDecommitter,
+    MemoryLogWitnessTracer,
+    8,
+    EncodingModeProduction,
+>;
+
+///
+/// The VM prepared for a run, which can be executed cycle by cycle.
+///
+pub struct VmSession {
+    pub vm: TesterVmState,
+    pub tracer: ExecutionTracer,
+    call_mocker: Option<CallMocker>,
+    options: VmRunOptions,
+    reverse_lookup_for_bytecode: Arc<HashMap<U256, Vec<[u8; 32]>>>,
+    entry_frame_address: Address,
+    cycles_used: usize,
+    result: Option<VmExecutionResult>,
+}
+
+impl VmSession {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        contracts: HashMap<Address, Vec<[u8; 32]>>,
+        calldata: &[u8],
+        mut storage: HashMap<StorageKey, H256>,
+        storage_transient: HashMap<StorageKey, H256>,
+        entry_address: Address,
+        context: Option<VmExecutionContext>,
+        vm_launch_option: VmLaunchOption,
+        known_contracts: HashMap<U256, Vec<[u8; 32]>>,
+        known_sha256_blobs: HashMap<U256, Vec<U256>>,
+        default_aa_code_hash: U256,
+        evm_simulator_code_hash: U256,
+        options: VmRunOptions,
+    ) -> anyhow::Result<Self> {
+        let (set_far_call_props, extra_props) = match &vm_launch_option {
+            VmLaunchOption::Default => (true, None),
+            VmLaunchOption::ManualCallABI(value) => (true, Some(value.clone())),
+        };
+
+        let mut tools = create_default_testing_tools();
+        tools.memory = TesterMemory::new(options.memory_backend);
+        let mut block_properties = create_default_block_properties();
+        block_properties.default_aa_code_hash = default_aa_code_hash;
+        // we can always pretend it to be empty account
+        block_properties.evm_simulator_code_hash = evm_simulator_code_hash;
+
+        if options.register_contracts {
+            for (address, bytecode) in contracts.iter() {
+                if storage.contains_key(&account_code_key(*address)) {
+                    continue;
+                }
+                let hash = words_hash(bytecode)?;
+                register_code_hash(&mut storage, *address, hash);
+            }
+        }
+
+        let calldata_length = calldata.len();
+
+        // fill the calldata
+        let aligned_calldata = calldata_to_aligned_data(calldata);
+        let initial_bytecode = {
+            let hash = storage
+                .get(&StorageKey {
+                    address: Address::from_low_u64_be(DEPLOYER_SYSTEM_CONTRACT_ADDRESS_LOW.into()),
+                    key: U256::from_big_endian(entry_address.as_bytes()),
+                })
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Entry address code hash not found in the storage")
+                })?;
+
+            // If it's an EVM contract, we should run the EVM simulator
+            if hash.as_bytes()[0] == BlobSha256Format::VERSION_BYTE {
+                known_contracts
+                    .get(&evm_simulator_code_hash)
+                    .cloned()
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("EVM simulator bytecode not found in the known contracts")
+                    })?
+            } else {
+                contracts
+                    .get(&entry_address)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("Initial bytecode not found"))?
+            }
+        };
+        let initial_bytecode_as_memory = zk_evm::contract_bytecode_to_words(&initial_bytecode);
+
+        tools.memory.populate(vec![
+            (CALLDATA_PAGE, aligned_calldata),
+            (ENTRY_POINT_PAGE, initial_bytecode_as_memory),
+        ]);
+
+        tools
+            .decommittment_processor
+            .populate(known_sha256_blobs.into_iter().collect());
+
+        if let Some(block_context) = options.block_context.as_ref() {
+            block_context.write_into(&mut storage);
+        }
+        crate::system_contracts::write_balances(&options.balances, &mut storage);
+        crate::system_contracts::write_nonces(&options.nonces, &mut storage);
+
+        // fill the storage. Only rollup shard for now
+        for (key, value) in storage.into_iter() {
+            let per_address_entry = tools.storage.inner[0].entry(key.address).or_default();
+            per_address_entry.insert(key.key, U256::from_big_endian(value.as_bytes()));
+        }
+
+        // fill the transient storage. Only rollup shard for now
+        for (key, value) in storage_transient.into_iter() {
+            let per_address_entry = tools.storage.inner_transient[0]
+                .entry(key.address)
+                .or_default();
+            per_address_entry.insert(key.key, U256::from_big_endian(value.as_bytes()));
+        }
+
+        // some context notion
+        let context = context.unwrap_or_else(|| VmExecutionContext {
+            this_address: entry_address,
+            ..Default::default()
+        });
+        let entry_frame_address = context.this_address;
+
+        // fill the rest
+        let (mut vm, reverse_lookup_for_bytecode) = create_vm(
+            tools,
+            block_properties,
+            context,
+            &contracts,
+            known_contracts,
+        )?;
+
+        if set_far_call_props {
+            // we need to properly set calldata abi
+            vm.local_state.registers[0] =
+                crate::utils::form_initial_calldata_ptr(CALLDATA_PAGE, calldata_length as u32);
+
+            vm.local_state.registers[1] = PrimitiveValue::empty();
+            vm.local_state.registers[2] = PrimitiveValue::empty();
+            vm.local_state.registers[3] = PrimitiveValue::empty();
+
+            if let Some(extra_props) = extra_props {
+                let mut r2_value = U256::zero();
+                if extra_props.is_constructor {
+                    r2_value += U256::from(1u64 << 0);
+                }
+                if extra_props.is_system_call {
+                    r2_value += U256::from(1u64 << 1);
+                }
+
+                let r3_value = extra_props.r3_value.unwrap_or(U256::zero());
+                let r4_value = extra_props.r4_value.unwrap_or(U256::zero());
+                let r5_value = extra_props.r5_value.unwrap_or(U256::zero());
+
+                vm.local_state.registers[1] = PrimitiveValue::from_value(r2_value);
+                vm.local_state.registers[2] = PrimitiveValue::from_value(r3_value);
+                vm.local_state.registers[3] = PrimitiveValue::from_value(r4_value);
+                vm.local_state.registers[4] = PrimitiveValue::from_value(r5_value);
+            }
+        }
+
+        let tracer = configure_tracing(&mut vm, &options);
+        let call_mocker = create_call_mocker(&vm, &options);
+
+        Ok(Self {
+            vm,
+            tracer,
+            call_mocker,
+            options,
+            reverse_lookup_for_bytecode: Arc::new(reverse_lookup_for_bytecode),
+            entry_frame_address,
+            cycles_used: 0,
+            result: None,
+        })
+    }
+
+    pub fn options(&self) -> &VmRunOptions {
+        &self.options
+    }
+
+    pub fn cycles_used(&self) -> usize {
+        self.cycles_used
+    }
+
+    /// The result of the execution, if it has ended.
+    pub fn result(&self) -> Option<&VmExecutionResult> {
+        self.result.as_ref()
+    }
+
+    /// Executes a single cycle. Returns `true` if the execution has ended.
+    pub fn step(&mut self) -> anyhow::Result<bool> {
+        if self.result.is_some() {
+            return Ok(true);
+        }
+
+        if let Some(watchpoints) = self.vm.witness_tracer.watchpoints.as_mut() {
+            let current_frame = self.vm.local_state.callstack.get_current_stack();
+            let location = ExecutionLocation::new(
+                current_frame.this_address,
+                current_frame.code_address,
+                current_frame.pc,
+            );
+            watchpoints.before_cycle(&self.vm.memory, location);
+        }
+        self.vm.cycle(&mut self.tracer)?;
+        self.tracer.resolve_far_call_panic(&self.vm.storage);
+        if self.options.free_released_pages {
+            if let Some(page_lifecycle_tracer) = self.tracer.page_lifecycle_tracer.as_mut() {
+                for page in page_lifecycle_tracer.take_released_pages() {
+                    self.vm.memory.free_page(page);
+                }
+            }
+        }
+        EvmBytecodeRecorder.after_cycle(&mut self.vm)?;
+        if let Some(call_mocker) = self.call_mocker.as_mut() {
+            call_mocker.after_cycle(&mut self.vm)?;
+        }
+        for hook in self.options.post_cycle_hooks.iter_mut() {
+            hook.after_cycle(&mut self.vm)?;
+        }
+        self.cycles_used += 1;
+
+        // early return
+        self.result = vm_may_have_ended(&self.vm, &self.tracer);
+
+        Ok(self.result.is_some())
+    }
+
+    ///
+    /// Executes up to `cycles` cycles. Returns `true` if the execution has ended.
+    ///
+    /// Stops early after a cycle that hit a watchpoint with the `Stop` action.
+    ///
+    pub fn run(&mut self, cycles: usize) -> anyhow::Result<bool> {
+        for _ in 0..cycles {
+            if self.step()? {
+                return Ok(true);
+            }
+            if self.watchpoint_stop().is_some() {
+                break;
+            }
+        }
+
+        Ok(self.result.is_some())
+    }
+
+    /// The watchpoint with the `Stop` action hit by the last cycle, if any.
+    pub fn watchpoint_stop(&self) -> Option<&WatchpointHit> {
+        self.vm
+            .witness_tracer
+            .watchpoints
+            .as_ref()
+            .and_then(|watchpoints| watchpoints.stopped_at.as_ref())
+    }
+
+    pub fn finish(self) -> anyhow::Result<VmSnapshot> {
+        let Self {
+            vm,
+            tracer,
+            call_mocker: _,
+            options,
+            reverse_lookup_for_bytecode,
+            entry_frame_address,
+            cycles_used,
+            result,
+        } = self;
+
+        let mut execution_result = if let Some(result) = result {
+            result
+        } else {
+            let current_address = vm.local_state.callstack.get_current_stack().this_address;
+            let pc = vm.local_state.callstack.get_current_stack().pc.as_u64();
+            VmExecutionResult::MostLikelyDidNotFinish(current_address, pc, current_instruction(&vm))
+        };
+
+        if let VmExecutionResult::Panic(info) = &mut execution_result {
+            info.location.annotate(&options.debug_info);
+        }
+
+        let mut result_location = match &execution_result {
+            VmExecutionResult::Ok(_) => tracer.result_location(false),
+            VmExecutionResult::Revert(_) => tracer.result_location(true),
+            VmExecutionResult::Panic(info) => Some(info.location.clone()),
+            VmExecutionResult::MostLikelyDidNotFinish(..) => {
+                let current_frame = vm.local_state.callstack.get_current_stack();
+                Some(ExecutionLocation::new(
+                    current_frame.this_address,
+                    current_frame.code_address,
+                    current_frame.pc,
+                ))
+            }
+        };
+        if let Some(location) = result_location.as_mut() {
+            location.annotate(&options.debug_info);
+        }
+
+        let mut call_tree = tracer.call_tree_tracer.call_tree;
+        call_tree.annotate(&options.debug_info);
+
+        let mut freed_page_accesses = tracer
+            .page_lifecycle_tracer
+            .map(|tracer| tracer.freed_page_accesses)
+            .unwrap_or_default();
+        for access in freed_page_accesses.iter_mut() {
+            access.location.annotate(&options.debug_info);
+        }
+        if !freed_page_accesses.is_empty() {
+            anyhow::bail!(
+                "Reads from the released memory pages:\n{}",
+                freed_page_accesses
+                    .iter()
+                    .map(|access| access.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+
+        let execution_has_ended = vm.execution_has_ended();
+
+        let VmState {
+            local_state,
+            block_properties: _,
+            storage,
+            event_sink,
+            decommittment_processor,
+            witness_tracer,
+            ..
+        } = vm;
+
+        let full_witness = witness_tracer.full_witness;
+        let watchpoint_hits = witness_tracer
+            .watchpoints
+            .map(|mut watchpoints| {
+                watchpoints.annotate(&options.debug_info);
+                watchpoints.hits
+            })
+            .unwrap_or_default();
+        let mut memory_queries = witness_tracer.queries;
+        if let Some(frames) = options
+            .memory_queries
+            .as_ref()
+            .and_then(|filter| filter.frames.as_ref())
+        {
+            let mut base_pages = vec![];
+            if frames.contains(&entry_frame_address) {
+                base_pages.push(MemoryPage(INITIAL_BASE_PAGE));
+            }
+            base_pages.extend(
+                call_tree
+                    .far_calls()
+                    .filter(|call| frames.contains(&call.callee))
+                    .map(|call| MemoryPage(call.base_memory_page)),
+            );
+            let frame_pages: HashSet<u32> = base_pages
+                .into_iter()
+                .flat_map(|base_page| {
+                    [
+                        code_page_candidate_from_base(base_page).0,
+                        stack_page_from_base(base_page).0,
+                        heap_page_from_base(base_page).0,
+                        aux_heap_page_from_base(base_page).0,
+                    ]
+                })
+                .collect();
+            memory_queries.retain(|query| frame_pages.contains(&query.location.page.0));
+        }
+
+        let mut result_storage = HashMap::new();
+        let mut deployed_contracts = HashMap::new();
+
+        // the frames of an unfinished run are still open, so their events are taken as they are
+        let mut event_sink = event_sink;
+        while event_sink.frames_stack.len() > 1 {
+            let frame = event_sink.frames_stack.pop().unwrap();
+            event_sink
+                .frames_stack
+                .last_mut()
+                .unwrap()
+                .forward
+                .extend(frame.forward);
+        }
+        let (_full_history, raw_events, l1_messages) = event_sink.flatten();
+        let events = crate::events::merge_events(raw_events.clone());
+
+        let storage = storage.inner;
+        let storage = storage.into_iter().next().unwrap();
+        let mut published_sha256_blobs = HashMap::new();
+
+        for (address, inner) in storage.into_iter() {
+            for (key, value) in inner.into_iter() {
+                let storage_key = StorageKey { address, key };
+                let mut buffer = [0u8; 32];
+                value.to_big_endian(&mut buffer);
+                let value_h256 = H256::from_slice(&buffer);
+                result_storage.insert(storage_key, value_h256);
+
+                if address == *DEPLOYER_SYSTEM_CONTRACT_ADDRESS {
+                    let mut buffer = [0u8; 32];
+                    key.to_big_endian(&mut buffer);
+                    let deployed_address = Address::from_slice(&buffer[12..]);
+                    if let Some(bytecode) = reverse_lookup_for_bytecode.get(&value) {
+                        deployed_contracts.insert(
+                            deployed_address,
+                            bytecode.iter().copied().flatten().collect(),
+                        );
+                    }
+                }
+
+                let mut key_buffer = [0u8; 32];
+                key.to_big_endian(&mut key_buffer);
+
+                // This is an EVM blob hash that has been set as known.
+                if address == *KNOWN_CODE_FACTORY_SYSTEM_CONTRACT_ADDRESS
+                    && value == 1.into()
+                    && key_buffer[0] == BlobSha256Format::VERSION_BYTE
+                {
+                    let (_, normalized_hash) =
+                        ContractCodeSha256Format::normalize_for_decommitment(&key_buffer);
+
+                    published_sha256_blobs.insert(
+                        key,
+                        decommittment_processor
+                            .get_preimage_by_hash(normalized_hash)
+                            .ok_or_else(|| anyhow::anyhow!("Published hash is unknown"))?
+                            .clone(),
+                    );
+                }
+            }
+        }
+
+        let balances = options
+            .balances
+            .keys()
+            .copied()
+            .chain([entry_frame_address])
+            .chain(
+                call_tree
+                    .far_calls()
+                    .flat_map(|call| [call.caller, call.callee]),
+            )
+            .chain(deployed_contracts.keys().copied())
+            .filter_map(|address| {
+                let key = crate::system_contracts::balance_key(address);
+                result_storage
+                    .get(&key)
+                    .map(|value| (address, U256::from_big_endian(value.as_bytes())))
+            })
+            .collect();
+
+        // memory dump for returndata
+        let returndata_page_content = vec![];
+
+        let returndata_mem = MemoryArea {
+            words: returndata_page_content,
+        };
+
+        let calldata_page_content = vec![];
+
+        let calldata_mem = MemoryArea {
+            words: calldata_page_content,
+        };
+
+        let returndata_bytes = match &execution_result {
+            VmExecutionResult::Ok(ref res) => res.clone(),
+            VmExecutionResult::Revert(ref res) => res.clone(),
+            VmExecutionResult::Panic(_) => vec![],
+            VmExecutionResult::MostLikelyDidNotFinish(..) => vec![],
+        };
+
+        let compiler_tests_events: Vec<crate::events::Event> =
+            events.iter().cloned().map(|el| el.into()).collect();
+
+        let serialized_events = serde_json::to_string_pretty(&compiler_tests_events).unwrap();
+
+        let did_call_or_ret_recently = local_state.previous_code_memory_page.0
+            != local_state.callstack.get_current_stack().code_page.0;
+
+        Ok(VmSnapshot {
+            registers: local_state.registers,
+            flags: local_state.flags,
+            timestamp: local_state.timestamp,
+            memory_page_counter: local_state.memory_page_counter,
+            tx_number_in_block: local_state.tx_number_in_block,
+            previous_super_pc: local_state.previous_super_pc.as_u64() as u32,
+            did_call_or_ret_recently,
+            calldata_area_dump: calldata_mem,
+            returndata_area_dump: returndata_mem,
+            execution_has_ended,
+            stack_dump: MemoryArea::empty(),
+            heap_dump: MemoryArea::empty(),
+            storage: result_storage,
+            deployed_contracts,
+            execution_result,
+            returndata_bytes,
+            raw_events,
+            to_l1_messages: l1_messages,
+            events,
+            serialized_events,
+            num_cycles_used: cycles_used,
+            // All the ergs from the empty frame should be passed into the root(bootloader) and unused ergs will be returned.
+            num_ergs_used: zk_evm::zkevm_opcode_defs::system_params::VM_INITIAL_FRAME_ERGS
+                - local_state.callstack.current.ergs_remaining,
+            published_sha256_blobs,
+            result_location,
+            call_tree,
+            memory_queries,
+            full_witness,
+            watchpoint_hits,
+            balances,
+        })
+    }
+
+    /// Captures the complete state of the session at the current cycle.
+    pub fn checkpoint(&self) -> VmCheckpoint {
+        VmCheckpoint {
+            local_state: self.vm.local_state.clone(),
+            memory: self.vm.memory.clone(),
+            storage: self.vm.storage.clone(),
+            event_sink: self.vm.event_sink.clone(),
+            decommittment_processor: self.vm.decommittment_processor.clone(),
+            witness_tracer: self.vm.witness_tracer.clone(),
+            block_properties: self.vm.block_properties,
+            tracer: self.tracer.clone(),
+            call_mocker: self.call_mocker.clone(),
+            options: self.options.clone(),
+            reverse_lookup_for_bytecode: self.reverse_lookup_for_bytecode.clone(),
+            entry_frame_address: self.entry_frame_address,
+            cycles_used: self.cycles_used,
+            result: self.result.clone(),
+        }
+    }
+
+    /// Brings the session back to the state captured by `checkpoint`.
+    pub fn restore(&mut self, checkpoint: &VmCheckpoint) {
+        self.vm.local_state = checkpoint.local_state.clone();
+        self.vm.memory = checkpoint.memory.clone();
+        self.vm.storage = checkpoint.storage.clone();
+        self.vm.event_sink = checkpoint.event_sink.clone();
+        self.vm.decommittment_processor = checkpoint.decommittment_processor.clone();
+        self.vm.witness_tracer = checkpoint.witness_tracer.clone();
+        self.vm.block_properties = checkpoint.block_properties;
+        self.tracer = checkpoint.tracer.clone();
+        self.call_mocker = checkpoint.call_mocker.clone();
+        self.options = checkpoint.options.clone();
+        self.reverse_lookup_for_bytecode = checkpoint.reverse_lookup_for_bytecode.clone();
+        self.entry_frame_address = checkpoint.entry_frame_address;
+        self.cycles_used = checkpoint.cycles_used;
+        self.result = checkpoint.result.clone();
+    }
+}
+
+///
+/// The owned copy of the whole session state at some cycle.
+///
+#[derive(Clone)]
+pub struct VmCheckpoint {
+    local_state: VmLocalState<8, EncodingModeProduction>,
+    memory: TesterMemory,
+    storage: InMemoryStorage,
+    event_sink: InMemoryEventSink,
+    decommittment_processor: TesterDecommitter,
+    witness_tracer: MemoryLogWitnessTracer,
+    block_properties: BlockProperties,
+    tracer: ExecutionTracer,
+    call_mocker: Option<CallMocker>,
+    options: VmRunOptions,
+    reverse_lookup_for_bytecode: Arc<HashMap<U256, Vec<[u8; 32]>>>,
+    entry_frame_address: Address,
+    cycles_used: usize,
+    result: Option<VmExecutionResult>,
+}
+
+impl VmCheckpoint {
+    pub fn cycles_used(&self) -> usize {
+        self.cycles_used
+    }
+
+    ///
+    /// Starts a new session from the checkpoint with different run options.
+    ///
+    /// The diagnostics are collected from scratch, so the resulting snapshot only describes
+    /// the part of the execution after the checkpoint.
+    ///
+    pub fn resume(&self, options: VmRunOptions) -> VmSession {
+        let mut vm = VmState::empty_state(
+            self.storage.clone(),
+            self.memory.clone(),
+            self.event_sink.clone(),
+            TesterPrecompilesProcessor::default(),
+            self.decommittment_processor.clone(),
+            self.witness_tracer.clone(),
+            self.block_properties,
+        );
+        vm.local_state = self.local_state.clone();
+        let tracer = configure_tracing(&mut vm, &options);
+        let call_mocker = create_call_mocker(&vm, &options);
+
+        VmSession {
+            vm,
+            tracer,
+            call_mocker,
+            options,
+            reverse_lookup_for_bytecode: self.reverse_lookup_for_bytecode.clone(),
+            entry_frame_address: self.entry_frame_address,
+            cycles_used: self.cycles_used,
+            result: self.result.clone(),
+        }
+    }
+}
+
+fn create_call_mocker(vm: &TesterVmState, options: &VmRunOptions) -> Option<CallMocker> {
+    if options.call_mocks.is_empty() {
+        None
+    } else {
+        Some(CallMocker::new(
+            options.call_mocks.clone(),
+            vm.local_state.callstack.depth(),
+        ))
+    }
+}
+
+fn configure_tracing(vm: &mut TesterVmState, options: &VmRunOptions) -> ExecutionTracer {
+    vm.witness_tracer.is_dummy = options.memory_queries.is_none();
+    vm.witness_tracer.pages = options
+        .memory_queries
+        .as_ref()
+        .and_then(|filter| filter.pages.clone());
+    vm.witness_tracer.full_witness = options.record_full_witness.then(FullWitnessTracer::new);
+    vm.witness_tracer.queries.clear();
+    // the full witness needs the decommitted code and the precompile witness
+    vm.precompiles_processor.record_witness = options.record_full_witness;
+    vm.decommittment_processor.record_witness = options.record_full_witness;
+    vm.witness_tracer.watchpoints = if options.watchpoints.is_empty() {
+        None
+    } else {
+        Some(Watchpoints::new(options.watchpoints.clone()))
+    };
+
+    ExecutionTracer::new()
+        .with_near_calls_traced(options.trace_near_calls)
+        .with_page_lifecycle_tracked(options.track_page_lifecycle || options.free_released_pages)
+}
+
+///
+/// The session that can be stepped backwards.
+///
+/// A checkpoint is taken every `checkpoint_interval` cycles. Stepping back restores the nearest
+/// checkpoint at or before the target cycle and re-executes the cycles up to it.
+///
+pub struct ReversibleVmSession {
+    pub session: VmSession,
+    checkpoint_interval: usize,
+    /// Sorted by the cycle they were taken at.
+    checkpoints: Vec<VmCheckpoint>,
+}
+
+impl ReversibleVmSession {
+    pub fn new(session: VmSession, checkpoint_interval: usize) -> Self {
+        assert!(
+            checkpoint_interval > 0,
+            "checkpoint interval must be positive"
+        );
+        let checkpoints = vec![session.checkpoint()];
+
+        Self {
+            session,
+            checkpoint_interval,
+            checkpoints,
+        }
+    }
+
+    pub fn checkpoint_interval(&self) -> usize {
+        self.checkpoint_interval
+    }
+
+    pub fn cycles_used(&self) -> usize {
+        self.session.cycles_used()
+    }
+
+    /// The cycle the wrapped session started at, which is not zero for resumed checkpoints.
+    pub fn start_cycle(&self) -> usize {
+        self.checkpoints[0].cycles_used()
+    }
+
+    /// Executes a single cycle. Returns `true` if the execution has ended.
+    pub fn step(&mut self) -> anyhow::Result<bool> {
+        let has_ended = self.session.step()?;
+        let cycle = self.session.cycles_used();
+        let is_known = self
+            .checkpoints
+            .last()
+            .map(|checkpoint| checkpoint.cycles_used() >= cycle)
+            .unwrap_or(false);
+        if cycle % self.checkpoint_interval == 0 && !is_known {
+            self.checkpoints.push(self.session.checkpoint());
+        }
+
+        Ok(has_ended)
+    }
+
+    /// Executes up to `cycles` cycles. Returns `true` if the execution has ended.
+    pub fn run(&mut self, cycles: usize) -> anyhow::Result<bool> {
+        for _ in 0..cycles {
+            if self.step()? {
+                return Ok(true);
+            }
+            if self.session.watchpoint_stop().is_some() {
+                break;
+            }
+        }
+
+        Ok(self.session.result().is_some())
+    }
+
+    /// Steps back by a single cycle. Returns `false` if already at the start of the session.
+    pub fn step_back(&mut self) -> anyhow::Result<bool> {
+        self.step_back_by(1)
+    }
+
+    /// Steps back by up to `cycles` cycles. Returns `false` if already at the start of the session.
+    pub fn step_back_by(&mut self, cycles: usize) -> anyhow::Result<bool> {
+        let current_cycle = self.session.cycles_used();
+        let start_cycle = self.start_cycle();
+        if current_cycle <= start_cycle {
+            return Ok(false);
+        }
+
+        self.go_to_cycle(current_cycle.saturating_sub(cycles).max(start_cycle))?;
+
+        Ok(true)
+    }
+
+    ///
+    /// Brings the session to the state right after `cycle` cycles were executed.
+    ///
+    /// Moving forward simply executes the cycles. Moving backward re-executes the cycles since
+    /// the nearest checkpoint. Fails if the cycle precedes the start of the session or the
+    /// execution ends before reaching it.
+    ///
+    pub fn go_to_cycle(&mut self, cycle: usize) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            cycle >= self.start_cycle(),
+            "Cycle {} precedes the start of the session at cycle {}",
+            cycle,
+            self.start_cycle()
+        );
+
+        if cycle < self.session.cycles_used() {
+            let index = self
+                .checkpoints
+                .partition_point(|checkpoint| checkpoint.cycles_used() <= cycle);
+            // the checkpoint of the initial state is at or before the cycle, as checked above
+            let checkpoint = &self.checkpoints[index - 1];
+            self.session.restore(checkpoint);
+        }
+
+        while self.session.cycles_used() < cycle {
+            if self.step()? && self.session.cycles_used() < cycle {
+                anyhow::bail!(
+                    "The execution has ended at cycle {} before reaching cycle {}",
+                    self.session.cycles_used(),
+                    cycle
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> anyhow::Result<VmSnapshot> {
+        self.session.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::bytecode_hash;
+    use crate::compiler_tests::run_vm_multi_contracts_with_options;
+    use crate::system_contracts::known_code_key;
+    use crate::test_utils::{
+        add_imm, assemble, default_code, far_call, instruction, ret_ok, ret_panic, session,
+    };
+    use zk_evm::zkevm_opcode_defs::{LogOpcode, Opcode, Operand};
+
+    const CALLEE: u64 = 0x1234;
+    const CALLER: u64 = 0x10000;
+
+    /// The caller far calls the callee twice, so that the second call finds the code decommitted.
+    fn contracts() -> HashMap<Address, Vec<u8>> {
+        // the exception handler is at pc 11
+        let mut program = far_call(CALLEE as u16, 11).to_vec();
+        program.extend(far_call(CALLEE as u16, 11));
+        program.extend([ret_ok(), ret_ok()]);
+
+        HashMap::from([
+            (Address::from_low_u64_be(CALLER), assemble(&program)),
+            (
+                Address::from_low_u64_be(CALLEE),
+                assemble(&[add_imm(1, 3), ret_ok()]),
+            ),
+        ])
+    }
+
+    fn new_session() -> VmSession {
+        session(
+            contracts(),
+            Address::from_low_u64_be(CALLER),
+            VmRunOptions::default(),
+        )
+    }
+
+    fn assert_same_state(expected: &VmSession, actual: &VmSession) {
+        assert_eq!(expected.cycles_used(), actual.cycles_used());
+        assert_eq!(expected.vm.local_state, actual.vm.local_state);
+        let (TesterMemory::Hashmap(expected_memory), TesterMemory::Hashmap(actual_memory)) =
+            (&expected.vm.memory, &actual.vm.memory)
+        else {
+            panic!("the sessions must use the default memory backend");
+        };
+        assert_eq!(expected_memory.inner, actual_memory.inner);
+        assert_eq!(expected.vm.storage.inner, actual.vm.storage.inner);
+        assert_eq!(
+            expected.vm.decommittment_processor.history,
+            actual.vm.decommittment_processor.history
+        );
+    }
+
+    #[test]
+    fn restored_and_resumed_checkpoints_match_an_uninterrupted_run() {
+        let mut uninterrupted = new_session();
+        assert!(uninterrupted.run(1_000).unwrap());
+
+        let mut session = new_session();
+        // inside the first far call, with the callee code decommitted
+        session.run(6).unwrap();
+        assert_eq!(session.vm.local_state.callstack.depth(), 2);
+        let checkpoint = session.checkpoint();
+        assert_eq!(checkpoint.cycles_used(), 6);
+
+        assert!(session.run(1_000).unwrap());
+        assert_same_state(&uninterrupted, &session);
+
+        session.restore(&checkpoint);
+        assert_eq!(session.cycles_used(), 6);
+        assert!(session.result().is_none());
+        assert!(session.run(1_000).unwrap());
+        assert_same_state(&uninterrupted, &session);
+
+        let mut resumed = checkpoint.resume(VmRunOptions::default());
+        assert!(resumed.run(1_000).unwrap());
+        assert_same_state(&uninterrupted, &resumed);
+
+        let expected = uninterrupted.finish().unwrap();
+        let actual = resumed.finish().unwrap();
+        assert!(matches!(actual.execution_result, VmExecutionResult::Ok(_)));
+        assert_eq!(expected.registers, actual.registers);
+        assert_eq!(expected.storage, actual.storage);
+        assert_eq!(expected.num_cycles_used, actual.num_cycles_used);
+        assert_eq!(expected.num_ergs_used, actual.num_ergs_used);
+    }
+
+    /// `sstore(5, value)`, then returns.
+    fn storing(value: u16) -> Vec<u8> {
+        assemble(&[
+            add_imm(5, 1),
+            add_imm(value, 2),
+            instruction(
+                Opcode::Log(LogOpcode::StorageWrite),
+                Operand::RegOnly,
+                Operand::RegOnly,
+                (1, 2, 0),
+                0,
+            ),
+            ret_ok(),
+        ])
+    }
+
+    /// Far calls the callee, panicking if the call fails.
+    fn calling_the_callee() -> Vec<u8> {
+        let mut program = far_call(CALLEE as u16, 6).to_vec();
+        program.extend([ret_ok(), ret_panic()]);
+
+        assemble(&program)
+    }
+
+    fn run_registering(
+        storage: HashMap<StorageKey, H256>,
+        known_contracts: HashMap<U256, Vec<u8>>,
+        register_contracts: bool,
+    ) -> anyhow::Result<VmSnapshot> {
+        let (default_code_hash, default_code) = default_code();
+        let mut known_contracts = known_contracts;
+        known_contracts.insert(default_code_hash, default_code);
+
+        run_vm_multi_contracts_with_options(
+            String::new(),
+            HashMap::from([
+                (Address::from_low_u64_be(CALLER), calling_the_callee()),
+                (Address::from_low_u64_be(CALLEE), storing(0x77)),
+            ]),
+            &[],
+            storage,
+            HashMap::new(),
+            Address::from_low_u64_be(CALLER),
+            None,
+            VmLaunchOption::Default,
+            1_000,
+            known_contracts,
+            HashMap::new(),
+            default_code_hash,
+            default_code_hash,
+            VmRunOptions {
+                register_contracts,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn stored_value(snapshot: &VmSnapshot) -> Option<H256> {
+        snapshot
+            .storage
+            .get(&StorageKey {
+                address: Address::from_low_u64_be(CALLEE),
+                key: U256::from(5),
+            })
+            .copied()
+    }
+
+    #[test]
+    fn contracts_are_registered_on_request() {
+        let snapshot = run_registering(HashMap::new(), HashMap::new(), true).unwrap();
+
+        assert!(matches!(
+            snapshot.execution_result,
+            VmExecutionResult::Ok(_)
+        ));
+        assert_eq!(stored_value(&snapshot), Some(H256::from_low_u64_be(0x77)));
+        for (address, bytecode) in [(CALLER, calling_the_callee()), (CALLEE, storing(0x77))] {
+            let hash = bytecode_hash(&bytecode).unwrap();
+            assert_eq!(
+                snapshot.storage[&account_code_key(Address::from_low_u64_be(address))],
+                hash
+            );
+            assert_eq!(
+                snapshot.storage[&known_code_key(hash)],
+                H256::from_low_u64_be(1)
+            );
+        }
+
+        let error = run_registering(HashMap::new(), HashMap::new(), false).unwrap_err();
+        assert!(
+            error.to_string().contains("code hash not found"),
+            "unexpected error: {}",
+            error
+        );
+    }
+
+    #[test]
+    fn existing_code_hashes_are_kept() {
+        // the callee address is already deployed with other code
+        let other_code = storing(0x88);
+        let other_hash = bytecode_hash(&other_code).unwrap();
+        let mut storage = HashMap::new();
+        register_code_hash(&mut storage, Address::from_low_u64_be(CALLEE), other_hash);
+
+        let snapshot = run_registering(
+            storage,
+            HashMap::from([(U256::from_big_endian(other_hash.as_bytes()), other_code)]),
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(
+            snapshot.storage[&account_code_key(Address::from_low_u64_be(CALLEE))],
+            other_hash
+        );
+        assert_eq!(stored_value(&snapshot), Some(H256::from_low_u64_be(0x88)));
+    }
+
+    /// A fresh session run forward to `cycle`.
+    fn forward_to(cycle: usize) -> VmSession {
+        let mut session = new_session();
+        session.run(cycle).unwrap();
+        assert_eq!(session.cycles_used(), cycle);
+
+        session
+    }
+
+    #[test]
+    fn stepping_back_matches_a_forward_run() {
+        let mut reversible = ReversibleVmSession::new(new_session(), 4);
+        assert!(reversible.run(1_000).unwrap());
+        let end_cycle = reversible.cycles_used();
+        assert!(end_cycle > 10);
+
+        // the checkpoints are at the cycles 0, 4, 8, ...
+        reversible.go_to_cycle(9).unwrap();
+        assert_same_state(&forward_to(9), &reversible.session);
+
+        // back across the checkpoint at cycle 8
+        assert!(reversible.step_back_by(3).unwrap());
+        assert_eq!(reversible.cycles_used(), 6);
+        assert_same_state(&forward_to(6), &reversible.session);
+
+        assert!(reversible.step_back().unwrap());
+        assert_same_state(&forward_to(5), &reversible.session);
+
+        // bounded by the start of the session
+        assert!(reversible.step_back_by(100).unwrap());
+        assert_eq!(reversible.cycles_used(), reversible.start_cycle());
+        assert_same_state(&new_session(), &reversible.session);
+        assert!(!reversible.step_back().unwrap());
+
+        // forward again to the end
+        reversible.go_to_cycle(end_cycle).unwrap();
+        let mut uninterrupted = new_session();
+        assert!(uninterrupted.run(1_000).unwrap());
+        assert_same_state(&uninterrupted, &reversible.session);
+        assert!(reversible.go_to_cycle(end_cycle + 1).is_err());
+    }
+
+    #[test]
+    fn resumed_session_steps_back_to_its_start_cycle() {
+        let checkpoint = forward_to(6).checkpoint();
+        let mut reversible =
+            ReversibleVmSession::new(checkpoint.resume(VmRunOptions::default()), 4);
+        assert_eq!(reversible.start_cycle(), 6);
+
+        reversible.go_to_cycle(10).unwrap();
+        assert_same_state(&forward_to(10), &reversible.session);
+
+        assert!(reversible.step_back_by(100).unwrap());
+        assert_eq!(reversible.cycles_used(), 6);
+        assert_same_state(&forward_to(6), &reversible.session);
+        assert!(!reversible.step_back().unwrap());
+        assert!(reversible.go_to_cycle(5).is_err());
+    }
+}
@@ -0,0 +1,274 @@
+use crate::compiler_tests::StorageKey;
+use crate::debug_info::{DebugInfo, ExecutionLocation};
+use crate::tester_memory::TesterMemory;
+use crate::{Address, U256};
+use std::collections::HashMap;
+use zk_evm::aux_structures::{LogQuery, MemoryQuery};
+use zk_evm::zkevm_opcode_defs::system_params::STORAGE_AUX_BYTE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Watchpoint {
+    Storage(StorageKey),
+    Memory { page: u32, index: u32 },
+}
+
+impl std::fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Storage(key) => write!(f, "storage {}", key),
+            Self::Memory { page, index } => write!(f, "memory page {} word {}", page, index),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WatchpointAction {
+    /// Stop the run after the cycle that wrote the location.
+    Stop,
+    /// Only record the write.
+    Record,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchpointHit {
+    pub cycle: u32,
+    pub watchpoint: Watchpoint,
+    pub action: WatchpointAction,
+    /// The instruction that made the write.
+    pub location: ExecutionLocation,
+    pub old_value: U256,
+    pub new_value: U256,
+}
+
+impl std::fmt::Display for WatchpointHit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "cycle {}: {} writes {}: 0x{:x} -> 0x{:x}",
+            self.cycle, self.location, self.watchpoint, self.old_value, self.new_value
+        )
+    }
+}
+
+///
+/// Records the writes to the watched storage slots and memory words.
+///
+/// The writes are reported to the witness tracer, so every write is caught, including the ones
+/// that leave the value unchanged. The old values of the memory words are read before every
+/// cycle, as the memory queries only carry the written value.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Watchpoints {
+    pub watched: HashMap<Watchpoint, WatchpointAction>,
+    pub hits: Vec<WatchpointHit>,
+    /// The hit with the `Stop` action in the last cycle, if any.
+    pub stopped_at: Option<WatchpointHit>,
+    current_location: Option<ExecutionLocation>,
+    memory_values: HashMap<(u32, u32), U256>,
+}
+
+impl Watchpoints {
+    pub fn new(watched: HashMap<Watchpoint, WatchpointAction>) -> Self {
+        Self {
+            watched,
+            ..Default::default()
+        }
+    }
+
+    /// Must be called before every cycle.
+    pub(crate) fn before_cycle(&mut self, memory: &TesterMemory, location: ExecutionLocation) {
+        self.stopped_at = None;
+        self.current_location = Some(location);
+        self.memory_values.clear();
+        for watchpoint in self.watched.keys() {
+            if let Watchpoint::Memory { page, index } = *watchpoint {
+                self.memory_values
+                    .insert((page, index), memory.read_slot(page, index).value);
+            }
+        }
+    }
+
+    fn record(&mut self, cycle: u32, watchpoint: Watchpoint, old_value: U256, new_value: U256) {
+        let Some(action) = self.watched.get(&watchpoint).copied() else {
+            return;
+        };
+        let location = self
+            .current_location
+            .clone()
+            .unwrap_or_else(|| ExecutionLocation::new(Address::zero(), Address::zero(), 0));
+        let hit = WatchpointHit {
+            cycle,
+            watchpoint,
+            action,
+            location,
+            old_value,
+            new_value,
+        };
+        if action == WatchpointAction::Stop && self.stopped_at.is_none() {
+            self.stopped_at = Some(hit.clone());
+        }
+        self.hits.push(hit);
+    }
+
+    pub(crate) fn on_memory_query(&mut self, cycle: u32, query: &MemoryQuery) {
+        if !query.rw_flag {
+            return;
+        }
+
+        let page = query.location.page.0;
+        let index = query.location.index.0;
+        let Some(old_value) = self.memory_values.get_mut(&(page, index)) else {
+            return;
+        };
+        let previous = std::mem::replace(old_value, query.value);
+        self.record(
+            cycle,
+            Watchpoint::Memory { page, index },
+            previous,
+            query.value,
+        );
+    }
+
+    pub(crate) fn on_log_query(&mut self, cycle: u32, query: &LogQuery) {
+        if query.aux_byte != STORAGE_AUX_BYTE || !query.rw_flag || query.rollback {
+            return;
+        }
+
+        let watchpoint = Watchpoint::Storage(StorageKey {
+            address: query.address,
+            key: query.key,
+        });
+        self.record(cycle, watchpoint, query.read_value, query.written_value);
+    }
+
+    pub fn annotate(&mut self, debug_info: &HashMap<Address, DebugInfo>) {
+        for hit in self.hits.iter_mut() {
+            hit.location.annotate(debug_info);
+        }
+        if let Some(hit) = self.stopped_at.as_mut() {
+            hit.location.annotate(debug_info);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler_tests::VmRunOptions;
+    use crate::default_environment::INITIAL_BASE_PAGE;
+    use crate::test_utils::{add_imm, assemble, instruction, ret_ok, session, uma};
+    use zk_evm::aux_structures::MemoryPage;
+    use zk_evm::vm_state::heap_page_from_base;
+    use zk_evm::zkevm_opcode_defs::{LogOpcode, Opcode, Operand, UMAOpcode};
+
+    const CONTRACT: u64 = 0x10000;
+
+    fn heap_word() -> Watchpoint {
+        Watchpoint::Memory {
+            page: heap_page_from_base(MemoryPage(INITIAL_BASE_PAGE)).0,
+            index: 1,
+        }
+    }
+
+    fn storage_slot() -> Watchpoint {
+        Watchpoint::Storage(StorageKey {
+            address: Address::from_low_u64_be(CONTRACT),
+            key: U256::from(5),
+        })
+    }
+
+    /// Writes 0x42 and then 0x43 to the heap word 1, then 0x77 to the storage slot 5.
+    fn contracts() -> HashMap<Address, Vec<u8>> {
+        let program = [
+            add_imm(32, 1),
+            add_imm(0x42, 2),
+            uma(UMAOpcode::HeapWrite, (1, 2, 0, 0)),
+            add_imm(0x43, 2),
+            uma(UMAOpcode::HeapWrite, (1, 2, 0, 0)),
+            add_imm(5, 1),
+            add_imm(0x77, 2),
+            instruction(
+                Opcode::Log(LogOpcode::StorageWrite),
+                Operand::RegOnly,
+                Operand::RegOnly,
+                (1, 2, 0),
+                0,
+            ),
+            ret_ok(),
+        ];
+
+        HashMap::from([(Address::from_low_u64_be(CONTRACT), assemble(&program))])
+    }
+
+    fn summary(hit: &WatchpointHit) -> (Watchpoint, u16, u64, u64) {
+        (
+            hit.watchpoint,
+            hit.location.pc,
+            hit.old_value.as_u64(),
+            hit.new_value.as_u64(),
+        )
+    }
+
+    #[test]
+    fn writes_are_recorded() {
+        let mut session = session(
+            contracts(),
+            Address::from_low_u64_be(CONTRACT),
+            VmRunOptions {
+                watchpoints: HashMap::from([
+                    (heap_word(), WatchpointAction::Record),
+                    (storage_slot(), WatchpointAction::Record),
+                ]),
+                ..Default::default()
+            },
+        );
+        assert!(session.run(1_000).unwrap());
+        let snapshot = session.finish().unwrap();
+
+        let hits: Vec<_> = snapshot.watchpoint_hits.iter().map(summary).collect();
+        assert_eq!(
+            hits,
+            [
+                (heap_word(), 2, 0, 0x42),
+                (heap_word(), 4, 0x42, 0x43),
+                (storage_slot(), 7, 0, 0x77),
+            ]
+        );
+        assert!(snapshot.watchpoint_hits[0].cycle < snapshot.watchpoint_hits[1].cycle);
+    }
+
+    #[test]
+    fn run_stops_after_the_write() {
+        let mut session = session(
+            contracts(),
+            Address::from_low_u64_be(CONTRACT),
+            VmRunOptions {
+                watchpoints: HashMap::from([
+                    (heap_word(), WatchpointAction::Stop),
+                    (storage_slot(), WatchpointAction::Record),
+                ]),
+                ..Default::default()
+            },
+        );
+
+        assert!(!session.run(1_000).unwrap());
+        assert_eq!(session.cycles_used(), 3);
+        let hit = session.watchpoint_stop().unwrap();
+        assert_eq!(summary(hit), (heap_word(), 2, 0, 0x42));
+        assert_eq!(
+            hit.to_string(),
+            format!(
+                "cycle {}: {:?} pc 0x0002 writes memory page {} word 1: 0x0 -> 0x42",
+                hit.cycle,
+                Address::from_low_u64_be(CONTRACT),
+                heap_page_from_base(MemoryPage(INITIAL_BASE_PAGE)).0
+            )
+        );
+
+        // the run continues up to the next write
+        assert!(!session.run(1_000).unwrap());
+        assert_eq!(summary(session.watchpoint_stop().unwrap()).1, 4);
+        assert!(session.run(1_000).unwrap());
+        assert!(session.watchpoint_stop().is_none());
+    }
+}